@@ -0,0 +1,157 @@
+use crate::models::OutputJson;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+// Renders a collection's output as an Atom feed with one entry per extracted
+// section, in rank order. An entry's summary is the refined text of the first
+// subsection on the same page, and its link points at that page of the PDF in
+// `pdf_dir`, a path relative to the collection directory.
+pub fn render(output: &OutputJson, collection: &str, pdf_dir: &str) -> String {
+    let metadata = &output.metadata;
+    // Atom requires RFC 3339, which a custom `timestamp_format` may not produce.
+    let updated = DateTime::parse_from_rfc3339(&metadata.processing_timestamp)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_else(|_| Utc::now().to_rfc3339());
+    let mut feed = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str(&format!("  <id>urn:sha256:{}</id>\n", digest(&[collection, &metadata.persona, &metadata.job_to_be_done])));
+    feed.push_str(&format!("  <title>{}</title>\n", escape(&format!("{}: {}", metadata.persona, metadata.job_to_be_done))));
+    feed.push_str(&format!("  <updated>{}</updated>\n", updated));
+    feed.push_str("  <author><name>pdf_analyzer</name></author>\n");
+    feed.push_str("  <generator>pdf_analyzer</generator>\n");
+
+    let dir_prefix: String = pdf_dir.split(['/', '\\'])
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .map(|segment| format!("{}/", encode_path_segment(segment)))
+        .collect();
+    let mut sections: Vec<_> = output.extracted_sections.iter().collect();
+    sections.sort_by_key(|section| section.importance_rank);
+    for section in sections {
+        let page = section.page_number.to_string();
+        feed.push_str("  <entry>\n");
+        feed.push_str(&format!("    <id>urn:sha256:{}</id>\n", digest(&[collection, &section.document, &page, &section.section_title])));
+        feed.push_str(&format!("    <title>{}</title>\n", escape(&section.section_title)));
+        feed.push_str(&format!(
+            "    <link rel=\"alternate\" href=\"{}{}#page={}\"/>\n",
+            escape(&dir_prefix), escape(&encode_path_segment(&section.document)), page
+        ));
+        feed.push_str(&format!("    <updated>{}</updated>\n", updated));
+        feed.push_str(&format!("    <category term=\"{}\"/>\n", escape(&section.document)));
+        let summary = output.subsection_analysis.iter()
+            .find(|sub| sub.document == section.document && sub.page_number == section.page_number);
+        if let Some(sub) = summary {
+            feed.push_str(&format!("    <summary>{}</summary>\n", escape(&sub.refined_text)));
+        }
+        feed.push_str("  </entry>\n");
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+fn digest(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+// Escapes markup and drops characters XML 1.0 doesn't allow, such as the form
+// feeds and other control characters extraction can leave behind.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if (c as u32) < 0x20 || c == '\u{FFFE}' || c == '\u{FFFF}' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that every tag is closed in order, every `&` starts an entity and
+    // no control characters slipped through, returning the element names in
+    // document order.
+    fn well_formed_elements(xml: &str) -> Vec<String> {
+        let body = xml.strip_prefix("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n").expect("missing XML declaration");
+        assert!(!body.chars().any(|c| (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r')), "control character in {body:?}");
+        static ENTITY: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+        let entity = ENTITY.get_or_init(|| regex::Regex::new(r"^&(amp|lt|gt|quot|apos);").unwrap());
+        for (i, _) in body.match_indices('&') {
+            assert!(entity.is_match(&body[i..]), "bare ampersand at {i} in {body:?}");
+        }
+        let mut open: Vec<String> = Vec::new();
+        let mut elements = Vec::new();
+        let mut rest = body;
+        while let Some(start) = rest.find('<') {
+            assert!(!rest[..start].contains('>'), "stray `>` in {rest:?}");
+            let end = start + rest[start..].find('>').expect("unterminated tag");
+            let tag = &rest[start + 1..end];
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop().as_deref(), Some(name), "mismatched closing tag");
+            } else {
+                let name = tag.split([' ', '/']).next().unwrap().to_string();
+                elements.push(name.clone());
+                if !tag.ends_with('/') {
+                    open.push(name);
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        assert!(open.is_empty(), "unclosed elements {open:?}");
+        assert!(rest.trim().is_empty());
+        elements
+    }
+
+    #[test]
+    fn feed_is_well_formed_with_one_entry_per_section() {
+        let output: OutputJson = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "input_documents": ["Tips & Tricks.pdf", "cities.pdf"],
+                "persona": "Travel <planner>",
+                "job_to_be_done": "Plan a \"trip\"",
+                "processing_timestamp": "2024-01-01T00:00:00Z",
+            },
+            "extracted_sections": [
+                { "document": "cities.pdf", "section_title": "Nice\u{c} & Cannes", "importance_rank": 2, "page_number": 4 },
+                { "document": "Tips & Tricks.pdf", "section_title": "Packing <light>", "importance_rank": 1, "page_number": 1 },
+                { "document": "cities.pdf", "section_title": "Marseille", "importance_rank": 3, "page_number": 7 },
+            ],
+            "subsection_analysis": [
+                { "document": "cities.pdf", "refined_text": "Beaches & old towns.", "page_number": 4 },
+            ],
+        })).unwrap();
+        let feed = render(&output, "collection_1", "./PDFs");
+
+        let elements = well_formed_elements(&feed);
+        assert_eq!(elements.first().map(String::as_str), Some("feed"));
+        assert_eq!(elements.iter().filter(|name| *name == "entry").count(), output.extracted_sections.len());
+        assert_eq!(elements.iter().filter(|name| *name == "summary").count(), 1);
+        let titles: Vec<&str> = feed.split("<entry>").skip(1)
+            .map(|entry| entry.split("<title>").nth(1).unwrap().split("</title>").next().unwrap())
+            .collect();
+        assert_eq!(titles, ["Packing &lt;light&gt;", "Nice &amp; Cannes", "Marseille"]);
+        assert!(feed.contains("href=\"PDFs/Tips%20%26%20Tricks.pdf#page=1\""));
+    }
+}
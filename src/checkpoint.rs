@@ -0,0 +1,68 @@
+use crate::utils::write_atomic;
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+pub const CHECKPOINT_FILENAME: &str = ".progress";
+
+// Records which collections finished, one name per line, so an interrupted
+// batch can be resumed without redoing completed work.
+pub struct Checkpoint {
+    path: PathBuf,
+    completed: BTreeSet<String>,
+}
+
+impl Checkpoint {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, completed: BTreeSet::new() }
+    }
+
+    pub fn load(path: PathBuf) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::new(path));
+        }
+        let raw = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read checkpoint at {}", path.display()))?;
+        let completed = raw.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect();
+        Ok(Self { path, completed })
+    }
+
+    pub fn is_completed(&self, name: &str) -> bool {
+        self.completed.contains(name)
+    }
+
+    pub fn mark_completed(&mut self, name: &str) -> Result<()> {
+        self.completed.insert(name.to_string());
+        self.save()
+    }
+
+    pub fn completed(&self) -> impl Iterator<Item = &str> {
+        self.completed.iter().map(String::as_str)
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut contents = self.completed.iter().cloned().collect::<Vec<_>>().join("\n");
+        contents.push('\n');
+        write_atomic(&self.path, contents)
+            .with_context(|| format!("Failed to update checkpoint at {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completed_collections_survive_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CHECKPOINT_FILENAME);
+        let mut checkpoint = Checkpoint::load(path.clone()).unwrap();
+        assert!(!checkpoint.is_completed("travel"));
+        checkpoint.mark_completed("travel").unwrap();
+        checkpoint.mark_completed("adobe/a").unwrap();
+
+        let reloaded = Checkpoint::load(path).unwrap();
+        assert!(reloaded.is_completed("travel") && reloaded.is_completed("adobe/a"));
+        assert_eq!(reloaded.completed().collect::<Vec<_>>(), ["adobe/a", "travel"]);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::config::OutputFormat;
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(name = "pdf_analyzer", about = "Persona-driven document intelligence over PDF collections")]
+pub struct Cli {
+    /// Processing options file (defaults to ./pdf_analyzer.json when present)
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Directory holding the collections (defaults to ./collections)
+    #[arg(long, value_name = "PATH")]
+    pub collections_dir: Option<PathBuf>,
+
+    /// Only process the collection with this folder name
+    #[arg(long, value_name = "NAME")]
+    pub collection: Option<String>,
+
+    /// Read every collection's PDFs from this directory instead of each collection's `pdfs/`
+    #[arg(long, value_name = "PATH")]
+    pub shared_pdf_dir: Option<PathBuf>,
+
+    /// Name of the subdirectory of each collection holding its PDFs (defaults to `pdfs`)
+    #[arg(long, value_name = "NAME")]
+    pub pdf_subdir: Option<String>,
+
+    /// Name of each collection's input file; a `*` matches several inputs (defaults to `challenge1b_input.json`)
+    #[arg(long, value_name = "NAME")]
+    pub input_filename: Option<String>,
+
+    /// Name of each collection's output file; a `*` takes the input's matched part (defaults to `challenge1b_output.json`)
+    #[arg(long, value_name = "NAME")]
+    pub output_filename: Option<String>,
+
+    /// Skip collections already recorded as completed in the checkpoint file
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Check that the external tools used by the configured backends are installed, then exit
+    #[arg(long)]
+    pub check_deps: bool,
+
+    /// Write a JSON summary of the run (collections processed, skipped and failed, counts, errors, timing) to this path
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Skip native extraction and run every document through OCR
+    #[arg(long)]
+    pub force_ocr: bool,
+
+    /// Drop text drawn outside each page's CropBox
+    #[arg(long)]
+    pub respect_cropbox: bool,
+
+    /// Include the effective keyword lists and match explanations in the output
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Write each collection's term-document matrix next to its output, for offline scoring analysis
+    #[arg(long)]
+    pub export_matrix: bool,
+
+    /// Only extract and detect structure, writing a per-document report of headings, paragraphs and lists instead of ranked output
+    #[arg(long)]
+    pub structure_report: bool,
+
+    /// Output format for each collection's results (defaults to JSON)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub format: Option<OutputFormat>,
+
+    /// Also write a Markdown report of each collection's results next to its output
+    #[arg(long)]
+    pub markdown_report: bool,
+
+    /// Also score adjacent persona and task keywords as phrases, such as "buffet menu"
+    #[arg(long)]
+    pub phrase_keywords: bool,
+
+    /// strftime-style format for the processing timestamp (defaults to RFC 3339)
+    #[arg(long, value_name = "FORMAT")]
+    pub timestamp_format: Option<String>,
+
+    /// Timezone for the processing timestamp: an IANA name or an offset like `+02:00` (defaults to UTC)
+    #[arg(long, value_name = "ZONE")]
+    pub timezone: Option<String>,
+
+    /// Program the Poppler backend runs instead of `pdftotext` from PATH
+    #[arg(long, value_name = "PATH")]
+    pub ocr_command: Option<String>,
+
+    /// Argument for the OCR command, replacing the default `-layout`; repeat for several
+    #[arg(long = "ocr-arg", value_name = "ARG", allow_hyphen_values = true)]
+    pub ocr_args: Vec<String>,
+
+    /// Only extract these 1-based pages from every document, e.g. `--pages 1,3,5`
+    #[arg(long, value_delimiter = ',', value_name = "PAGES")]
+    pub pages: Option<Vec<u32>>,
+
+    /// Also write results into a SQLite database (requires the `sqlite` feature)
+    #[arg(long, value_name = "PATH")]
+    pub sqlite: Option<PathBuf>,
+
+    /// Write outputs to `s3://bucket/prefix` instead of the collection directories (requires the `s3` feature)
+    #[arg(long, value_name = "URI")]
+    pub s3_output: Option<String>,
+
+    /// Fetch collections and their PDFs from a remote JSON manifest instead of `collections/` (requires the `http` feature)
+    #[arg(long, value_name = "URL")]
+    pub manifest_url: Option<String>,
+}
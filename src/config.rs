@@ -1,28 +1,853 @@
-use std::path::PathBuf;
-use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use log::warn;
+use serde::Deserialize;
+use crate::cli::Cli;
+use crate::language::Language;
+
+pub const OPTIONS_FILENAME: &str = "pdf_analyzer.json";
+// Where collections fetched from a remote manifest are cached, inside `collections/`.
+pub const REMOTE_CACHE_DIRNAME: &str = ".remote";
 
 pub struct Config {
     pub collections_dir: PathBuf,
+    pub options: ProcessingOptions,
+    // Remote manifest to fetch collections from instead of `collections_dir`.
+    pub manifest_url: Option<String>,
+    // Only this collection is processed, when set.
+    pub collection: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoringMode {
+    /// Count persona and task keyword hits.
+    #[default]
+    Keyword,
+    /// Score by how many of the combined persona+task word bigrams and trigrams
+    /// a paragraph repeats.
+    Phrase,
+    /// Weight each keyword found by how rare it is across the collection's
+    /// pages, ln(N/df), so terms on every page stop dominating.
+    TfIdf,
+}
+
+/// How a keyword found in both the persona and the task is counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeywordOverlap {
+    /// Once per list it appears in, so shared terms count twice.
+    #[default]
+    Additive,
+    /// Once, regardless of how many lists contain it.
+    DedupAcross,
+    /// Distinct terms count once; a term in both lists counts the given weight.
+    Weighted(f64),
+}
+
+/// How many distinct keywords of a list a paragraph must contain to be relevant.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeywordRequirement {
+    #[default]
+    Any,
+    All,
+    /// Capped at the number of distinct keywords, and at least one.
+    AtLeast(usize),
+}
+
+/// How text is normalized before keywords are compared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseFolding {
+    #[default]
+    Lowercase,
+    /// Full Unicode case folding, so e.g. "STRASSE" matches "straße".
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleCase {
+    #[default]
+    Preserve,
+    Lower,
+    /// Capitalize the first letter of each word and lowercase the rest.
+    Title,
+}
+
+/// Clean-ups applied to detected headings before they become `section_title`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TitleNormalization {
+    /// Remove leading numbering such as "3.", "2.1" or "IV.".
+    pub strip_numbering: bool,
+    /// Remove trailing colons, periods, commas, semicolons and dashes.
+    pub strip_trailing_punctuation: bool,
+    pub collapse_whitespace: bool,
+    pub case: TitleCase,
+    /// Report the detected heading as `original_title` when it was changed.
+    pub keep_original: bool,
+}
+
+/// How form feeds (`\x0c`) in native text extraction are treated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormFeed {
+    /// End the current paragraph, so relevance matching never spans the break.
+    #[default]
+    ParagraphBreak,
+    /// Collapse into a space like any other whitespace.
+    Space,
+}
+
+/// The order `extracted_sections` are written in. Either way each keeps its
+/// `importance_rank`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionOrdering {
+    /// Most important first.
+    #[default]
+    ByRank,
+    /// In reading order: by document as listed in the input, then page, then
+    /// where the heading sits on the page.
+    ByPosition,
+}
+
+/// A secondary sort key for sections with equal scores. Every key sorts ascending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreakKey {
+    Document,
+    PageNumber,
+    Title,
+    HeadingLevel,
+}
+
+/// What happens to tokens longer than `max_token_length`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LongTokenAction {
+    /// Break at lowercase-to-uppercase and punctuation-to-letter transitions,
+    /// then into `max_token_length` chunks.
+    #[default]
+    Split,
+    Drop,
+}
+
+/// How subsection scores combine into a document's score in `document_rankings`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreAggregation {
+    #[default]
+    Sum,
+    Max,
+    Mean,
+}
+
+/// What `tf_idf` scoring weights keywords by in collections with fewer than
+/// `min_idf_documents` documents, where ln(N/df) is degenerate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdfFallback {
+    /// Every keyword weighs 1, leaving plain term frequency.
+    #[default]
+    TermFrequency,
+    /// ln(1 + N/df), which stays positive for terms on every page.
+    Smoothed,
+}
+
+/// File format of each collection's results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// `challenge1b_output.json`.
+    #[default]
+    Json,
+    /// An Atom feed, `challenge1b_output.atom`, with an entry per extracted section.
+    Atom,
+    /// A Markdown report for human review, `challenge1b_output.md`.
+    Markdown,
+    /// The ranked sections as CSV, `challenge1b_sections.csv`.
+    Csv,
+}
+
+/// File format of the term-document matrix written by `export_matrix`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatrixFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// The shape of the recency weight as a document ages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecencyDecay {
+    /// Halves every `scale_days`.
+    #[default]
+    Exponential,
+    /// Falls in a straight line, reaching `min_weight` at `scale_days`.
+    Linear,
+    /// Full weight up to `scale_days`, `min_weight` after.
+    Step,
+}
+
+/// Scales scores by document age, measured from the newest dated document in
+/// the collection, so recent documents rank higher at equal relevance.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecencyWeighting {
+    pub decay: RecencyDecay,
+    pub scale_days: f64,
+    /// The lowest weight a document gets; undated documents get this too.
+    pub min_weight: f64,
+}
+
+impl Default for RecencyWeighting {
+    fn default() -> Self {
+        Self { decay: RecencyDecay::default(), scale_days: 365.0, min_weight: 0.1 }
+    }
+}
+
+/// A text extraction backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// Parse the PDF's content streams directly.
+    Native,
+    /// Run poppler's `pdftotext` over the whole file.
+    Poppler,
+}
+
+// Unknown fields are rejected so that a misspelled option fails the run
+// instead of silently keeping its default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessingOptions {
+    /// Score multipliers by heading level, starting at H1. Deeper levels reuse the
+    /// last weight; an empty list ranks every level equally.
+    pub heading_level_weights: Vec<f64>,
+    /// When a page has no regex-matched headings, title each text block by its
+    /// first line if that line uses a larger or bold font.
+    pub infer_titles_from_fonts: bool,
+    /// Optional output fields to serialize, in order, after the core fields.
+    /// `None` serializes everything.
+    pub output_fields: Option<Vec<String>>,
+    /// Attach the page-space bounding box of each subsection's text to the output.
+    pub record_bboxes: bool,
+    pub scoring_mode: ScoringMode,
+    pub keyword_overlap: KeywordOverlap,
+    /// How many persona keywords a paragraph needs to be relevant.
+    pub persona_match: KeywordRequirement,
+    /// How many task keywords a paragraph needs to be relevant.
+    pub task_match: KeywordRequirement,
+    /// Also write results into this SQLite database (requires the `sqlite` feature).
+    pub sqlite_path: Option<PathBuf>,
+    /// Directory every collection's documents are read from, instead of each
+    /// collection's own `pdf_subdir`.
+    pub shared_pdf_dir: Option<PathBuf>,
+    /// Subdirectory of each collection holding its documents. When it doesn't
+    /// exist, documents are read from beside the input JSON.
+    pub pdf_subdir: String,
+    /// Name of each collection's input file. A single `*` matches any part of
+    /// the name, so one collection directory can hold several inputs.
+    pub input_filename: String,
+    /// Name of each collection's output file. When `input_filename` has a `*`,
+    /// this needs one too, and it is replaced with the part the input's matched.
+    pub output_filename: String,
+    /// Document language used to pick heading patterns; detected per document when unset.
+    pub language: Option<Language>,
+    /// Send every document straight to the OCR backend.
+    pub force_ocr: bool,
+    /// Emit `document_links` between documents covering overlapping topics.
+    pub document_links: bool,
+    /// Minimum cosine similarity for a document pair to be linked.
+    pub document_link_threshold: f64,
+    /// 1-based PDF page indices to extract from every document; all pages when unset.
+    pub pages: Option<Vec<u32>>,
+    /// Token window within which two different keywords earn `proximity_bonus`
+    /// in keyword scoring; no bonus when unset.
+    pub proximity_window: Option<usize>,
+    pub proximity_bonus: f64,
+    /// Also match runs of two or three adjacent persona or task keywords as
+    /// phrases, each present phrase adding `phrase_weight` in keyword scoring.
+    pub phrase_keywords: bool,
+    pub phrase_weight: f64,
+    /// Add per-document `document_coverage` to the output metadata.
+    pub report_coverage: bool,
+    /// Add page, word and character counts and the processing time to the
+    /// output metadata.
+    pub report_statistics: bool,
+    /// Add explanations of how results were matched to the output.
+    pub explain: bool,
+    /// Analyze each page as soon as it is extracted and discard its text, so
+    /// memory use doesn't grow with document length.
+    pub streaming: bool,
+    /// Strip lines repeated on at least this fraction of a document's pages,
+    /// such as running headers and page-numbered footers; off when unset.
+    pub repeated_line_threshold: Option<f64>,
+    /// Rejoin words hyphenated across a line break before analysis.
+    pub dehyphenate: bool,
+    /// Analyze pages without text as empty pages instead of leaving them out,
+    /// so they count in page totals and coverage.
+    pub keep_blank_pages: bool,
+    /// Report consecutive relevant paragraphs on a page as one subsection.
+    pub merge_adjacent: bool,
+    /// NFKC-normalize extracted text, expanding ligatures, and turn curly
+    /// quotes and dashes into ASCII.
+    pub normalize_unicode: bool,
+    /// Add each section's text, up to the next heading on its page, as `section_body`.
+    pub section_bodies: bool,
+    /// Add the heading each subsection falls under as `parent_section_title`.
+    pub parent_sections: bool,
+    /// Lines per page for `.txt` and `.md` documents without form feeds.
+    pub text_page_lines: usize,
+    /// Drop subsections scoring below this; none are dropped when unset.
+    pub min_relevance: Option<f64>,
+    /// Regexes that find headings in page text, replacing the detected
+    /// language's built-in ones; the first capture group is the title.
+    pub heading_patterns: Option<Vec<String>>,
+    /// Report a heading only on the first page of a document it appears on.
+    pub dedupe_document_headings: bool,
+    /// Password for encrypted PDFs whose input entry doesn't give their own.
+    pub pdf_password: Option<String>,
+    /// Extraction backends to try, in order, until one yields text.
+    pub backend_order: Vec<Backend>,
+    /// Attach per-keyword match counts to each extracted section.
+    pub section_term_frequencies: bool,
+    pub case_folding: CaseFolding,
+    /// Match keywords by their Snowball stem in the query's language, so
+    /// "planning" also matches "plans" and "planned".
+    pub stemming: bool,
+    /// Soft limit, in MiB, on the estimated text held by concurrent extractions;
+    /// unlimited when unset.
+    pub memory_budget_mb: Option<usize>,
+    /// Attach the bullet and numbered list items in each subsection, with their
+    /// indentation-based nesting levels.
+    pub detect_lists: bool,
+    /// Emit `report_bundle`: ranked sections joined with their best excerpt.
+    pub report_bundle: bool,
+    pub title_normalization: TitleNormalization,
+    /// Write each collection's output to this `s3://bucket/prefix` location
+    /// instead of the local filesystem (requires the `s3` feature).
+    pub s3_output: Option<String>,
+    /// Keep at most this many persona keywords and this many task keywords;
+    /// uncapped when unset.
+    pub max_keywords: Option<usize>,
+    pub form_feed: FormFeed,
+    /// Move small-font text at the bottom of each page out of the body text
+    /// into separately scored `footnotes`.
+    pub separate_footnotes: bool,
+    /// Keys applied in order to sections with equal scores; document, page
+    /// number, then title by default.
+    pub tie_break: Vec<TieBreakKey>,
+    /// Whether `extracted_sections` are listed by rank or in reading order.
+    pub section_ordering: SectionOrdering,
+    /// Keep only this many of the highest-ranked sections; all of them when unset.
+    pub top_sections: Option<usize>,
+    /// Keep only this many of the highest-scoring subsections; all of them when unset.
+    pub top_subsections: Option<usize>,
+    /// Cut each subsection's text down to this many of its sentences with the
+    /// most persona and task keywords; the full text when unset.
+    pub summarize: Option<usize>,
+    /// Cut each subsection's text down the same way until it fits in this
+    /// many characters; no limit when unset.
+    pub summarize_max_chars: Option<usize>,
+    /// Record the SHA-256 of each source PDF in the output metadata.
+    pub record_source_hashes: bool,
+    /// Drop headings deeper than this level from `extracted_sections`; all
+    /// levels are kept when unset.
+    pub max_heading_level: Option<u8>,
+    /// Record separate `persona_score` and `task_score` on each subsection.
+    pub split_scores: bool,
+    /// Drop text drawn outside each page's CropBox, such as bleed and printer marks.
+    pub respect_cropbox: bool,
+    /// Emit `keyword_index`, mapping each matched keyword to the pages it was found on.
+    pub keyword_index: bool,
+    /// Tokens longer than this many characters are treated as fused extraction
+    /// output and handled by `long_token_action`; no limit when unset.
+    pub max_token_length: Option<usize>,
+    pub long_token_action: LongTokenAction,
+    /// Added to a paragraph's score for each distinct keyword from its document's
+    /// title that it contains; no boost at `0.0`.
+    pub title_term_boost: f64,
+    /// Kill `pdftotext`, `pdftoppm` or a `tesseract` run after this many
+    /// seconds; unlimited when unset.
+    pub ocr_timeout_secs: Option<u64>,
+    /// Keep at most this many bytes of `pdftotext` output; unlimited when unset.
+    pub ocr_max_output_bytes: Option<usize>,
+    /// For tagged PDFs, take headings, paragraphs and list items from the
+    /// structure tree instead of heuristics.
+    pub use_structure_tags: bool,
+    /// Skip a document whose processing panics instead of aborting the collection.
+    pub catch_panics: bool,
+    /// List each document's title in the output metadata: the input's title,
+    /// else the PDF's /Title, else the filename stem.
+    pub document_titles: bool,
+    /// Emit `document_rankings`, ordering documents by their aggregated subsection scores.
+    pub document_rankings: bool,
+    pub document_score_aggregation: ScoreAggregation,
+    /// Keep line breaks and runs of spaces in natively extracted text instead of
+    /// collapsing them; a document's own `preserve_whitespace` also enables this.
+    pub preserve_whitespace: bool,
+    /// Add a per-page extraction confidence estimate to the output metadata.
+    pub report_page_confidence: bool,
+    /// strftime-style format for `processing_timestamp`; RFC 3339 when unset.
+    pub timestamp_format: Option<String>,
+    /// IANA timezone name or fixed offset for `processing_timestamp`; UTC when unset.
+    pub timezone: Option<String>,
+    /// How many documents of a collection are processed at once; defaults to
+    /// the number of available CPUs.
+    pub parallel_documents: Option<usize>,
+    /// How many threads extract the pages of a single document; sequential
+    /// when unset.
+    pub parallel_pages: Option<usize>,
+    /// Join a heading line with the next one when the second continues the
+    /// first, so headings that wrap onto two lines are found as one title.
+    pub merge_wrapped_headings: bool,
+    /// Weight scores by document date (the input's `date`, else the PDF's
+    /// modification or creation date). Off when unset.
+    pub recency_weighting: Option<RecencyWeighting>,
+    /// Write each collection's term-document count matrix next to its output.
+    pub export_matrix: bool,
+    pub matrix_format: MatrixFormat,
+    /// `tf_idf` scoring falls back to `idf_fallback` in collections with fewer
+    /// documents than this.
+    pub min_idf_documents: usize,
+    pub idf_fallback: IdfFallback,
+    /// Only run extraction and structure detection, writing a per-document
+    /// structure report instead of the ranked output.
+    pub structure_report: bool,
+    /// When `pdftotext` finds no text, rasterize the pages with `pdftoppm` and
+    /// OCR each one with `tesseract`.
+    pub tesseract_fallback: bool,
+    /// Tesseract language codes, e.g. "eng+deu"; tesseract's default when unset.
+    pub tesseract_language: Option<String>,
+    /// Resolution pages are rasterized at for tesseract.
+    pub ocr_dpi: u32,
+    /// Words kept even though they are on the stopword list, for domain terms
+    /// such as "will" in legal documents.
+    pub stopword_exemptions: Vec<String>,
+    /// Words dropped from the persona, task and title keywords on top of the
+    /// built-in stopwords.
+    pub extra_stopwords: Vec<String>,
+    /// Program the Poppler backend runs to extract text, e.g.
+    /// "/opt/poppler/bin/pdftotext".
+    pub ocr_command: String,
+    /// Arguments passed to `ocr_command` before the PDF path and "-" (stdout).
+    /// When one contains `{input}`, the path is substituted there instead and
+    /// nothing is appended.
+    pub ocr_args: Vec<String>,
+    pub output_format: OutputFormat,
+    /// Also write a Markdown report, `challenge1b_output.md`, next to the output.
+    pub markdown_report: bool,
+}
+
+impl Default for ProcessingOptions {
+    fn default() -> Self {
+        Self {
+            heading_level_weights: Vec::new(),
+            infer_titles_from_fonts: false,
+            output_fields: None,
+            record_bboxes: false,
+            scoring_mode: ScoringMode::default(),
+            keyword_overlap: KeywordOverlap::default(),
+            persona_match: KeywordRequirement::default(),
+            task_match: KeywordRequirement::default(),
+            sqlite_path: None,
+            shared_pdf_dir: None,
+            pdf_subdir: "pdfs".to_string(),
+            input_filename: "challenge1b_input.json".to_string(),
+            output_filename: "challenge1b_output.json".to_string(),
+            language: None,
+            force_ocr: false,
+            document_links: false,
+            document_link_threshold: 0.5,
+            pages: None,
+            proximity_window: None,
+            proximity_bonus: 1.0,
+            phrase_keywords: false,
+            phrase_weight: 1.0,
+            report_coverage: false,
+            report_statistics: false,
+            explain: false,
+            streaming: false,
+            repeated_line_threshold: None,
+            dehyphenate: true,
+            keep_blank_pages: false,
+            merge_adjacent: false,
+            normalize_unicode: true,
+            section_bodies: false,
+            parent_sections: false,
+            text_page_lines: 60,
+            min_relevance: None,
+            heading_patterns: None,
+            dedupe_document_headings: false,
+            pdf_password: None,
+            backend_order: vec![Backend::Native, Backend::Poppler],
+            section_term_frequencies: false,
+            case_folding: CaseFolding::default(),
+            stemming: false,
+            memory_budget_mb: None,
+            detect_lists: false,
+            report_bundle: false,
+            title_normalization: TitleNormalization::default(),
+            s3_output: None,
+            max_keywords: None,
+            form_feed: FormFeed::default(),
+            separate_footnotes: false,
+            tie_break: vec![TieBreakKey::Document, TieBreakKey::PageNumber, TieBreakKey::Title],
+            section_ordering: SectionOrdering::default(),
+            top_sections: None,
+            top_subsections: None,
+            summarize: None,
+            summarize_max_chars: None,
+            record_source_hashes: false,
+            max_heading_level: None,
+            split_scores: false,
+            respect_cropbox: false,
+            keyword_index: false,
+            max_token_length: None,
+            long_token_action: LongTokenAction::default(),
+            title_term_boost: 0.0,
+            ocr_timeout_secs: None,
+            ocr_max_output_bytes: None,
+            use_structure_tags: false,
+            catch_panics: false,
+            document_titles: false,
+            document_rankings: false,
+            document_score_aggregation: ScoreAggregation::default(),
+            preserve_whitespace: false,
+            report_page_confidence: false,
+            timestamp_format: None,
+            timezone: None,
+            parallel_documents: None,
+            parallel_pages: None,
+            merge_wrapped_headings: false,
+            recency_weighting: None,
+            export_matrix: false,
+            matrix_format: MatrixFormat::default(),
+            min_idf_documents: 2,
+            idf_fallback: IdfFallback::default(),
+            structure_report: false,
+            tesseract_fallback: true,
+            tesseract_language: None,
+            ocr_dpi: 300,
+            stopword_exemptions: Vec::new(),
+            extra_stopwords: Vec::new(),
+            ocr_command: "pdftotext".to_string(),
+            ocr_args: vec!["-layout".to_string()],
+            output_format: OutputFormat::default(),
+            markdown_report: false,
+        }
+    }
+}
+
+impl ProcessingOptions {
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read options at {}", path.display()))?;
+        serde_json::from_str(&raw)
+            .with_context(|| format!("Failed to parse options at {}", path.display()))
+    }
 }
 
 impl Config {
-    pub fn new() -> Result<Self> {
+    pub fn new(cli: &Cli) -> Result<Self> {
         let current_dir = std::env::current_dir()?;
-        let collections_dir = current_dir.join("collections");
-        Ok(Self { collections_dir })
+        let collections_dir = match &cli.collections_dir {
+            Some(dir) => current_dir.join(dir),
+            None => current_dir.join("collections"),
+        };
+        let mut options = match &cli.config {
+            Some(path) => ProcessingOptions::load(path)?,
+            None => {
+                let default_path = current_dir.join(OPTIONS_FILENAME);
+                if default_path.exists() {
+                    ProcessingOptions::load(&default_path)?
+                } else {
+                    ProcessingOptions::default()
+                }
+            }
+        };
+        if cli.force_ocr {
+            options.force_ocr = true;
+        }
+        if cli.explain {
+            options.explain = true;
+        }
+        if cli.respect_cropbox {
+            options.respect_cropbox = true;
+        }
+        if cli.export_matrix {
+            options.export_matrix = true;
+        }
+        if cli.structure_report {
+            options.structure_report = true;
+        }
+        if let Some(format) = cli.format {
+            options.output_format = format;
+        }
+        if cli.markdown_report {
+            options.markdown_report = true;
+        }
+        if cli.phrase_keywords {
+            options.phrase_keywords = true;
+        }
+        if let Some(format) = &cli.timestamp_format {
+            options.timestamp_format = Some(format.clone());
+        }
+        if let Some(timezone) = &cli.timezone {
+            options.timezone = Some(timezone.clone());
+        }
+        if let Some(command) = &cli.ocr_command {
+            options.ocr_command = command.clone();
+        }
+        if !cli.ocr_args.is_empty() {
+            options.ocr_args = cli.ocr_args.clone();
+        }
+        if let Some(pages) = &cli.pages {
+            options.pages = Some(pages.clone());
+        }
+        if let Some(dir) = &cli.shared_pdf_dir {
+            options.shared_pdf_dir = Some(dir.clone());
+        }
+        if let Some(subdir) = &cli.pdf_subdir {
+            options.pdf_subdir = subdir.clone();
+        }
+        if let Some(name) = &cli.input_filename {
+            options.input_filename = name.clone();
+        }
+        if let Some(name) = &cli.output_filename {
+            options.output_filename = name.clone();
+        }
+        if let Some(path) = &cli.sqlite {
+            options.sqlite_path = Some(path.clone());
+        }
+        if let Some(uri) = &cli.s3_output {
+            options.s3_output = Some(uri.clone());
+        }
+        if cfg!(not(feature = "sqlite")) && options.sqlite_path.is_some() {
+            return Err(anyhow::anyhow!("SQLite output requires building with `--features sqlite`"));
+        }
+        if let Some(uri) = &options.s3_output {
+            #[cfg(feature = "s3")]
+            crate::s3_output::S3Location::parse(uri)?;
+            #[cfg(not(feature = "s3"))]
+            return Err(anyhow::anyhow!("S3 output to {} requires building with `--features s3`", uri));
+        }
+        // Rejects a bad format or timezone before any collection is processed.
+        crate::utils::format_timestamp(chrono::Utc::now(), options.timestamp_format.as_deref(), options.timezone.as_deref())?;
+        validate_filenames(&options.input_filename, &options.output_filename)?;
+        if options.summarize == Some(0) {
+            return Err(anyhow::anyhow!("summarize must keep at least one sentence"));
+        }
+        if options.summarize_max_chars == Some(0) {
+            return Err(anyhow::anyhow!("summarize_max_chars must allow at least one character"));
+        }
+        if options.backend_order.is_empty() {
+            return Err(anyhow::anyhow!("backend_order must name at least one extraction backend"));
+        }
+        if cfg!(not(feature = "http")) && cli.manifest_url.is_some() {
+            return Err(anyhow::anyhow!("Fetching a remote manifest requires building with `--features http`"));
+        }
+        Ok(Self {
+            collections_dir,
+            options,
+            manifest_url: cli.manifest_url.clone(),
+            collection: cli.collection.clone(),
+        })
     }
 
     pub fn get_collection_paths(&self) -> Result<Vec<(String, PathBuf, PathBuf)>> {
+        let collections = self.all_collection_paths()?;
+        let Some(only) = &self.collection else {
+            return Ok(collections);
+        };
+        let selected: Vec<_> = collections.into_iter().filter(|(name, _, _)| name == only).collect();
+        if selected.is_empty() {
+            return Err(anyhow::anyhow!("No collection named {:?} in {}", only, self.collections_dir.display()));
+        }
+        Ok(selected)
+    }
+
+    fn all_collection_paths(&self) -> Result<Vec<(String, PathBuf, PathBuf)>> {
+        #[cfg(feature = "http")]
+        if let Some(url) = &self.manifest_url {
+            return crate::remote::fetch_collections(url, &self.collections_dir.join(REMOTE_CACHE_DIRNAME), &self.options);
+        }
         let mut collections = Vec::new();
-        for entry in std::fs::read_dir(&self.collections_dir)? {
+        let entries = std::fs::read_dir(&self.collections_dir)
+            .with_context(|| format!("Failed to read collections directory {}", self.collections_dir.display()))?;
+        for entry in entries {
             let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                let name = entry.file_name().to_string_lossy().to_string();
-                let input_path = entry.path().join("challenge1b_input.json");
-                let output_path = entry.path().join("challenge1b_output.json");
-                collections.push((name, input_path, output_path));
+            let name = entry.file_name().to_string_lossy().to_string();
+            // Hidden directories hold tool state such as the remote download cache.
+            if entry.file_type()?.is_dir() && !name.starts_with('.') {
+                collections.extend(self.collection_inputs(name, &entry.path())?);
             }
         } 
         Ok(collections)
     }
-}
\ No newline at end of file
+
+    // The (name, input path, output path) of each input in one collection
+    // directory. With a `*` in `input_filename` every matching file is its own
+    // collection, named `<directory>/<matched part>`.
+    fn collection_inputs(&self, name: String, dir: &Path) -> Result<Vec<(String, PathBuf, PathBuf)>> {
+        let input_filename = &self.options.input_filename;
+        let output_filename = &self.options.output_filename;
+        let Some((prefix, suffix)) = input_filename.split_once('*') else {
+            return Ok(vec![(name, dir.join(input_filename), dir.join(output_filename))]);
+        };
+        let mut matched = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read collection directory {}", dir.display()))? {
+            let entry = entry?;
+            let filename = entry.file_name().to_string_lossy().to_string();
+            if !entry.file_type()?.is_file() || filename.len() < prefix.len() + suffix.len() {
+                continue;
+            }
+            if let Some(part) = filename.strip_prefix(prefix).and_then(|rest| rest.strip_suffix(suffix)) {
+                let output = output_filename.replacen('*', part, 1);
+                matched.push((format!("{}/{}", name, part), dir.join(&filename), dir.join(output)));
+            }
+        }
+        // With a broad pattern like `*.json`, an earlier run's outputs match too;
+        // they are outputs of other inputs, not inputs themselves.
+        let outputs: HashSet<PathBuf> = matched.iter().map(|(_, _, output)| output.clone()).collect();
+        matched.retain(|(_, input, _)| !outputs.contains(input));
+        if matched.is_empty() {
+            warn!("Collection {} has no input matching {:?}", name, input_filename);
+        }
+        matched.sort();
+        Ok(matched)
+    }
+}
+
+// Input and output names are file names within a collection directory, and a
+// wildcard input needs a wildcard output so matched inputs don't share one.
+fn validate_filenames(input_filename: &str, output_filename: &str) -> Result<()> {
+    for (option, filename) in [("input_filename", input_filename), ("output_filename", output_filename)] {
+        if filename.is_empty() || filename.contains(['/', '\\']) || filename == "." || filename == ".." {
+            return Err(anyhow::anyhow!("{} must be a file name without path separators, got {:?}", option, filename));
+        }
+        if filename.matches('*').count() > 1 {
+            return Err(anyhow::anyhow!("{} may contain at most one `*`, got {:?}", option, filename));
+        }
+    }
+    if input_filename.contains('*') != output_filename.contains('*') {
+        return Err(anyhow::anyhow!(
+            "input_filename and output_filename must both contain a `*` or neither, got {:?} and {:?}",
+            input_filename, output_filename
+        ));
+    }
+    if input_filename == output_filename {
+        return Err(anyhow::anyhow!("input_filename and output_filename must differ, both are {:?}", input_filename));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(collections_dir: &Path, input_filename: &str, output_filename: &str) -> Config {
+        let options = ProcessingOptions {
+            input_filename: input_filename.to_string(),
+            output_filename: output_filename.to_string(),
+            ..Default::default()
+        };
+        Config { collections_dir: collections_dir.to_path_buf(), options, manifest_url: None, collection: None }
+    }
+
+    #[test]
+    fn the_collections_directory_and_a_single_collection_come_from_the_command_line() {
+        use clap::Parser;
+        let root = tempfile::tempdir().unwrap();
+        for name in ["alpha", "beta"] {
+            std::fs::create_dir_all(root.path().join(name)).unwrap();
+        }
+        let dir = root.path().to_str().unwrap();
+        let parse = |args: &[&str]| Config::new(&Cli::parse_from([&["pdf_analyzer"], args].concat())).unwrap();
+        let names = |config: &Config| config.get_collection_paths().unwrap().into_iter().map(|(name, _, _)| name).collect::<Vec<_>>();
+
+        let mut all = names(&parse(&["--collections-dir", dir]));
+        all.sort();
+        assert_eq!(all, ["alpha", "beta"]);
+        let only = parse(&["--collections-dir", dir, "--collection", "beta"]).get_collection_paths().unwrap();
+        let beta = root.path().join("beta");
+        assert_eq!(only, [("beta".to_string(), beta.join("challenge1b_input.json"), beta.join("challenge1b_output.json"))]);
+        let error = parse(&["--collections-dir", dir, "--collection", "gamma"]).get_collection_paths().unwrap_err();
+        assert_eq!(error.to_string(), format!("No collection named \"gamma\" in {}", dir));
+        assert_eq!(parse(&[]).collections_dir, std::env::current_dir().unwrap().join("collections"));
+    }
+
+    #[test]
+    fn misspelled_options_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let load = |raw: &str| {
+            let path = dir.path().join("pdf_analyzer.json");
+            std::fs::write(&path, raw).unwrap();
+            ProcessingOptions::load(&path)
+        };
+
+        assert_eq!(load(r#"{ "min_relevance": 2.0 }"#).unwrap().min_relevance, Some(2.0));
+        let error = load(r#"{ "min_relevence": 2.0 }"#).unwrap_err();
+        assert!(format!("{:#}", error).contains("unknown field `min_relevence`"), "{:#}", error);
+        let error = load(r#"{ "recency_weighting": { "scale_day": 30 } }"#).unwrap_err();
+        assert!(format!("{:#}", error).contains("unknown field `scale_day`"), "{:#}", error);
+    }
+
+    #[test]
+    fn configured_filenames_locate_each_collections_input_and_output() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("trip");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("guide.txt"), "A beach trip for the travel planner.").unwrap();
+        let input = serde_json::json!({
+            "challenge_info": { "challenge_id": "test", "test_case_name": "trip" },
+            "documents": [{ "filename": "guide.txt" }],
+            "persona": { "role": "Travel planner" },
+            "job_to_be_done": { "task": "Plan a beach trip" },
+        });
+        std::fs::write(dir.join("my_input.json"), input.to_string()).unwrap();
+        let config = config(root.path(), "my_input.json", "my_output.json");
+
+        let collections = config.get_collection_paths().unwrap();
+        assert_eq!(collections, [("trip".to_string(), dir.join("my_input.json"), dir.join("my_output.json"))]);
+        let (_, input_path, output_path) = &collections[0];
+        crate::pdf_processor::PdfProcessor::new(config.options.clone()).unwrap()
+            .process_pdf_collection(input_path.to_str().unwrap(), output_path.to_str().unwrap())
+            .unwrap();
+        assert!(dir.join("my_output.json").is_file());
+        assert!(!dir.join("challenge1b_output.json").exists());
+    }
+
+    #[test]
+    fn a_wildcard_input_yields_one_collection_per_match() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("trips");
+        std::fs::create_dir_all(&dir).unwrap();
+        for filename in ["nice_input.json", "rome_input.json", "nice_output.json", "notes.txt"] {
+            std::fs::write(dir.join(filename), "{}").unwrap();
+        }
+
+        let collections = config(root.path(), "*_input.json", "*_output.json").get_collection_paths().unwrap();
+        assert_eq!(collections, [
+            ("trips/nice".to_string(), dir.join("nice_input.json"), dir.join("nice_output.json")),
+            ("trips/rome".to_string(), dir.join("rome_input.json"), dir.join("rome_output.json")),
+        ]);
+        // An earlier run's outputs aren't mistaken for inputs.
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        for filename in ["nice.json", "nice.out.json"] {
+            std::fs::write(dir.join(filename), "{}").unwrap();
+        }
+        let collections = config(root.path(), "*.json", "*.out.json").get_collection_paths().unwrap();
+        assert_eq!(collections, [("trips/nice".to_string(), dir.join("nice.json"), dir.join("nice.out.json"))]);
+        assert!(validate_filenames("*_input.json", "output.json").is_err());
+        assert!(validate_filenames("my_input.json", "my_input.json").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::models::OutputJson;
+use anyhow::Result;
+
+pub const SECTIONS_CSV_FILENAME: &str = "challenge1b_sections.csv";
+
+// Renders the extracted sections as CSV, one row per section in rank order,
+// for loading into a spreadsheet.
+pub fn render_sections(output: &OutputJson) -> Result<String> {
+    let mut csv = csv::Writer::from_writer(Vec::new());
+    csv.write_record(["document", "section_title", "importance_rank", "page_number"])?;
+    let mut sections: Vec<_> = output.extracted_sections.iter().collect();
+    sections.sort_by_key(|section| section.importance_rank);
+    for section in sections {
+        csv.write_record([
+            section.document.as_str(),
+            section.section_title.as_str(),
+            &section.importance_rank.to_string(),
+            &section.page_number.to_string(),
+        ])?;
+    }
+    Ok(String::from_utf8(csv.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_with_commas_and_quotes_round_trip() {
+        let output: OutputJson = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "input_documents": ["guide, vol. 2.pdf"],
+                "persona": "Travel planner",
+                "job_to_be_done": "Plan a trip",
+                "processing_timestamp": "2024-01-01T00:00:00Z",
+            },
+            "extracted_sections": [
+                { "document": "guide, vol. 2.pdf", "section_title": "Nice, \"the\" Riviera", "importance_rank": 2, "page_number": 4 },
+                { "document": "guide, vol. 2.pdf", "section_title": "Overview", "importance_rank": 1, "page_number": 1 },
+            ],
+            "subsection_analysis": [],
+        })).unwrap();
+        let csv = render_sections(&output).unwrap();
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(reader.headers().unwrap(), vec!["document", "section_title", "importance_rank", "page_number"]);
+        let rows: Vec<Vec<String>> = reader.records()
+            .map(|record| record.unwrap().iter().map(String::from).collect())
+            .collect();
+        assert_eq!(rows, [
+            ["guide, vol. 2.pdf", "Overview", "1", "1"],
+            ["guide, vol. 2.pdf", "Nice, \"the\" Riviera", "2", "4"],
+        ]);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::config::{Backend, ProcessingOptions};
+use std::process::Command;
+
+// An external program one of the configured backends shells out to.
+pub struct Tool {
+    pub backend: Backend,
+    pub command: String,
+    pub version_args: &'static [&'static str],
+}
+
+pub enum ToolStatus {
+    // First line of the tool's version output.
+    Found(String),
+    Missing(String),
+}
+
+pub fn required_tools(options: &ProcessingOptions) -> Vec<Tool> {
+    let mut tools = Vec::new();
+    for &backend in &options.backend_order {
+        let needed = match backend {
+            Backend::Native => continue,
+            // The poppler tools only understand `-v`, which prints to stderr.
+            Backend::Poppler if options.tesseract_fallback => vec![
+                Tool { backend, command: options.ocr_command.clone(), version_args: &["-v"] },
+                Tool { backend, command: "pdftoppm".to_string(), version_args: &["-v"] },
+                Tool { backend, command: "tesseract".to_string(), version_args: &["--version"] },
+            ],
+            Backend::Poppler => vec![Tool { backend, command: options.ocr_command.clone(), version_args: &["-v"] }],
+        };
+        for tool in needed {
+            if !tools.iter().any(|t: &Tool| t.command == tool.command) {
+                tools.push(tool);
+            }
+        }
+    }
+    tools
+}
+
+pub fn check(command: &str, version_args: &[&str]) -> ToolStatus {
+    match Command::new(command).args(version_args).output() {
+        Ok(output) if output.status.success() => {
+            let text = if output.stdout.is_empty() { output.stderr } else { output.stdout };
+            let version = String::from_utf8_lossy(&text).lines().next().unwrap_or_default().trim().to_string();
+            ToolStatus::Found(version)
+        }
+        Ok(output) => ToolStatus::Missing(format!("`{} {}` exited with {}", command, version_args.join(" "), output.status)),
+        Err(e) => ToolStatus::Missing(format!("not found ({})", e)),
+    }
+}
+
+// Prints the status of every tool the configured backends need and returns
+// whether all of them are available.
+pub fn report(options: &ProcessingOptions) -> bool {
+    let tools = required_tools(options);
+    if tools.is_empty() {
+        println!("No external tools are needed by backend_order {:?}", options.backend_order);
+        return true;
+    }
+    let mut all_found = true;
+    for tool in tools {
+        match check(&tool.command, tool.version_args) {
+            ToolStatus::Found(version) => println!("[ok] {} ({:?} backend): {}", tool.command, tool.backend, version),
+            ToolStatus::Missing(reason) => {
+                all_found = false;
+                println!("[missing] {} ({:?} backend): {}", tool.command, tool.backend, reason);
+            }
+        }
+    }
+    all_found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mock_command(dir: &std::path::Path, name: &str, script: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn check_reports_present_and_missing_tools() {
+        let dir = tempfile::tempdir().unwrap();
+        let present = mock_command(dir.path(), "pdftotext", "echo 'pdftotext version 24.02.0' >&2");
+        let broken = mock_command(dir.path(), "broken", "exit 3");
+
+        assert!(matches!(check(&present, &["-v"]), ToolStatus::Found(version) if version == "pdftotext version 24.02.0"));
+        assert!(matches!(check(&broken, &["-v"]), ToolStatus::Missing(reason) if reason.contains("exited with")));
+        let missing = dir.path().join("absent").to_string_lossy().into_owned();
+        assert!(matches!(check(&missing, &["-v"]), ToolStatus::Missing(reason) if reason.starts_with("not found")));
+    }
+
+    #[test]
+    fn report_checks_the_tools_of_the_configured_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let present = mock_command(dir.path(), "pdftotext", "echo 'pdftotext version 24.02.0' >&2");
+        let missing = dir.path().join("absent").to_string_lossy().into_owned();
+
+        let native = ProcessingOptions { backend_order: vec![Backend::Native], ocr_command: missing.clone(), ..Default::default() };
+        assert!(required_tools(&native).is_empty());
+        assert!(report(&native));
+        let poppler = |ocr_command: &str, tesseract_fallback| ProcessingOptions {
+            ocr_command: ocr_command.to_string(),
+            tesseract_fallback,
+            ..Default::default()
+        };
+        assert!(report(&poppler(&present, false)));
+        assert!(!report(&poppler(&missing, false)));
+        let commands: Vec<String> = required_tools(&poppler(&present, true)).into_iter().map(|tool| tool.command).collect();
+        assert_eq!(commands, [present.as_str(), "pdftoppm", "tesseract"]);
+    }
+}
@@ -0,0 +1,170 @@
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    English,
+    German,
+    French,
+}
+
+const ENGLISH_MARKERS: &[&str] = &["the", "and", "of", "to", "is", "for", "with", "that", "this", "are"];
+const GERMAN_MARKERS: &[&str] = &["der", "die", "und", "das", "ist", "nicht", "mit", "von", "für", "ein"];
+const FRENCH_MARKERS: &[&str] = &["le", "la", "les", "et", "des", "est", "une", "pour", "dans", "du"];
+
+const ENGLISH_STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "in", "into", "is", "it",
+    "its", "of", "on", "or", "that", "the", "their", "this", "to", "was", "were", "will", "with", "you", "your",
+];
+const GERMAN_STOPWORDS: &[&str] = &[
+    "aber", "als", "am", "an", "auch", "auf", "aus", "bei", "das", "dass", "dem", "den", "der", "des", "die", "ein",
+    "eine", "einen", "einer", "es", "für", "im", "in", "ist", "mit", "nach", "nicht", "oder", "sich", "sie", "sind",
+    "und", "von", "wird", "zu", "zum", "zur",
+];
+const FRENCH_STOPWORDS: &[&str] = &[
+    "à", "au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "elle", "en", "est", "et", "il", "la", "le",
+    "les", "leur", "mais", "ne", "nous", "ou", "par", "pas", "pour", "qu", "que", "qui", "sa", "se", "son", "sont",
+    "sur", "un", "une", "vous",
+];
+
+const ENGLISH_HEADINGS: &[&str] = &[
+    r"(?m)^([A-Z][A-Za-z \t]{3,}):?$",
+    r"(?m)^(\d+\.?[ \t]+[A-Z][A-Za-z \t]+):?$",
+    r"(?m)^(Chapter[ \t]+\d+[^.\n]*):?$",
+    r"(?m)^([A-Z \t]{4,}):?$",
+];
+
+// Nouns are capitalized mid-heading and umlauts/ß are common.
+const GERMAN_HEADINGS: &[&str] = &[
+    r"(?m)^(\p{Lu}[\p{L} \t\-]{3,}):?$",
+    r"(?m)^(\d+(?:\.\d+)*\.?[ \t]+\p{Lu}[\p{L} \t\-]+):?$",
+    r"(?m)^(Kapitel[ \t]+\d+[^.\n]*):?$",
+    r"(?m)^([\p{Lu} \t]{4,}):?$",
+];
+
+// Roman-numeral numbering, elided articles ("L'histoire") and accented capitals.
+const FRENCH_HEADINGS: &[&str] = &[
+    r"(?m)^(\p{Lu}[\p{L}'’ \t\-]{3,}):?$",
+    r"(?m)^((?:\d+|[IVXLC]+)[.)\-]?[ \t]+\p{Lu}[\p{L}'’ \t\-]+):?$",
+    r"(?m)^(Chapitre[ \t]+(?:\d+|[IVXLC]+)[^.\n]*):?$",
+    r"(?m)^([\p{Lu} \t]{4,}):?$",
+];
+
+impl Language {
+    // Picks the language whose common function words occur most often,
+    // falling back to English when nothing stands out.
+    pub fn detect(text: &str) -> Self {
+        let mut counts = [0usize; 3];
+        for token in text.split_whitespace() {
+            let token = token.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+            for (count, markers) in counts.iter_mut().zip([ENGLISH_MARKERS, GERMAN_MARKERS, FRENCH_MARKERS]) {
+                if markers.contains(&token.as_str()) {
+                    *count += 1;
+                }
+            }
+        }
+        let [english, german, french] = counts;
+        if german > english && german >= french {
+            Language::German
+        } else if french > english && french > german {
+            Language::French
+        } else {
+            Language::English
+        }
+    }
+
+    pub fn stemmer(self) -> Stemmer {
+        Stemmer::create(match self {
+            Language::English => Algorithm::English,
+            Language::German => Algorithm::German,
+            Language::French => Algorithm::French,
+        })
+    }
+
+    pub fn stopwords(self) -> &'static [&'static str] {
+        match self {
+            Language::English => ENGLISH_STOPWORDS,
+            Language::German => GERMAN_STOPWORDS,
+            Language::French => FRENCH_STOPWORDS,
+        }
+    }
+
+    // Whether the lowercased `word` is one of the language's stopwords, unless
+    // `exemptions` lists it (in any case).
+    pub fn is_stopword(self, word: &str, exemptions: &[String]) -> bool {
+        self.stopwords().contains(&word) && !exemptions.iter().any(|exempt| exempt.to_lowercase() == word)
+    }
+
+    // Lowercased content words, keeping exempted stopwords. French also splits
+    // elisions ("l'histoire").
+    pub fn content_tokens(self, text: &str, exemptions: &[String]) -> Vec<String> {
+        let lower = text.to_lowercase();
+        let words: Vec<&str> = match self {
+            Language::French => lower.split(|c: char| c.is_whitespace() || c == '\'' || c == '’').collect(),
+            _ => lower.split_whitespace().collect(),
+        };
+        words.into_iter()
+            .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()))
+            .filter(|w| !w.is_empty() && !self.is_stopword(w, exemptions))
+            .map(String::from)
+            .collect()
+    }
+
+    pub fn heading_patterns(self) -> &'static [&'static str] {
+        match self {
+            Language::English => ENGLISH_HEADINGS,
+            Language::German => GERMAN_HEADINGS,
+            Language::French => FRENCH_HEADINGS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::collections::HashMap;
+    use std::sync::OnceLock;
+
+    fn headings(language: Language, text: &str) -> Vec<String> {
+        static PATTERNS: OnceLock<HashMap<Language, Vec<Regex>>> = OnceLock::new();
+        let patterns = PATTERNS.get_or_init(|| [Language::English, Language::German, Language::French].into_iter()
+            .map(|language| (language, language.heading_patterns().iter().map(|pattern| Regex::new(pattern).unwrap()).collect()))
+            .collect());
+        patterns[&language].iter()
+            .flat_map(|pattern| pattern.captures_iter(text)
+                .map(|cap| cap[1].trim().to_string())
+                .collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[test]
+    fn german_headings_need_the_german_patterns() {
+        let text = "Übersicht über Straßenbahnen\n\nDie Bahn fährt stündlich.";
+        assert_eq!(headings(Language::German, text), ["Übersicht über Straßenbahnen"]);
+        assert!(headings(Language::English, text).is_empty());
+        assert_eq!(headings(Language::German, "Kapitel 3 Anreise\n"), ["Kapitel 3 Anreise"]);
+    }
+
+    #[test]
+    fn french_headings_allow_elisions_and_roman_numerals() {
+        assert_eq!(headings(Language::French, "II. L'histoire de Nice\n"), ["II. L'histoire de Nice"]);
+    }
+
+    #[test]
+    fn detect_picks_the_language_with_the_most_marker_words() {
+        assert_eq!(Language::detect("The trains leave from the station and the buses wait outside."), Language::English);
+        assert_eq!(Language::detect("Die Züge fahren vom Bahnhof und die Busse warten draußen."), Language::German);
+        assert_eq!(Language::detect("Les trains partent de la gare et les bus attendent dehors."), Language::French);
+        assert_eq!(Language::detect(""), Language::English);
+    }
+
+    #[test]
+    fn content_tokens_use_each_languages_stopwords() {
+        assert_eq!(Language::German.content_tokens("Die Züge und der Bahnhof", &[]), ["züge", "bahnhof"]);
+        assert_eq!(Language::French.content_tokens("L'histoire de la gare", &[]), ["l", "histoire", "gare"]);
+        assert_eq!(Language::English.content_tokens("Die Züge and the station", &[]), ["die", "züge", "station"]);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::models::{BoundingBox, ListItem};
+use crate::structure::{StructRole, TaggedBlock};
+use pdf::content::{Matrix, Op, TextDrawAdjusted};
+use pdf::primitive::Primitive;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+// Glyph widths aren't resolved, so run widths are estimated from the font size.
+const AVG_CHAR_WIDTH: f32 = 0.5;
+// List items whose x positions differ by less than this share an indent.
+const INDENT_TOLERANCE: f32 = 3.0;
+// Footnotes sit in this bottom share of the page's text and use a font smaller
+// than this share of the body size.
+const FOOTNOTE_REGION: f32 = 0.25;
+const FOOTNOTE_FONT_RATIO: f32 = 0.85;
+
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    pub text: String,
+    pub x: f32,
+    pub y: f32,
+    pub font_size: f32,
+    pub bold: bool,
+    // The marked-content ID the run was drawn under, for tagged PDFs.
+    pub mcid: Option<i32>,
+}
+
+impl TextRun {
+    pub fn width(&self) -> f32 {
+        self.text.chars().count() as f32 * self.font_size * AVG_CHAR_WIDTH
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TextLine {
+    pub text: String,
+    pub x: f32,
+    pub y: f32,
+    pub font_size: f32,
+    pub bold: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PageLayout {
+    pub runs: Vec<TextRun>,
+    // The page's headings, paragraphs and list items from the structure tree,
+    // when the document is tagged and structure tags are in use.
+    pub blocks: Option<Vec<TaggedBlock>>,
+}
+
+impl PageLayout {
+    // Consecutive runs sharing a baseline make up one line.
+    pub fn lines(&self) -> Vec<TextLine> {
+        self.grouped_lines().into_iter().map(|(line, _)| line).collect()
+    }
+
+    // Drops the runs of every line `keep` rejects.
+    pub fn retain_lines(&mut self, mut keep: impl FnMut(&TextLine) -> bool) {
+        let mut dropped = HashSet::new();
+        for (line, runs) in self.grouped_lines() {
+            if !keep(&line) {
+                dropped.extend(runs);
+            }
+        }
+        self.runs = std::mem::take(&mut self.runs).into_iter()
+            .enumerate()
+            .filter(|(i, _)| !dropped.contains(i))
+            .map(|(_, run)| run)
+            .collect();
+    }
+
+    // Each line with the indices of the runs it was built from.
+    fn grouped_lines(&self) -> Vec<(TextLine, Vec<usize>)> {
+        let mut lines: Vec<(TextLine, Vec<usize>)> = Vec::new();
+        for (i, run) in self.runs.iter().enumerate().filter(|(_, r)| !r.text.trim().is_empty()) {
+            match lines.last_mut() {
+                Some((line, runs)) if (line.y - run.y).abs() < run.font_size.max(1.0) * 0.5 => {
+                    line.text.push_str(&run.text);
+                    line.font_size = line.font_size.max(run.font_size);
+                    line.bold &= run.bold;
+                    runs.push(i);
+                }
+                _ => lines.push((TextLine {
+                    text: run.text.clone(),
+                    x: run.x,
+                    y: run.y,
+                    font_size: run.font_size,
+                    bold: run.bold,
+                }, vec![i])),
+            }
+        }
+        for (line, _) in &mut lines {
+            line.text = line.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        lines
+    }
+
+    // The font size carrying the most characters is taken as body text.
+    pub fn body_font_size(&self) -> f32 {
+        let mut chars_by_size: HashMap<u32, usize> = HashMap::new();
+        for run in &self.runs {
+            *chars_by_size.entry((run.font_size * 10.0).round() as u32).or_default() += run.text.trim().chars().count();
+        }
+        chars_by_size.into_iter()
+            .max_by_key(|(size, count)| (*count, *size))
+            .map(|(size, _)| size as f32 / 10.0)
+            .unwrap_or(0.0)
+    }
+
+    fn mostly_bold(&self) -> bool {
+        let (bold, total) = self.runs.iter().fold((0, 0), |(bold, total), run| {
+            let n = run.text.trim().chars().count();
+            (bold + if run.bold { n } else { 0 }, total + n)
+        });
+        total > 0 && bold * 2 > total
+    }
+
+    // Locates each run in the cleaned page text by searching for its words in
+    // reading order, then unions the boxes of the runs inside `start..end`.
+    pub fn bbox_for_span(&self, page_text: &str, start: usize, end: usize) -> Option<BoundingBox> {
+        let mut cursor = 0;
+        let mut bounds: Option<(f32, f32, f32, f32)> = None;
+        for run in &self.runs {
+            let needle = run.text.split_whitespace().collect::<Vec<_>>().join(" ");
+            if needle.is_empty() || cursor >= page_text.len() {
+                continue;
+            }
+            let Some(found) = page_text[cursor..].find(&needle) else {
+                continue;
+            };
+            let run_start = cursor + found;
+            cursor = run_start + needle.len();
+            if run_start >= end || cursor <= start {
+                continue;
+            }
+            let (left, bottom, right, top) = (run.x, run.y, run.x + run.width(), run.y + run.font_size);
+            bounds = Some(match bounds {
+                None => (left, bottom, right, top),
+                Some((l, b, r, t)) => (l.min(left), b.min(bottom), r.max(right), t.max(top)),
+            });
+        }
+        bounds.map(|(left, bottom, right, top)| BoundingBox {
+            x: left,
+            y: bottom,
+            width: right - left,
+            height: top - bottom,
+        })
+    }
+
+    // Lines starting with a bullet or list number, paired with the full line text.
+    // Items have their marker removed and a nesting level from 1 given by how far
+    // the line is indented relative to the page's other list items. Tagged pages
+    // use their list item elements and list nesting instead.
+    pub fn list_items(&self) -> Vec<(String, ListItem)> {
+        if let Some(blocks) = &self.blocks {
+            return blocks.iter().filter_map(|block| match block.role {
+                StructRole::ListItem(level) => Some((block.full_text(), ListItem { text: block.text.clone(), level })),
+                _ => None,
+            }).collect();
+        }
+        static LIST_MARKER: OnceLock<Regex> = OnceLock::new();
+        let marker = LIST_MARKER.get_or_init(|| Regex::new(r"^(?:[•◦▪▫‣⁃●○■□\-–*]|\d+[.)]|[a-zA-Z][.)]|[ivxIVX]+[.)])\s+").unwrap());
+        let items: Vec<(TextLine, usize)> = self.lines().into_iter()
+            .filter_map(|line| {
+                let marker_len = marker.find(&line.text)?.end();
+                (marker_len < line.text.len()).then_some((line, marker_len))
+            })
+            .collect();
+        let mut indents: Vec<f32> = Vec::new();
+        for (line, _) in &items {
+            if !indents.iter().any(|x| (x - line.x).abs() < INDENT_TOLERANCE) {
+                indents.push(line.x);
+            }
+        }
+        indents.sort_by(|a, b| a.total_cmp(b));
+        items.into_iter().map(|(line, marker_len)| {
+            let level = indents.iter().position(|x| (x - line.x).abs() < INDENT_TOLERANCE).unwrap_or(0) + 1;
+            let item = ListItem { text: line.text[marker_len..].to_string(), level: level as u8 };
+            (line.text, item)
+        }).collect()
+    }
+
+    // Footnotes are the run of small-font lines at the bottom of the page, in the
+    // lowest quarter of its text. A line starting with a number or note mark
+    // begins a new footnote; other lines continue the previous one.
+    pub fn footnotes(&self) -> Vec<String> {
+        let mut lines = self.lines();
+        let body_size = self.body_font_size();
+        let (Some(bottom), Some(top)) = (
+            lines.iter().map(|l| l.y).reduce(f32::min),
+            lines.iter().map(|l| l.y).reduce(f32::max),
+        ) else {
+            return Vec::new();
+        };
+        let limit = bottom + (top - bottom) * FOOTNOTE_REGION;
+        lines.sort_by(|a, b| a.y.total_cmp(&b.y));
+        let region: Vec<&TextLine> = lines.iter()
+            .take_while(|line| line.y <= limit && line.font_size < body_size * FOOTNOTE_FONT_RATIO)
+            .filter(|line| !line.text.is_empty())
+            .collect();
+        let mut notes: Vec<String> = Vec::new();
+        for line in region.into_iter().rev() {
+            let starts_note = line.text.starts_with(|c: char| c.is_ascii_digit() || "*†‡§".contains(c));
+            match notes.last_mut() {
+                Some(note) if !starts_note => {
+                    note.push(' ');
+                    note.push_str(&line.text);
+                }
+                _ => notes.push(line.text.clone()),
+            }
+        }
+        notes
+    }
+
+    // Splits the page into blocks at large vertical gaps and returns the first line
+    // of each block when it stands out from body text (larger font, or bold when
+    // body text isn't), with a level of 1 for larger text and 2 for bold-only.
+    // With `merge_wrapped`, lines that continue a title are appended to it.
+    pub fn block_titles(&self, merge_wrapped: bool) -> Vec<(String, u8)> {
+        let lines = self.lines();
+        let body_size = self.body_font_size();
+        let body_bold = self.mostly_bold();
+        let mut titles: Vec<(String, u8)> = Vec::new();
+        let mut previous: Option<&TextLine> = None;
+        let mut in_title = false;
+        for line in &lines {
+            let starts_block = match previous {
+                None => true,
+                Some(prev) => (prev.y - line.y).abs() > prev.font_size.max(line.font_size) * 1.6,
+            };
+            if !starts_block {
+                let continues = in_title && merge_wrapped && previous.is_some_and(|prev| Self::continues_title(prev, line));
+                match titles.last_mut() {
+                    Some((title, _)) if continues => {
+                        title.push(' ');
+                        title.push_str(&line.text);
+                    }
+                    _ => in_title = false,
+                }
+                previous = Some(line);
+                continue;
+            }
+            previous = Some(line);
+            in_title = false;
+            if line.text.is_empty() {
+                continue;
+            }
+            if let Some(level) = Self::title_level(line, body_size, body_bold) {
+                titles.push((line.text.clone(), level));
+                in_title = true;
+            }
+        }
+        titles
+    }
+
+    // Pairs of consecutive lines that both stand out from body text in the same
+    // style, one line apart, so the second reads as the first one wrapping.
+    pub fn wrapped_title_lines(&self) -> Vec<(String, String)> {
+        let lines = self.lines();
+        let body_size = self.body_font_size();
+        let body_bold = self.mostly_bold();
+        lines.windows(2)
+            .filter(|pair| {
+                let [first, second] = pair else { return false };
+                Self::title_level(first, body_size, body_bold).is_some()
+                    && Self::title_level(second, body_size, body_bold).is_some()
+                    && Self::continues_title(first, second)
+            })
+            .map(|pair| (pair[0].text.clone(), pair[1].text.clone()))
+            .collect()
+    }
+
+    fn title_level(line: &TextLine, body_size: f32, body_bold: bool) -> Option<u8> {
+        if line.font_size > body_size * 1.15 {
+            Some(1)
+        } else if line.bold && !body_bold {
+            Some(2)
+        } else {
+            None
+        }
+    }
+
+    fn continues_title(prev: &TextLine, line: &TextLine) -> bool {
+        !line.text.is_empty()
+            && (prev.font_size - line.font_size).abs() < 0.5
+            && prev.bold == line.bold
+            && (prev.y - line.y).abs() <= prev.font_size.max(line.font_size) * 1.6
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GraphicsState {
+    ctm: Matrix,
+    font_size: f32,
+    bold: bool,
+    leading: f32,
+}
+
+// Follows the text and graphics state operators of a content stream closely
+// enough to place each text run on the page.
+pub struct TextState {
+    state: GraphicsState,
+    stack: Vec<GraphicsState>,
+    // The MCID of each open marked-content sequence, innermost last.
+    marked_content: Vec<Option<i32>>,
+    text_matrix: Matrix,
+    line_matrix: Matrix,
+    bold_fonts: HashSet<String>,
+}
+
+impl TextState {
+    pub fn new(bold_fonts: HashSet<String>) -> Self {
+        Self {
+            state: GraphicsState { ctm: Matrix::default(), font_size: 0.0, bold: false, leading: 0.0 },
+            stack: Vec::new(),
+            marked_content: Vec::new(),
+            text_matrix: Matrix::default(),
+            line_matrix: Matrix::default(),
+            bold_fonts,
+        }
+    }
+
+    pub fn apply(&mut self, op: &Op) {
+        match op {
+            Op::Save => self.stack.push(self.state),
+            Op::Restore => {
+                if let Some(state) = self.stack.pop() {
+                    self.state = state;
+                }
+            }
+            Op::Transform { matrix } => self.state.ctm = multiply(matrix, &self.state.ctm),
+            Op::BeginText => {
+                self.text_matrix = Matrix::default();
+                self.line_matrix = Matrix::default();
+            }
+            Op::SetTextMatrix { matrix } => {
+                self.text_matrix = *matrix;
+                self.line_matrix = *matrix;
+            }
+            Op::MoveTextPosition { translation } => self.move_line(translation.x, translation.y),
+            Op::TextNewline => self.move_line(0.0, -self.state.leading),
+            Op::Leading { leading } => self.state.leading = *leading,
+            Op::TextFont { name, size } => {
+                self.state.font_size = *size;
+                self.state.bold = self.bold_fonts.contains(name.as_str());
+            }
+            Op::BeginMarkedContent { properties, .. } => {
+                let mcid = match properties {
+                    Some(Primitive::Dictionary(dict)) => dict.get("MCID").and_then(|id| id.as_integer().ok()),
+                    _ => None,
+                };
+                self.marked_content.push(mcid);
+            }
+            Op::EndMarkedContent => {
+                self.marked_content.pop();
+            }
+            _ => {}
+        }
+    }
+
+    // Returns the runs drawn by a text-showing operator and advances the text position.
+    pub fn draw(&mut self, op: &Op) -> Vec<TextRun> {
+        let mut runs = Vec::new();
+        match op {
+            Op::TextDraw { text } => runs.push(self.emit(text.to_string_lossy())),
+            Op::TextDrawAdjusted { array } => {
+                for item in array {
+                    match item {
+                        TextDrawAdjusted::Text(text) => runs.push(self.emit(text.to_string_lossy())),
+                        TextDrawAdjusted::Spacing(adjust) => self.advance(-adjust / 1000.0 * self.state.font_size),
+                    }
+                }
+            }
+            _ => {}
+        }
+        runs
+    }
+
+    // The distance between baselines on the page: the leading when one is set,
+    // else the usual 1.2 times the font size.
+    pub fn line_spacing(&self) -> f32 {
+        let spacing = if self.state.leading > 0.0 { self.state.leading } else { self.state.font_size * 1.2 };
+        spacing * self.scale()
+    }
+
+    fn scale(&self) -> f32 {
+        let rendering = multiply(&self.text_matrix, &self.state.ctm);
+        let scale = (rendering.c * rendering.c + rendering.d * rendering.d).sqrt();
+        if scale > 0.0 { scale } else { 1.0 }
+    }
+
+    fn emit(&mut self, text: String) -> TextRun {
+        let rendering = multiply(&self.text_matrix, &self.state.ctm);
+        let run = TextRun {
+            x: rendering.e,
+            y: rendering.f,
+            font_size: self.state.font_size * self.scale(),
+            bold: self.state.bold,
+            mcid: self.marked_content.iter().rev().find_map(|mcid| *mcid),
+            text,
+        };
+        self.advance(run.text.chars().count() as f32 * self.state.font_size * AVG_CHAR_WIDTH);
+        run
+    }
+
+    fn advance(&mut self, tx: f32) {
+        self.text_matrix = multiply(&translation(tx, 0.0), &self.text_matrix);
+    }
+
+    fn move_line(&mut self, tx: f32, ty: f32) {
+        self.line_matrix = multiply(&translation(tx, ty), &self.line_matrix);
+        self.text_matrix = self.line_matrix;
+    }
+}
+
+fn translation(tx: f32, ty: f32) -> Matrix {
+    Matrix { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: tx, f: ty }
+}
+
+// PDF matrices act on row vectors, so `multiply(m, n)` applies `m` first.
+fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    Matrix {
+        a: m.a * n.a + m.b * n.c,
+        b: m.a * n.b + m.b * n.d,
+        c: m.c * n.a + m.d * n.c,
+        d: m.c * n.b + m.d * n.d,
+        e: m.e * n.a + m.f * n.c + n.e,
+        f: m.e * n.b + m.f * n.d + n.f,
+    }
+}
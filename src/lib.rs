@@ -0,0 +1,26 @@
+pub mod atom_output;
+pub mod checkpoint;
+pub mod cli;
+pub mod config;
+pub mod csv_output;
+pub mod deps;
+pub mod language;
+pub mod layout;
+pub mod markdown_output;
+pub mod memory_budget;
+pub mod models;
+pub mod pdf_processor;
+pub mod post_processor;
+#[cfg(feature = "http")]
+pub mod remote;
+#[cfg(feature = "s3")]
+pub mod s3_output;
+pub mod scoring;
+pub mod structure;
+#[cfg(test)]
+mod test_pdf;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_output;
+pub mod utils;
+
+pub use pdf_processor::extract_pages;
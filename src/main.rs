@@ -1,20 +1,300 @@
-mod config;
-mod models;
-mod pdf_processor;
+use anyhow::{Context, Result};
+use clap::Parser;
+use log::{error, info, warn};
+use pdf_analyzer::models::{CollectionStatus, CollectionSummary, RunReport};
+use pdf_analyzer::{checkpoint, cli, config, deps, pdf_processor, utils};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
-use anyhow::Result;
+// The library's PDF writer, built into the binary's tests too; they only
+// need plain text pages.
+#[cfg(test)]
+#[path = "test_pdf.rs"]
+#[allow(dead_code)]
+mod test_pdf;
+
+const INTERRUPTED_EXIT_CODE: i32 = 130;
 
 fn main() -> Result<()> {
-    let config = config::Config::new()?;
+    let started = Instant::now();
+    let cli = cli::Cli::parse();
+    // Progress and warnings go to stderr; RUST_LOG adjusts the level.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let config = config::Config::new(&cli)?;
+    if cli.check_deps {
+        std::process::exit(if deps::report(&config.options) { 0 } else { 1 });
+    }
     let collections = config.get_collection_paths()?;
+    let processor = pdf_processor::PdfProcessor::new(config.options.clone())?;
 
-    for (name, input_path, output_path) in collections {
-        println!("Processing collection: {}", name);
-        pdf_processor::PdfProcessor::process_pdf_collection(
-            &input_path.to_string_lossy(),
-            &output_path.to_string_lossy()
-        )?;
+    let checkpoint_path = config.collections_dir.join(checkpoint::CHECKPOINT_FILENAME);
+    let mut checkpoint = if cli.resume {
+        checkpoint::Checkpoint::load(checkpoint_path)?
+    } else {
+        checkpoint::Checkpoint::new(checkpoint_path)
+    };
+
+    // The first Ctrl-C lets the current collection finish; a second one exits immediately.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = Arc::clone(&interrupted);
+        ctrlc::set_handler(move || {
+            if interrupted.swap(true, Ordering::SeqCst) {
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            warn!("Interrupt received, finishing the current collection (Ctrl-C again to abort)");
+        })?;
+    }
+
+    let (mut report, completed_this_run) = run_collections(&processor, collections, &mut checkpoint, &interrupted)?;
+    report.interrupted = interrupted.load(Ordering::SeqCst);
+    report.elapsed_secs = started.elapsed().as_secs_f64();
+    if let Some(path) = &cli.report {
+        write_report(path, &report)?;
+    }
+    info!("{}", summary_line(&report));
+
+    if report.interrupted {
+        warn!(
+            "Interrupted after {} collection(s) this run: {}",
+            completed_this_run.len(),
+            completed_this_run.join(", ")
+        );
+        warn!("Completed so far: {}", checkpoint.completed().collect::<Vec<_>>().join(", "));
+        warn!("Re-run with --resume to continue");
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    if report.failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} collection(s) failed", report.failed, report.collections.len()));
     }
 
     Ok(())
-} 
\ No newline at end of file
+}
+
+// Processes each collection the checkpoint doesn't list, stopping once
+// `interrupted` is set. Returns the report and the collections completed.
+fn run_collections(
+    processor: &pdf_processor::PdfProcessor,
+    collections: Vec<(String, PathBuf, PathBuf)>,
+    checkpoint: &mut checkpoint::Checkpoint,
+    interrupted: &AtomicBool,
+) -> Result<(RunReport, Vec<String>)> {
+    let mut completed_this_run = Vec::new();
+    let mut report = RunReport::default();
+    for (name, input_path, output_path) in collections {
+        if interrupted.load(Ordering::SeqCst) {
+            report.record(&name, CollectionStatus::NotRun, CollectionSummary::default(), None);
+            continue;
+        }
+        if checkpoint.is_completed(&name) {
+            info!("Skipping completed collection: {}", name);
+            report.record(&name, CollectionStatus::Skipped, CollectionSummary::default(), None);
+            continue;
+        }
+        info!("Processing collection: {}", name);
+        match processor.process_pdf_collection(&input_path.to_string_lossy(), &output_path.to_string_lossy()) {
+            Ok(summary) => {
+                checkpoint.mark_completed(&name)?;
+                report.record(&name, CollectionStatus::Processed, summary, None);
+                completed_this_run.push(name);
+            }
+            // A failed collection is left out of the checkpoint, so `--resume`
+            // retries it.
+            Err(e) => {
+                error!("Collection {} failed: {:#}", name, e);
+                report.record(&name, CollectionStatus::Failed, CollectionSummary::default(), Some(format!("{:#}", e)));
+            }
+        }
+    }
+    Ok((report, completed_this_run))
+}
+
+// "7 succeeded, 1 failed", with the skipped count when there is one.
+fn summary_line(report: &RunReport) -> String {
+    let mut summary = format!("{} succeeded, {} failed", report.processed, report.failed);
+    if report.skipped > 0 {
+        summary.push_str(&format!(", {} skipped", report.skipped));
+    }
+    summary
+}
+
+fn write_report(path: &Path, report: &RunReport) -> Result<()> {
+    utils::write_atomic(path, serde_json::to_string_pretty(report)?)
+        .with_context(|| format!("Failed to write run report to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf_analyzer::config::ProcessingOptions;
+    use pdf_analyzer::models::{InputJson, OutputJson};
+    use pdf_analyzer::post_processor::OutputPostProcessor;
+    use test_pdf::TestPdf;
+
+    // Raises the interrupt flag while the `at`th collection (from 1) is being
+    // processed, as the Ctrl-C handler would.
+    struct InterruptAt {
+        at: usize,
+        seen: std::sync::atomic::AtomicUsize,
+        interrupted: Arc<AtomicBool>,
+    }
+
+    impl InterruptAt {
+        fn new(at: usize, interrupted: &Arc<AtomicBool>) -> Self {
+            Self { at, seen: Default::default(), interrupted: Arc::clone(interrupted) }
+        }
+    }
+
+    impl OutputPostProcessor for InterruptAt {
+        fn process(&self, _output: &mut OutputJson, _input: &InputJson) {
+            if self.seen.fetch_add(1, Ordering::SeqCst) + 1 == self.at {
+                self.interrupted.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn collections(root: &Path, names: &[&str]) -> Vec<(String, PathBuf, PathBuf)> {
+        names.iter().map(|name| {
+            let dir = root.join(name);
+            std::fs::create_dir_all(dir.join("pdfs")).unwrap();
+            TestPdf::new().plain_text("A beach trip for the travel planner.\n").write(&dir.join("pdfs/guide.pdf"));
+            let input = serde_json::json!({
+                "challenge_info": { "challenge_id": "test", "test_case_name": name },
+                "documents": [{ "filename": "guide.pdf", "title": "Guide" }],
+                "persona": { "role": "Travel planner" },
+                "job_to_be_done": { "task": "Plan a beach trip" },
+            });
+            std::fs::write(dir.join("challenge1b_input.json"), input.to_string()).unwrap();
+            (name.to_string(), dir.join("challenge1b_input.json"), dir.join("challenge1b_output.json"))
+        }).collect()
+    }
+
+    fn statuses(report: &RunReport) -> Vec<(&str, CollectionStatus)> {
+        report.collections.iter().map(|collection| (collection.name.as_str(), collection.status)).collect()
+    }
+
+    #[test]
+    fn resume_processes_only_the_collections_left_by_an_interrupt() {
+        let root = tempfile::tempdir().unwrap();
+        let collections = collections(root.path(), &["alpha", "beta", "gamma"]);
+        let checkpoint_path = root.path().join(checkpoint::CHECKPOINT_FILENAME);
+
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let processor = pdf_processor::PdfProcessor::new(ProcessingOptions::default()).unwrap()
+            .with_post_processor(InterruptAt::new(1, &interrupted));
+        let mut checkpoint = checkpoint::Checkpoint::new(checkpoint_path.clone());
+        let (report, completed) = run_collections(&processor, collections.clone(), &mut checkpoint, &interrupted).unwrap();
+        assert_eq!(completed, ["alpha"]);
+        assert_eq!(statuses(&report), [
+            ("alpha", CollectionStatus::Processed),
+            ("beta", CollectionStatus::NotRun),
+            ("gamma", CollectionStatus::NotRun),
+        ]);
+        // The completed collection's output is whole; the others weren't started.
+        let output: OutputJson = serde_json::from_str(&std::fs::read_to_string(&collections[0].2).unwrap()).unwrap();
+        assert_eq!(output.subsection_analysis.len(), 1);
+        assert!(!collections[1].2.exists() && !collections[2].2.exists());
+
+        let processor = pdf_processor::PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let mut checkpoint = checkpoint::Checkpoint::load(checkpoint_path).unwrap();
+        let (report, completed) = run_collections(&processor, collections.clone(), &mut checkpoint, &AtomicBool::new(false)).unwrap();
+        assert_eq!(completed, ["beta", "gamma"]);
+        assert_eq!(statuses(&report), [
+            ("alpha", CollectionStatus::Skipped),
+            ("beta", CollectionStatus::Processed),
+            ("gamma", CollectionStatus::Processed),
+        ]);
+        assert_eq!(checkpoint.completed().collect::<Vec<_>>(), ["alpha", "beta", "gamma"]);
+    }
+
+    #[test]
+    fn failed_collections_are_left_for_resume() {
+        let root = tempfile::tempdir().unwrap();
+        let collections = collections(root.path(), &["alpha", "beta"]);
+        std::fs::remove_file(root.path().join("alpha/pdfs/guide.pdf")).unwrap();
+        let processor = pdf_processor::PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let mut checkpoint = checkpoint::Checkpoint::new(root.path().join(checkpoint::CHECKPOINT_FILENAME));
+        let (report, _) = run_collections(&processor, collections, &mut checkpoint, &AtomicBool::new(false)).unwrap();
+        assert_eq!(statuses(&report), [("alpha", CollectionStatus::Failed), ("beta", CollectionStatus::Processed)]);
+        assert!(!checkpoint.is_completed("alpha"));
+    }
+
+    #[test]
+    fn an_interrupt_lets_the_current_collection_finish_writing() {
+        let root = tempfile::tempdir().unwrap();
+        let collections = collections(root.path(), &["alpha", "beta", "gamma"]);
+        let checkpoint_path = root.path().join(checkpoint::CHECKPOINT_FILENAME);
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let processor = pdf_processor::PdfProcessor::new(ProcessingOptions::default()).unwrap()
+            .with_post_processor(InterruptAt::new(2, &interrupted));
+        let mut checkpoint = checkpoint::Checkpoint::new(checkpoint_path.clone());
+        let (report, completed) = run_collections(&processor, collections.clone(), &mut checkpoint, &interrupted).unwrap();
+
+        assert_eq!(completed, ["alpha", "beta"]);
+        assert_eq!(report.processed, 2);
+        for (_, _, output_path) in &collections[..2] {
+            let output: OutputJson = serde_json::from_str(&std::fs::read_to_string(output_path).unwrap()).unwrap();
+            assert_eq!(output.metadata.input_documents, ["guide.pdf"]);
+        }
+        assert!(!collections[2].2.exists());
+        assert_eq!(std::fs::read_to_string(checkpoint_path).unwrap(), "alpha\nbeta\n");
+        // Atomic writes leave no temp files behind.
+        let leftovers: Vec<_> = walkdir::WalkDir::new(root.path()).into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "tmp"))
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+    }
+
+    #[test]
+    fn the_written_report_matches_what_was_processed_and_what_failed() {
+        let root = tempfile::tempdir().unwrap();
+        let collections = collections(root.path(), &["alpha", "beta", "gamma"]);
+        std::fs::remove_file(root.path().join("beta/pdfs/guide.pdf")).unwrap();
+        let processor = pdf_processor::PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let mut checkpoint = checkpoint::Checkpoint::new(root.path().join(checkpoint::CHECKPOINT_FILENAME));
+        checkpoint.mark_completed("gamma").unwrap();
+        let (report, _) = run_collections(&processor, collections, &mut checkpoint, &AtomicBool::new(false)).unwrap();
+        let path = root.path().join("report.json");
+        write_report(&path, &report).unwrap();
+
+        let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!((written["processed"].as_u64(), written["failed"].as_u64(), written["skipped"].as_u64()), (Some(1), Some(1), Some(1)));
+        assert_eq!((written["documents"].as_u64(), written["subsections"].as_u64()), (Some(1), Some(1)));
+        let entries = written["collections"].as_array().unwrap();
+        let statuses: Vec<(&str, &str)> = entries.iter()
+            .map(|entry| (entry["name"].as_str().unwrap(), entry["status"].as_str().unwrap()))
+            .collect();
+        assert_eq!(statuses, [("alpha", "processed"), ("beta", "failed"), ("gamma", "skipped")]);
+        assert!(entries[0].get("error").is_none());
+        assert!(entries[1]["error"].as_str().unwrap().contains("guide.pdf"));
+    }
+
+    #[test]
+    fn a_failed_collection_does_not_stop_the_ones_after_it() {
+        let root = tempfile::tempdir().unwrap();
+        let collections = collections(root.path(), &["alpha", "beta", "gamma", "delta"]);
+        std::fs::write(&collections[1].1, "{ not json").unwrap();
+        std::fs::remove_file(root.path().join("delta/pdfs/guide.pdf")).unwrap();
+        let processor = pdf_processor::PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let mut checkpoint = checkpoint::Checkpoint::new(root.path().join(checkpoint::CHECKPOINT_FILENAME));
+        let (report, completed) = run_collections(&processor, collections.clone(), &mut checkpoint, &AtomicBool::new(false)).unwrap();
+
+        assert_eq!(statuses(&report), [
+            ("alpha", CollectionStatus::Processed),
+            ("beta", CollectionStatus::Failed),
+            ("gamma", CollectionStatus::Processed),
+            ("delta", CollectionStatus::Failed),
+        ]);
+        assert_eq!(completed, ["alpha", "gamma"]);
+        let written: Vec<bool> = collections.iter().map(|(_, _, output_path)| output_path.exists()).collect();
+        assert_eq!(written, [true, false, true, false]);
+        assert_eq!(summary_line(&report), "2 succeeded, 2 failed");
+
+        let mut checkpoint = checkpoint::Checkpoint::load(root.path().join(checkpoint::CHECKPOINT_FILENAME)).unwrap();
+        let (report, _) = run_collections(&processor, collections, &mut checkpoint, &AtomicBool::new(false)).unwrap();
+        assert_eq!(summary_line(&report), "0 succeeded, 2 failed, 2 skipped");
+    }
+}
@@ -0,0 +1,131 @@
+use crate::models::{OutputJson, SubsectionAnalysis};
+
+// Renders a collection's output as a Markdown report for human review: the
+// query as the title, a table of the ranked sections, and each document's
+// subsections as blockquotes. Documents appear in input order.
+pub fn render(output: &OutputJson) -> String {
+    let metadata = &output.metadata;
+    let mut report = format!("# {}: {}\n\n", inline(&metadata.persona), inline(&metadata.job_to_be_done));
+    report.push_str(&format!("- **Persona:** {}\n", inline(&metadata.persona)));
+    report.push_str(&format!("- **Job to be done:** {}\n", inline(&metadata.job_to_be_done)));
+    report.push_str(&format!("- **Documents:** {}\n", metadata.input_documents.iter().map(|doc| inline(doc)).collect::<Vec<_>>().join(", ")));
+    report.push_str(&format!("- **Processed:** {}\n", inline(&metadata.processing_timestamp)));
+
+    report.push_str("\n## Extracted sections\n\n");
+    if output.extracted_sections.is_empty() {
+        report.push_str("No sections were extracted.\n");
+    } else {
+        report.push_str("| Rank | Document | Page | Section |\n| ---: | --- | ---: | --- |\n");
+        let mut sections: Vec<_> = output.extracted_sections.iter().collect();
+        sections.sort_by_key(|section| section.importance_rank);
+        for section in sections {
+            report.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                section.importance_rank, cell(&section.document), section.page_number, cell(&section.section_title)
+            ));
+        }
+    }
+
+    report.push_str("\n## Subsection analysis\n");
+    if output.subsection_analysis.is_empty() {
+        report.push_str("\nNo relevant subsections were found.\n");
+    }
+    let mut documents: Vec<&str> = metadata.input_documents.iter().map(String::as_str).collect();
+    for subsection in &output.subsection_analysis {
+        if !documents.contains(&subsection.document.as_str()) {
+            documents.push(&subsection.document);
+        }
+    }
+    for document in documents {
+        let subsections: Vec<&SubsectionAnalysis> = output.subsection_analysis.iter()
+            .filter(|subsection| subsection.document == document)
+            .collect();
+        if subsections.is_empty() {
+            continue;
+        }
+        report.push_str(&format!("\n### {}\n", inline(document)));
+        for subsection in subsections {
+            report.push('\n');
+            for line in subsection.refined_text.lines() {
+                let line = line.trim_end();
+                if line.is_empty() {
+                    report.push_str(">\n");
+                } else {
+                    report.push_str(&format!("> {}\n", line));
+                }
+            }
+            report.push_str(&format!(">\n> — page {}\n", subsection.page_number));
+        }
+    }
+    report
+}
+
+// Text on a single line, as headings and list items need.
+fn inline(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Pipes would end the table cell early.
+fn cell(text: &str) -> String {
+    inline(text).replace('|', "\\|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(sections: serde_json::Value, subsections: serde_json::Value) -> OutputJson {
+        serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "input_documents": ["south.pdf", "north.pdf"],
+                "persona": "Travel planner",
+                "job_to_be_done": "Plan a trip\nfor 10 friends",
+                "processing_timestamp": "2024-01-01T00:00:00Z",
+            },
+            "extracted_sections": sections,
+            "subsection_analysis": subsections,
+        })).unwrap()
+    }
+
+    #[test]
+    fn the_report_ranks_sections_and_groups_subsections_by_document() {
+        let output = output(
+            serde_json::json!([
+                { "document": "north.pdf", "section_title": "Hotels | Hostels", "importance_rank": 2, "page_number": 3 },
+                { "document": "south.pdf", "section_title": "Beaches\nand Coves", "importance_rank": 1, "page_number": 1 },
+            ]),
+            serde_json::json!([
+                { "document": "north.pdf", "refined_text": "A hostel near the station.", "page_number": 3 },
+                { "document": "south.pdf", "refined_text": "A beach trip.\n\nSandy coves.", "page_number": 1 },
+                { "document": "south.pdf", "refined_text": "A harbour walk.", "page_number": 2 },
+            ]),
+        );
+
+        assert_eq!(render(&output), concat!(
+            "# Travel planner: Plan a trip for 10 friends\n\n",
+            "- **Persona:** Travel planner\n",
+            "- **Job to be done:** Plan a trip for 10 friends\n",
+            "- **Documents:** south.pdf, north.pdf\n",
+            "- **Processed:** 2024-01-01T00:00:00Z\n",
+            "\n## Extracted sections\n\n",
+            "| Rank | Document | Page | Section |\n| ---: | --- | ---: | --- |\n",
+            "| 1 | south.pdf | 1 | Beaches and Coves |\n",
+            "| 2 | north.pdf | 3 | Hotels \\| Hostels |\n",
+            "\n## Subsection analysis\n",
+            "\n### south.pdf\n",
+            "\n> A beach trip.\n>\n> Sandy coves.\n>\n> — page 1\n",
+            "\n> A harbour walk.\n>\n> — page 2\n",
+            "\n### north.pdf\n",
+            "\n> A hostel near the station.\n>\n> — page 3\n",
+        ));
+    }
+
+    #[test]
+    fn an_empty_output_says_nothing_was_found() {
+        let report = render(&output(serde_json::json!([]), serde_json::json!([])));
+        assert!(report.ends_with(concat!(
+            "\n## Extracted sections\n\nNo sections were extracted.\n",
+            "\n## Subsection analysis\n\nNo relevant subsections were found.\n",
+        )), "{}", report);
+    }
+}
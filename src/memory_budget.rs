@@ -0,0 +1,83 @@
+use std::sync::{Condvar, Mutex};
+
+// A soft cap on the estimated bytes of text held by in-flight extractions.
+// Reservations block while the budget is exhausted and are released on drop.
+// A reservation larger than the whole budget still proceeds once nothing else
+// is in flight, so oversized documents are serialized rather than rejected.
+pub struct MemoryBudget {
+    limit: usize,
+    in_use: Mutex<usize>,
+    released: Condvar,
+}
+
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: usize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self { limit, in_use: Mutex::new(0), released: Condvar::new() }
+    }
+
+    pub fn reserve(&self, bytes: usize) -> Reservation<'_> {
+        let mut in_use = self.in_use.lock().unwrap_or_else(|e| e.into_inner());
+        while *in_use > 0 && *in_use + bytes > self.limit {
+            in_use = self.released.wait(in_use).unwrap_or_else(|e| e.into_inner());
+        }
+        *in_use += bytes;
+        Reservation { budget: self, bytes }
+    }
+
+    pub fn in_use(&self) -> usize {
+        *self.in_use.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut in_use = self.budget.in_use.lock().unwrap_or_else(|e| e.into_inner());
+        *in_use -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    // Runs `workers` threads that each reserve `bytes` for a moment and returns
+    // the most that were holding a reservation at once.
+    fn peak_concurrency(budget: &MemoryBudget, workers: usize, bytes: usize) -> usize {
+        let active = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    let _reservation = budget.reserve(bytes);
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(50));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        peak.into_inner()
+    }
+
+    #[test]
+    fn reservations_over_the_budget_wait_for_earlier_ones() {
+        let budget = MemoryBudget::new(100);
+        assert_eq!(peak_concurrency(&budget, 4, 60), 1);
+        assert!(peak_concurrency(&budget, 4, 25) > 1);
+        assert_eq!(budget.in_use(), 0);
+    }
+
+    #[test]
+    fn an_oversized_reservation_proceeds_alone() {
+        let budget = MemoryBudget::new(100);
+        assert_eq!(peak_concurrency(&budget, 2, 500), 1);
+    }
+}
@@ -1,4 +1,12 @@
+use crate::language::Language;
 use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+const CORE_OUTPUT_FIELDS: &[&str] = &["metadata", "extracted_sections", "subsection_analysis"];
+const CORE_METADATA_FIELDS: &[&str] = &["input_documents", "persona", "job_to_be_done", "processing_timestamp"];
+const CORE_SECTION_FIELDS: &[&str] = &["document", "section_title", "importance_rank", "page_number"];
+const CORE_SUBSECTION_FIELDS: &[&str] = &["document", "refined_text", "page_number"];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChallengeInfo {
@@ -11,7 +19,26 @@ pub struct ChallengeInfo {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Document {
     pub filename: String,
+    // May be left blank; see `document_titles` for the fallbacks.
+    #[serde(default)]
     pub title: String,
+    // Added to the PDF page index so reported page numbers match the printed ones.
+    #[serde(default)]
+    pub page_offset: i32,
+    #[serde(default)]
+    pub force_ocr: bool,
+    // 1-based PDF page indices to extract; all pages when unset.
+    #[serde(default)]
+    pub pages: Option<Vec<u32>>,
+    // Keep line breaks and spacing, for code listings and aligned tables.
+    #[serde(default)]
+    pub preserve_whitespace: bool,
+    // "YYYY-MM-DD" or RFC 3339; overrides the PDF's own dates for recency weighting.
+    #[serde(default)]
+    pub date: Option<String>,
+    // Opens the PDF when it is encrypted; see the `pdf_password` option.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,12 +59,107 @@ pub struct InputJson {
     pub job_to_be_done: JobToBeDone,
 }
 
+impl InputJson {
+    // Checks what parsing can't: that there is something to process and that
+    // each document can be told apart. Reports every problem at once.
+    pub fn validate(&self) -> Result<(), InvalidInput> {
+        let mut problems = Vec::new();
+        if self.documents.is_empty() {
+            problems.push("`documents` is empty; list at least one document".to_string());
+        }
+        let mut seen = std::collections::HashSet::new();
+        for (i, doc) in self.documents.iter().enumerate() {
+            if doc.filename.trim().is_empty() {
+                problems.push(format!("documents[{}] has an empty `filename`", i));
+            } else if !seen.insert(doc.filename.as_str()) {
+                problems.push(format!("documents[{}] repeats the filename {:?}", i, doc.filename));
+            }
+        }
+        if self.persona.role.trim().is_empty() {
+            problems.push("`persona.role` is empty".to_string());
+        }
+        if self.job_to_be_done.task.trim().is_empty() {
+            problems.push("`job_to_be_done.task` is empty".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidInput { problems })
+        }
+    }
+}
+
+// Everything `InputJson::validate` found wrong, one message per problem.
+#[derive(Debug)]
+pub struct InvalidInput {
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for InvalidInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} problem(s) in the input:", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidInput {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ExtractedSection {
     pub document: String,
     pub section_title: String,
+    // The heading as detected, when title normalization changed it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_title: Option<String>,
     pub importance_rank: u32,
     pub page_number: u32,
+    // Only reported when `heading_level_weights` or `max_heading_level` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heading_level: Option<u8>,
+    // Occurrences of each matched persona/task keyword in the section's subsections.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub term_frequencies: Option<BTreeMap<String, u32>>,
+    // The page text between this heading and the next one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section_body: Option<String>,
+}
+
+// Page coordinates with the origin at the bottom-left, as in PDF user space.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+// A bullet or numbered list item; `level` 1 is the outermost list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListItem {
+    pub text: String,
+    pub level: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeywordMatch {
+    pub keyword: String,
+    pub positions: Vec<usize>,
+}
+
+// Why a subsection was selected: the keywords it matched and how they scored.
+// `score` is the paragraph score used for ranking, which can include bonuses
+// beyond the persona and task counts.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatchExplanation {
+    pub summary: String,
+    pub persona_matches: Vec<KeywordMatch>,
+    pub task_matches: Vec<KeywordMatch>,
+    pub persona_score: f64,
+    pub task_score: f64,
+    pub score: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -45,6 +167,113 @@ pub struct SubsectionAnalysis {
     pub document: String,
     pub refined_text: String,
     pub page_number: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<BoundingBox>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub list_items: Vec<ListItem>,
+    // Distinct persona and task keywords matched, when scores are split.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persona_score: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_score: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<MatchExplanation>,
+    // The heading this paragraph falls under on its page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_section_title: Option<String>,
+    // The score the paragraph was ranked by, for re-ranking downstream.
+    #[serde(default)]
+    pub relevance_score: f32,
+}
+
+// A footnote scored separately from the body text of its page.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FootnoteAnalysis {
+    pub document: String,
+    pub text: String,
+    pub page_number: u32,
+    pub score: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SkippedPage {
+    pub document: String,
+    pub page_number: u32,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceHash {
+    pub document: String,
+    pub sha256: String,
+}
+
+// A document whose declared page count disagrees with its page tree.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageCountMismatch {
+    pub document: String,
+    pub declared: u32,
+    pub actual: u32,
+}
+
+// How much a page's extracted text looks like real text, from 0 to 1. The
+// signals behind it are reported alongside.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageConfidence {
+    pub document: String,
+    pub page_number: u32,
+    pub confidence: f64,
+    pub chars: u32,
+    pub word_ratio: f64,
+    pub replacement_chars: u32,
+}
+
+// Share of a document's extracted pages and characters that made it into results.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentCoverage {
+    pub document: String,
+    pub pages_extracted: u32,
+    pub pages_with_results: u32,
+    pub page_coverage: f64,
+    pub text_coverage: f64,
+}
+
+// How much text was extracted from a document.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentStatistics {
+    pub document: String,
+    pub pages: u32,
+    pub words: u64,
+    pub characters: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentLanguage {
+    pub document: String,
+    pub language: Language,
+}
+
+// Where a document's resolved title came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleSource {
+    Input,
+    PdfMetadata,
+    Filename,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentTitle {
+    pub document: String,
+    pub title: String,
+    pub source: TitleSource,
+}
+
+// The keyword lists actually used for matching, after all filtering.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EffectiveKeywords {
+    pub persona: Vec<String>,
+    pub task: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -53,6 +282,181 @@ pub struct Metadata {
     pub persona: String,
     pub job_to_be_done: String,
     pub processing_timestamp: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_pages: Vec<SkippedPage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub page_count_mismatches: Vec<PageCountMismatch>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_hashes: Vec<SourceHash>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub document_coverage: Vec<DocumentCoverage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub page_confidence: Vec<PageConfidence>,
+    // Only reported when documents differ in language from each other or from the query.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub document_languages: Vec<DocumentLanguage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub document_titles: Vec<DocumentTitle>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language_note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_keywords: Option<EffectiveKeywords>,
+    // Totals over the pages text was extracted from, and the time taken.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_pages: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_words: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_characters: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub document_statistics: Vec<DocumentStatistics>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processing_duration_ms: Option<u64>,
+}
+
+// A page where an index keyword was matched, with the best paragraph score
+// among the subsections there that contain it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeywordLocation {
+    pub document: String,
+    pub page_number: u32,
+    pub score: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentRanking {
+    pub document: String,
+    pub score: f64,
+    pub rank: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentLink {
+    pub source: String,
+    pub target: String,
+    pub similarity: f64,
+}
+
+// Where a bundle excerpt came from.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceAnchor {
+    pub document: String,
+    pub page_number: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<BoundingBox>,
+}
+
+// A ranked section with the best subsection supporting it, ready for presentation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub rank: u32,
+    pub section_title: String,
+    pub excerpt: String,
+    pub source: SourceAnchor,
+}
+
+// What extraction and structure detection found in one document, for
+// `--structure-report`. `sample_titles` are the first detected headings.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentStructure {
+    pub document: String,
+    pub pages_extracted: usize,
+    pub characters: usize,
+    // Pages whose text came from structure tags (`use_structure_tags`).
+    pub tagged_pages: usize,
+    pub headings: usize,
+    pub headings_by_level: BTreeMap<u8, usize>,
+    pub sample_titles: Vec<String>,
+    pub paragraphs: usize,
+    pub list_items: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StructureReport {
+    pub documents: Vec<DocumentStructure>,
+}
+
+// Counts for one collection written by `process_pdf_collection`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct CollectionSummary {
+    pub documents: usize,
+    pub sections: usize,
+    pub subsections: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollectionStatus {
+    Processed,
+    // Already completed according to the checkpoint.
+    Skipped,
+    Failed,
+    // Not reached because the run stopped early.
+    NotRun,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionReport {
+    pub name: String,
+    pub status: CollectionStatus,
+    #[serde(flatten)]
+    pub summary: CollectionSummary,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// The run summary written with `--report`, for schedulers.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RunReport {
+    pub processed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub documents: usize,
+    pub sections: usize,
+    pub subsections: usize,
+    pub elapsed_secs: f64,
+    pub interrupted: bool,
+    pub collections: Vec<CollectionReport>,
+}
+
+impl RunReport {
+    pub fn record(&mut self, name: &str, status: CollectionStatus, summary: CollectionSummary, error: Option<String>) {
+        match status {
+            CollectionStatus::Processed => self.processed += 1,
+            CollectionStatus::Skipped => self.skipped += 1,
+            CollectionStatus::Failed => self.failed += 1,
+            CollectionStatus::NotRun => {}
+        }
+        self.documents += summary.documents;
+        self.sections += summary.sections;
+        self.subsections += summary.subsections;
+        self.collections.push(CollectionReport { name: name.to_string(), status, summary, error });
+    }
+}
+
+// The term-document count matrix behind TF-IDF similarity, exported with
+// `--export-matrix`. `counts[d][t]` is how often `terms[t]` occurs in
+// `documents[d]`; `document_frequency[t]` is how many documents contain it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TermMatrix {
+    pub documents: Vec<String>,
+    pub terms: Vec<String>,
+    pub counts: Vec<Vec<u32>>,
+    pub document_frequency: Vec<u32>,
+}
+
+impl TermMatrix {
+    // One row per term: the term, its document frequency, then its count in
+    // each document, under a header naming the documents.
+    pub fn to_csv(&self) -> anyhow::Result<String> {
+        let mut csv = csv::Writer::from_writer(Vec::new());
+        csv.write_record(["term", "document_frequency"].into_iter().chain(self.documents.iter().map(String::as_str)))?;
+        for (t, term) in self.terms.iter().enumerate() {
+            let counts = self.counts.iter().map(|row| row[t].to_string());
+            csv.write_record([term.clone(), self.document_frequency[t].to_string()].into_iter().chain(counts))?;
+        }
+        Ok(String::from_utf8(csv.into_inner()?)?)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -60,4 +464,153 @@ pub struct OutputJson {
     pub metadata: Metadata,
     pub extracted_sections: Vec<ExtractedSection>,
     pub subsection_analysis: Vec<SubsectionAnalysis>,
-}
\ No newline at end of file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub footnotes: Vec<FootnoteAnalysis>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keyword_index: BTreeMap<String, Vec<KeywordLocation>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub document_rankings: Vec<DocumentRanking>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub document_links: Vec<DocumentLink>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub report_bundle: Vec<BundleEntry>,
+    // Written to its own file next to the output rather than into it.
+    #[serde(skip)]
+    pub term_matrix: Option<TermMatrix>,
+}
+
+impl OutputJson {
+    // Keeps the core fields plus the listed optional ones, in the listed order.
+    // Optional field names apply at every level (top level, metadata, entries).
+    pub fn to_value_with_fields(&self, optional_fields: &[String]) -> serde_json::Result<Value> {
+        let mut value = serde_json::to_value(self)?;
+        retain_fields(&mut value, CORE_OUTPUT_FIELDS, optional_fields);
+        if let Some(metadata) = value.get_mut("metadata") {
+            retain_fields(metadata, CORE_METADATA_FIELDS, optional_fields);
+        }
+        for (key, core) in [("extracted_sections", CORE_SECTION_FIELDS), ("subsection_analysis", CORE_SUBSECTION_FIELDS)] {
+            if let Some(Value::Array(entries)) = value.get_mut(key) {
+                for entry in entries {
+                    retain_fields(entry, core, optional_fields);
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+fn retain_fields(value: &mut Value, core: &[&str], optional_fields: &[String]) {
+    if let Value::Object(map) = value {
+        let mut retained = Map::new();
+        for field in core {
+            if let Some(v) = map.remove(*field) {
+                retained.insert(field.to_string(), v);
+            }
+        }
+        for field in optional_fields {
+            if let Some(v) = map.remove(field) {
+                retained.insert(field.clone(), v);
+            }
+        }
+        *map = retained;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn term_matrix_csv_quotes_document_names() {
+        let matrix = TermMatrix {
+            documents: vec!["Nice, \"the\" Riviera.pdf".to_string(), "guide.pdf".to_string()],
+            terms: vec!["beach".to_string(), "hotel".to_string()],
+            counts: vec![vec![3, 0], vec![1, 2]],
+            document_frequency: vec![2, 1],
+        };
+        let csv = matrix.to_csv().unwrap();
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(reader.headers().unwrap(), vec!["term", "document_frequency", "Nice, \"the\" Riviera.pdf", "guide.pdf"]);
+        let rows: Vec<Vec<String>> = reader.records()
+            .map(|record| record.unwrap().iter().map(String::from).collect())
+            .collect();
+        assert_eq!(rows, [["beach", "2", "3", "1"], ["hotel", "1", "0", "2"]]);
+    }
+
+    #[test]
+    fn output_fields_keep_core_fields_and_the_listed_optional_ones() {
+        let output: OutputJson = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "input_documents": ["guide.pdf"],
+                "persona": "Travel planner",
+                "job_to_be_done": "Plan a trip",
+                "processing_timestamp": "2024-01-01T00:00:00Z",
+                "skipped_pages": [{ "document": "guide.pdf", "page_number": 2, "reason": "unreadable" }],
+            },
+            "extracted_sections": [
+                { "document": "guide.pdf", "section_title": "Beaches", "importance_rank": 1, "page_number": 1, "heading_level": 1 },
+            ],
+            "subsection_analysis": [
+                { "document": "guide.pdf", "refined_text": "A beach trip.", "page_number": 1, "relevance_score": 0.5, "persona_score": 0.2 },
+            ],
+        })).unwrap();
+        let value = output.to_value_with_fields(&["relevance_score".to_string(), "skipped_pages".to_string()]).unwrap();
+
+        let keys = |value: &Value| value.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys(&value), ["metadata", "extracted_sections", "subsection_analysis"]);
+        assert_eq!(keys(&value["metadata"]), ["input_documents", "persona", "job_to_be_done", "processing_timestamp", "skipped_pages"]);
+        assert_eq!(keys(&value["extracted_sections"][0]), ["document", "section_title", "importance_rank", "page_number"]);
+        assert_eq!(keys(&value["subsection_analysis"][0]), ["document", "refined_text", "page_number", "relevance_score"]);
+
+        let core_only = output.to_value_with_fields(&[]).unwrap();
+        assert!(core_only["metadata"].get("skipped_pages").is_none());
+        assert!(core_only["subsection_analysis"][0].get("relevance_score").is_none());
+    }
+
+    fn problems(input: serde_json::Value) -> Vec<String> {
+        let input: InputJson = serde_json::from_value(input).unwrap();
+        input.validate().err().map_or_else(Vec::new, |invalid| invalid.problems)
+    }
+
+    #[test]
+    fn validation_reports_each_problem() {
+        let valid = || serde_json::json!({
+            "challenge_info": { "challenge_id": "test", "test_case_name": "test" },
+            "documents": [{ "filename": "guide.pdf" }, { "filename": "hotels.pdf" }],
+            "persona": { "role": "Travel planner" },
+            "job_to_be_done": { "task": "Plan a trip" },
+        });
+        assert!(problems(valid()).is_empty());
+
+        let mut input = valid();
+        input["documents"] = serde_json::json!([]);
+        assert_eq!(problems(input), ["`documents` is empty; list at least one document"]);
+        let mut input = valid();
+        input["documents"][1]["filename"] = " ".into();
+        assert_eq!(problems(input), ["documents[1] has an empty `filename`"]);
+        let mut input = valid();
+        input["documents"][1]["filename"] = "guide.pdf".into();
+        assert_eq!(problems(input), ["documents[1] repeats the filename \"guide.pdf\""]);
+        let mut input = valid();
+        input["persona"]["role"] = "".into();
+        assert_eq!(problems(input), ["`persona.role` is empty"]);
+        let mut input = valid();
+        input["job_to_be_done"]["task"] = "\n".into();
+        assert_eq!(problems(input), ["`job_to_be_done.task` is empty"]);
+    }
+
+    #[test]
+    fn validation_reports_every_problem_at_once() {
+        let input: InputJson = serde_json::from_value(serde_json::json!({
+            "challenge_info": { "challenge_id": "test", "test_case_name": "test" },
+            "documents": [{ "filename": "" }],
+            "persona": { "role": "" },
+            "job_to_be_done": { "task": "" },
+        })).unwrap();
+        let message = input.validate().unwrap_err().to_string();
+        assert_eq!(message, "3 problem(s) in the input:\n  \
+            - documents[0] has an empty `filename`\n  \
+            - `persona.role` is empty\n  \
+            - `job_to_be_done.task` is empty");
+    }
+}
@@ -1,239 +1,4494 @@
-use crate::models::{ExtractedSection, SubsectionAnalysis, OutputJson, Metadata};
+use crate::config::{Backend, FormFeed, IdfFallback, KeywordOverlap, LongTokenAction, MatrixFormat, OutputFormat, ProcessingOptions, RecencyDecay, RecencyWeighting, ScoreAggregation, ScoringMode, SectionOrdering, TieBreakKey, TitleCase, TitleNormalization};
+use crate::language::Language;
+use crate::layout::{PageLayout, TextRun, TextState};
+use crate::memory_budget::MemoryBudget;
+use crate::models::{BundleEntry, CollectionSummary, Document, FootnoteAnalysis, InputJson, KeywordLocation, KeywordMatch, MatchExplanation, DocumentCoverage, PageConfidence, DocumentLanguage, DocumentStatistics, DocumentLink, DocumentRanking, DocumentStructure, DocumentTitle, EffectiveKeywords, ExtractedSection, SubsectionAnalysis, OutputJson, Metadata, PageCountMismatch, SkippedPage, SourceAnchor, SourceHash, StructureReport, TermMatrix, TitleSource};
+use crate::post_processor::OutputPostProcessor;
+use crate::scoring::{self, Query};
+use crate::structure::{StructRole, StructureMap, TaggedBlock};
 use anyhow::{Context, Result};
-use chrono::Utc;
-use pdf::file::FileOptions;
-use pdf::content::{Content, Op};
-use pdf::object::Resolve;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use log::{info, trace, warn};
+use pdf::error::PdfError;
+use pdf::file::{CachedFile, FileOptions};
+use pdf::content::Content;
+use pdf::object::{Page, PageTree, PagesNode, RcRef, Rect, Resolve};
 use regex::Regex;
-use std::path::Path;
+use std::any::Any;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::AtomicUsize;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-pub struct PdfProcessor;
+/// Extracts the cleaned text of each page of a PDF, keyed by 1-based page
+/// number, without running relevance analysis. Pages without text are omitted.
+///
+/// ```
+/// use std::path::Path;
+///
+/// let pdf = Path::new("collections/adobe_learning/PDFs/Learn Acrobat - Fill and Sign.pdf");
+/// let pages = pdf_analyzer::extract_pages(pdf).unwrap();
+/// for (page_number, text) in &pages {
+///     assert!(*page_number >= 1 && !text.is_empty());
+/// }
+/// assert_eq!(pages.len(), 18);
+/// assert_eq!(pages.last().map(|(page_number, _)| *page_number), Some(18));
+/// ```
+pub fn extract_pages(path: &Path) -> Result<Vec<(usize, String)>> {
+    Ok(PdfProcessor::new(ProcessingOptions::default())?.extract_pdf_text(path, None, None, false)?.page_texts)
+}
+
+// Matches the limit the pdf crate applies when looking pages up by index.
+const MAX_PAGE_TREE_DEPTH: usize = 16;
+// Pages with fewer characters than this get proportionally lower confidence.
+const CONFIDENT_PAGE_CHARS: f64 = 200.0;
+// Confidence drops to zero once this share of characters are U+FFFD.
+const MAX_REPLACEMENT_SHARE: f64 = 0.1;
+// Longer lines are taken for body text when looking for wrapped headings.
+const MAX_WRAPPED_HEADING_LINE: usize = 80;
+// Written next to a collection's output, with a `.json` or `.csv` extension.
+const TERM_MATRIX_STEM: &str = "term_matrix";
+// Written next to a collection's output in place of it by `structure_report`.
+const STRUCTURE_REPORT_FILENAME: &str = "structure_report.json";
+// How many detected headings the structure report lists per document.
+const STRUCTURE_SAMPLE_TITLES: usize = 5;
+// Documents with fewer pages than this have no running headers to detect.
+const MIN_PAGES_FOR_REPEATED_LINES: usize = 3;
+
+// Analyzes one document. `PdfProcessor::process_document` unless a test swaps it.
+type Extractor = dyn Fn(&PdfProcessor, &Document, &Path, &Query) -> Result<Option<DocumentAnalysis>> + Send + Sync;
+
+pub struct PdfProcessor {
+    options: ProcessingOptions,
+    post_processors: Vec<Box<dyn OutputPostProcessor>>,
+    memory_budget: Option<MemoryBudget>,
+    heading_patterns: HashMap<Language, Vec<Regex>>,
+    extractor: Box<Extractor>,
+}
+
+struct CollectedDocument {
+    filename: String,
+    language: Language,
+    term_counts: scoring::TermVector,
+}
+
+// What processing one document produced, before merging into the collection.
+struct DocumentOutcome {
+    source_hash: Option<SourceHash>,
+    // `None` when no backend produced text or the document panicked.
+    analysis: Option<DocumentAnalysis>,
+}
+
+struct Heading {
+    title: String,
+    level: u8,
+}
+
+struct ExtractedText {
+    page_texts: Vec<(usize, String)>,
+    layouts: BTreeMap<usize, PageLayout>,
+    visit: PageVisit,
+}
+
+// One page as `extract_page` left it.
+enum ExtractedPage {
+    Text(String, PageLayout),
+    // No text once cleaned, as on blank or image-only pages.
+    Empty,
+    // The page couldn't be read: its page tree entry didn't resolve or its
+    // content stream didn't parse. Holds the error.
+    Unreadable(String),
+}
+
+// What a walk over a PDF's pages found besides the page text itself.
+struct PageVisit {
+    skipped_pages: Vec<(usize, String)>,
+    // The trailer's /Count and the number of pages actually in the tree, when they differ.
+    page_count_mismatch: Option<(u32, u32)>,
+}
+
+// Results for one document, accumulated page by page so that streaming
+// extraction can drop each page's text once it has been analyzed.
+struct DocumentAnalysis {
+    language: Option<Language>,
+    sections: Vec<ExtractedSection>,
+    subsections: Vec<SubsectionAnalysis>,
+    footnotes: Vec<FootnoteAnalysis>,
+    skipped_pages: Vec<SkippedPage>,
+    page_confidence: Vec<PageConfidence>,
+    page_count_mismatch: Option<PageCountMismatch>,
+    term_counts: scoring::TermVector,
+    // How many pages contain each keyword, for TF-IDF scoring.
+    keyword_pages: HashMap<String, usize>,
+    pages_extracted: usize,
+    pages_with_results: usize,
+    total_chars: usize,
+    total_words: usize,
+    // Only counted for the structure report.
+    paragraphs: usize,
+    list_items: usize,
+    tagged_pages: usize,
+}
+
+impl DocumentAnalysis {
+    fn new(language: Option<Language>) -> Self {
+        Self {
+            language,
+            sections: Vec::new(),
+            subsections: Vec::new(),
+            footnotes: Vec::new(),
+            skipped_pages: Vec::new(),
+            page_confidence: Vec::new(),
+            page_count_mismatch: None,
+            term_counts: scoring::TermVector::new(),
+            keyword_pages: HashMap::new(),
+            pages_extracted: 0,
+            pages_with_results: 0,
+            total_chars: 0,
+            total_words: 0,
+            paragraphs: 0,
+            list_items: 0,
+            tagged_pages: 0,
+        }
+    }
+
+    fn record_visit(&mut self, doc: &Document, visit: PageVisit) -> Result<()> {
+        for (page_index, reason) in visit.skipped_pages {
+            self.skipped_pages.push(SkippedPage {
+                document: doc.filename.clone(),
+                page_number: PdfProcessor::printed_page_number(doc, page_index)? as u32,
+                reason,
+            });
+        }
+        self.skipped_pages.sort_by_key(|page| page.page_number);
+        self.page_count_mismatch = visit.page_count_mismatch.map(|(declared, actual)| PageCountMismatch {
+            document: doc.filename.clone(),
+            declared,
+            actual,
+        });
+        Ok(())
+    }
+
+    fn statistics(&self, doc_name: &str) -> DocumentStatistics {
+        DocumentStatistics {
+            document: doc_name.to_string(),
+            pages: self.pages_extracted as u32,
+            words: self.total_words as u64,
+            characters: self.total_chars as u64,
+        }
+    }
+
+    fn coverage(&self, doc_name: &str) -> DocumentCoverage {
+        let result_chars: usize = self.subsections.iter().map(|s| s.refined_text.chars().count()).sum();
+        let ratio = |part: usize, whole: usize| if whole == 0 { 0.0 } else { part as f64 / whole as f64 };
+        DocumentCoverage {
+            document: doc_name.to_string(),
+            pages_extracted: self.pages_extracted as u32,
+            pages_with_results: self.pages_with_results as u32,
+            page_coverage: ratio(self.pages_with_results, self.pages_extracted),
+            text_coverage: ratio(result_chars, self.total_chars),
+        }
+    }
+}
 
 impl PdfProcessor {
-    pub fn process_pdf_collection(input_path: &str, output_path: &str) -> Result<()> {
+    // Fails when a configured heading pattern isn't a valid regex.
+    pub fn new(options: ProcessingOptions) -> Result<Self> {
+        let memory_budget = options.memory_budget_mb.map(|mb| MemoryBudget::new(mb * 1024 * 1024));
+        let heading_patterns = Self::compile_heading_patterns(options.heading_patterns.as_deref())?;
+        Ok(Self {
+            options,
+            post_processors: Vec::new(),
+            memory_budget,
+            heading_patterns,
+            extractor: Box::new(Self::process_document),
+        })
+    }
+
+    #[cfg(test)]
+    fn with_extractor(
+        mut self,
+        extractor: impl Fn(&PdfProcessor, &Document, &Path, &Query) -> Result<Option<DocumentAnalysis>> + Send + Sync + 'static,
+    ) -> Self {
+        self.extractor = Box::new(extractor);
+        self
+    }
+
+    // Configured patterns apply to every language.
+    fn compile_heading_patterns(configured: Option<&[String]>) -> Result<HashMap<Language, Vec<Regex>>> {
+        let configured: Option<Vec<Regex>> = configured
+            .map(|patterns| patterns.iter()
+                .map(|pattern| Regex::new(pattern).with_context(|| format!("Invalid heading pattern {:?}", pattern)))
+                .collect::<Result<_>>())
+            .transpose()?;
+        Ok([Language::English, Language::German, Language::French].into_iter()
+            .map(|language| {
+                let patterns = configured.clone().unwrap_or_else(|| language.heading_patterns().iter()
+                    .map(|pattern| Regex::new(pattern).expect("built-in heading patterns are valid"))
+                    .collect());
+                (language, patterns)
+            })
+            .collect())
+    }
+
+    pub fn with_post_processor(mut self, post_processor: impl OutputPostProcessor + 'static) -> Self {
+        self.post_processors.push(Box::new(post_processor));
+        self
+    }
+
+    pub fn process_pdf_collection(&self, input_path: &str, output_path: &str) -> Result<CollectionSummary> {
         let input_json = std::fs::read_to_string(input_path)
             .with_context(|| format!("Failed to read input JSON at {}", input_path))?;
-        let input: crate::models::InputJson = serde_json::from_str(&input_json)
+        let input: InputJson = serde_json::from_str(&input_json)
             .with_context(|| format!("Failed to parse input JSON at {}", input_path))?;
+        input.validate().with_context(|| format!("Invalid input JSON at {}", input_path))?;
+        let (pdf_dir, tried) = self.pdf_dir(Path::new(input_path));
+        Self::check_documents_exist(&input, &pdf_dir, &tried)?;
+        if self.options.structure_report {
+            let report = self.structure_report(&input, &pdf_dir)?;
+            let path = Path::new(output_path).with_file_name(STRUCTURE_REPORT_FILENAME);
+            self.write_files(input_path, vec![(path, serde_json::to_string_pretty(&report)?)])?;
+            return Ok(CollectionSummary { documents: input.documents.len(), sections: 0, subsections: 0 });
+        }
+        let output = self.analyze(&input, &pdf_dir)?;
+        let summary = CollectionSummary {
+            documents: input.documents.len(),
+            sections: output.extracted_sections.len(),
+            subsections: output.subsection_analysis.len(),
+        };
+        let markdown_path = Path::new(output_path).with_extension("md");
+        // Feed links are relative to the collection directory.
+        let link_dir = pdf_dir.strip_prefix(Path::new(input_path).parent().unwrap_or(Path::new("")))
+            .map_or_else(|_| self.options.pdf_subdir.clone(), |dir| dir.to_string_lossy().to_string());
+        let (json, output_path) = match (self.options.output_format, &self.options.output_fields) {
+            (OutputFormat::Atom, _) => (
+                crate::atom_output::render(&output, &Self::collection_name(input_path), &link_dir),
+                Path::new(output_path).with_extension("atom"),
+            ),
+            (OutputFormat::Csv, _) => (
+                crate::csv_output::render_sections(&output)?,
+                Path::new(output_path).with_file_name(crate::csv_output::SECTIONS_CSV_FILENAME),
+            ),
+            (OutputFormat::Markdown, _) => (crate::markdown_output::render(&output), markdown_path.clone()),
+            (OutputFormat::Json, Some(fields)) => (serde_json::to_string_pretty(&output.to_value_with_fields(fields)?)?, PathBuf::from(output_path)),
+            (OutputFormat::Json, None) => (serde_json::to_string_pretty(&output)?, PathBuf::from(output_path)),
+        };
+        let mut files = vec![(output_path, json)];
+        if self.options.markdown_report && self.options.output_format != OutputFormat::Markdown {
+            files.push((markdown_path, crate::markdown_output::render(&output)));
+        }
+        if let Some(matrix) = &output.term_matrix {
+            files.push(self.term_matrix_file(matrix, &files[0].0)?);
+        }
+
+        #[cfg(feature = "sqlite")]
+        if let Some(db_path) = &self.options.sqlite_path {
+            crate::sqlite_output::write_collection(db_path, &Self::collection_name(input_path), &output)?;
+        }
+
+        self.write_files(input_path, files)?;
+        Ok(summary)
+    }
 
+    // Every artifact goes to `s3_output` when it's set, named as it would be
+    // in the collection directory, and to the local paths otherwise.
+    fn write_files(
+        &self,
+        #[cfg_attr(not(feature = "s3"), allow(unused_variables))] input_path: &str,
+        files: Vec<(PathBuf, String)>,
+    ) -> Result<()> {
+        #[cfg(feature = "s3")]
+        if let Some(uri) = &self.options.s3_output {
+            for (output_path, contents) in &files {
+                let filename = output_path.file_name().unwrap_or_default().to_string_lossy();
+                crate::s3_output::write_collection(uri, &Self::collection_name(input_path), &filename, contents)?;
+            }
+            return Ok(());
+        }
+
+        for (output_path, contents) in files {
+            crate::utils::write_atomic(&output_path, contents)
+                .with_context(|| format!("Failed to write output to {}", output_path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Extracts and ranks a collection without writing anything, returning
+    /// what `process_pdf_collection` would serialize (post-processors included).
+    /// The input's documents are read from `pdf_dir`.
+    ///
+    /// ```
+    /// use pdf_analyzer::config::ProcessingOptions;
+    /// use pdf_analyzer::models::InputJson;
+    /// use pdf_analyzer::pdf_processor::PdfProcessor;
+    /// use std::path::Path;
+    ///
+    /// let dir = Path::new("collections/travel_planning");
+    /// let input: InputJson = serde_json::from_str(&std::fs::read_to_string(dir.join("challenge1b_input.json"))?)?;
+    /// let output = PdfProcessor::new(ProcessingOptions::default())?.analyze(&input, &dir.join("PDFs"))?;
+    /// assert_eq!(output.metadata.input_documents.len(), input.documents.len());
+    /// assert!(!output.extracted_sections.is_empty());
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn analyze(&self, input: &InputJson, pdf_dir: &Path) -> Result<OutputJson> {
+        Self::check_documents_exist(input, pdf_dir, &[])?;
         let mut extracted_sections = Vec::new();
         let mut subsection_analysis = Vec::new();
+        let mut footnotes = Vec::new();
+        let mut skipped_pages = Vec::new();
+        let mut page_count_mismatches = Vec::new();
+        let mut source_hashes = Vec::new();
+        let mut document_texts = Vec::new();
+        let mut document_coverage = Vec::new();
+        let mut page_confidence = Vec::new();
+        let mut document_statistics = Vec::new();
+        let started = Instant::now();
 
-        let persona_keywords = Self::extract_keywords_from_text(&input.persona.role);
-        let task_keywords = Self::extract_keywords_from_text(&input.job_to_be_done.task);
+        let mut query = self.query(input, pdf_dir);
+        if let Some(weighting) = &self.options.recency_weighting {
+            let dates = input.documents.iter()
+                .map(|doc| Ok((doc.filename.clone(), Self::document_date(doc, &pdf_dir.join(&doc.filename), self.pdf_password(doc))?)))
+                .collect::<Result<Vec<_>>>()?;
+            query.recency_weights = Self::recency_weights(weighting, &dates);
+        }
 
-        for doc in &input.documents {
-            let pdf_path = Path::new(input_path).parent().unwrap().join("pdfs").join(&doc.filename);
-            if !pdf_path.exists() {
-                return Err(anyhow::anyhow!("PDF not found at: {}", pdf_path.display()));
-            }
-
-            match Self::extract_pdf_text(&pdf_path) {
-                Ok((_full_text, page_texts)) => {
-                    for (page_num, page_text) in &page_texts {
-                        let headings = Self::extract_headings_from_page(page_text);
-                        for heading in headings {
-                            extracted_sections.push(ExtractedSection {
-                                document: doc.filename.clone(),
-                                section_title: heading,
-                                importance_rank: 0, // Placeholder, will be updated later
-                                page_number: *page_num as u32,
-                            });
-                        }
-                    }
+        let document_titles = if self.options.document_titles {
+            input.documents.iter().map(|doc| Self::document_title(doc, &pdf_dir.join(&doc.filename), self.pdf_password(doc))).collect()
+        } else {
+            Vec::new()
+        };
 
-                    let relevant_content = Self::find_relevant_content(
-                        &doc.filename,
-                        &page_texts,
-                        &persona_keywords,
-                        &task_keywords,
-                    );
-                    subsection_analysis.extend(relevant_content);
-                }
-                Err(e) => {
-                    eprintln!("Error processing {}: {}", pdf_path.display(), e);
-                    // Try OCR as fallback
-                    match Self::extract_with_ocr(&pdf_path) {
-                        Ok(ocr_text) => {
-                            println!("[INFO] Using OCR-extracted text for {}", pdf_path.display());
-                            let page_texts = vec![(1, ocr_text.clone())]; // Treat OCR output as a single page
-                            subsection_analysis.extend(Self::find_relevant_content(
-                                &doc.filename,
-                                &page_texts,
-                                &persona_keywords,
-                                &task_keywords
-                            ));
-                        }
-                        Err(ocr_err) => {
-                            eprintln!("OCR also failed for {}: {}", pdf_path.display(), ocr_err);
-                        }
-                    }
+        let mut keyword_pages: HashMap<String, usize> = HashMap::new();
+        let mut pages_extracted = 0;
+        let mut documents_extracted = 0;
+        let outcomes = self.process_documents(&input.documents, pdf_dir, &query);
+        for (doc, outcome) in input.documents.iter().zip(outcomes) {
+            let outcome = outcome?;
+            source_hashes.extend(outcome.source_hash);
+            // Documents without text are listed with zero counts.
+            if self.options.report_statistics {
+                let empty = DocumentAnalysis::new(None);
+                document_statistics.push(outcome.analysis.as_ref().unwrap_or(&empty).statistics(&doc.filename));
+            }
+            let Some(analysis) = outcome.analysis else {
+                continue;
+            };
+            if self.options.report_coverage {
+                document_coverage.push(analysis.coverage(&doc.filename));
+            }
+            if let Some(language) = analysis.language {
+                document_texts.push(CollectedDocument {
+                    filename: doc.filename.clone(),
+                    language,
+                    term_counts: analysis.term_counts,
+                });
+            }
+            for (keyword, pages) in analysis.keyword_pages {
+                *keyword_pages.entry(keyword).or_default() += pages;
+            }
+            pages_extracted += analysis.pages_extracted;
+            if analysis.pages_extracted > 0 {
+                documents_extracted += 1;
+            }
+            extracted_sections.extend(analysis.sections);
+            subsection_analysis.extend(analysis.subsections);
+            footnotes.extend(analysis.footnotes);
+            skipped_pages.extend(analysis.skipped_pages);
+            page_confidence.extend(analysis.page_confidence);
+            page_count_mismatches.extend(analysis.page_count_mismatch);
+        }
+
+        // Scores given during extraction didn't have the IDFs yet.
+        if self.options.scoring_mode == ScoringMode::TfIdf {
+            query.keyword_idf = if documents_extracted >= self.options.min_idf_documents {
+                scoring::inverse_document_frequencies(&keyword_pages, pages_extracted)
+            } else {
+                info!(
+                    "Only {} document(s) with text, below min_idf_documents ({}); weighting keywords by {:?}",
+                    documents_extracted, self.options.min_idf_documents, self.options.idf_fallback
+                );
+                match self.options.idf_fallback {
+                    IdfFallback::TermFrequency => keyword_pages.keys().map(|keyword| (keyword.clone(), 1.0)).collect(),
+                    IdfFallback::Smoothed => scoring::smoothed_inverse_document_frequencies(&keyword_pages, pages_extracted),
                 }
+            };
+            for note in &mut footnotes {
+                note.score = self.paragraph_score(&note.document, &note.text, &query);
+            }
+            for subsection in subsection_analysis.iter_mut().filter(|s| s.explanation.is_some()) {
+                subsection.explanation = Some(self.explain_match(&subsection.document, &subsection.refined_text, &query));
+            }
+        }
+
+        // Scores are final from here on, so the threshold matches the ranking.
+        subsection_analysis.retain_mut(|subsection| {
+            let score = self.paragraph_score(&subsection.document, &subsection.refined_text, &query);
+            subsection.relevance_score = score as f32;
+            !self.options.min_relevance.is_some_and(|min| score < min)
+        });
+
+        self.rank_sections(&mut extracted_sections, &subsection_analysis, &query);
+        // Levels are only reported where they shaped the result.
+        if self.options.heading_level_weights.is_empty() && self.options.max_heading_level.is_none() {
+            for section in &mut extracted_sections {
+                section.heading_level = None;
             }
         }
+        if self.options.scoring_mode == ScoringMode::Phrase {
+            self.rank_subsections(&mut subsection_analysis, &query);
+        }
+        if let Some(top) = self.options.top_subsections {
+            self.keep_top_subsections(&mut subsection_analysis, top, &query);
+        }
 
-        Self::rank_sections(&mut extracted_sections, &subsection_analysis, &persona_keywords, &task_keywords);
+        footnotes.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        let query_language = Language::detect(&format!("{} {}", input.persona.role, input.job_to_be_done.task));
+        let (document_languages, language_note) = Self::language_report(&document_texts, query_language);
+
+        let document_links = if self.options.document_links {
+            self.link_documents(&document_texts)
+        } else {
+            Vec::new()
+        };
+        let keyword_index = if self.options.keyword_index {
+            self.keyword_index(&subsection_analysis, &query)
+        } else {
+            BTreeMap::new()
+        };
+        let document_rankings = if self.options.document_rankings {
+            self.rank_documents(&input.documents, &subsection_analysis, &query)
+        } else {
+            Vec::new()
+        };
+        let report_bundle = if self.options.report_bundle {
+            self.bundle_report(&extracted_sections, &subsection_analysis, &query)
+        } else {
+            Vec::new()
+        };
+
+        // Last, so scores and everything derived from them use the full text.
+        if self.options.summarize.is_some() || self.options.summarize_max_chars.is_some() {
+            for subsection in &mut subsection_analysis {
+                subsection.refined_text = Self::summarize(
+                    &subsection.refined_text, self.options.summarize, self.options.summarize_max_chars, &query,
+                );
+            }
+        }
 
-        let output = OutputJson {
+        let mut output = OutputJson {
             metadata: Metadata {
                 input_documents: input.documents.iter().map(|d| d.filename.clone()).collect(),
                 persona: input.persona.role.clone(),
                 job_to_be_done: input.job_to_be_done.task.clone(),
-                processing_timestamp: Utc::now().to_rfc3339(),
+                processing_timestamp: crate::utils::format_timestamp(
+                    Utc::now(),
+                    self.options.timestamp_format.as_deref(),
+                    self.options.timezone.as_deref(),
+                )?,
+                skipped_pages,
+                page_count_mismatches,
+                source_hashes,
+                document_coverage,
+                page_confidence,
+                document_languages,
+                document_titles,
+                language_note,
+                effective_keywords: self.options.explain.then(|| EffectiveKeywords {
+                    persona: query.persona_keywords.clone(),
+                    task: query.task_keywords.clone(),
+                }),
+                total_pages: self.options.report_statistics.then(|| document_statistics.iter().map(|stats| stats.pages).sum()),
+                total_words: self.options.report_statistics.then(|| document_statistics.iter().map(|stats| stats.words).sum()),
+                total_characters: self.options.report_statistics.then(|| document_statistics.iter().map(|stats| stats.characters).sum()),
+                document_statistics,
+                processing_duration_ms: self.options.report_statistics.then(|| started.elapsed().as_millis() as u64),
             },
             extracted_sections,
             subsection_analysis,
+            footnotes,
+            keyword_index,
+            document_rankings,
+            document_links,
+            report_bundle,
+            term_matrix: self.options.export_matrix.then(|| Self::term_matrix(&document_texts)),
         };
-
-        std::fs::write(output_path, serde_json::to_string_pretty(&output)?)
-            .with_context(|| format!("Failed to write output to {}", output_path))?;
-        
-        Ok(())
+        for post_processor in &self.post_processors {
+            post_processor.process(&mut output, input);
+        }
+        Ok(output)
     }
 
-    fn extract_pdf_text(path: &Path) -> Result<(String, Vec<(usize, String)>)> {
-        let file = FileOptions::cached().open(path)?;
-        let mut full_text = String::new();
-        let mut page_texts = Vec::new();
-        
-        for page_num in 0..file.num_pages() {
-            let page = file.get_page(page_num)?;
-            let mut page_text = String::new();
-            
-            if let Some(content) = &page.contents {
-                Self::extract_text_from_content(&file, content, &mut page_text)?;
+    // Documents that yield no text are listed with zero counts.
+    fn structure_report(&self, input: &InputJson, pdf_dir: &Path) -> Result<StructureReport> {
+        let outcomes = self.process_documents(&input.documents, pdf_dir, &self.query(input, pdf_dir));
+        let mut documents = Vec::new();
+        for (doc, outcome) in input.documents.iter().zip(outcomes) {
+            let analysis = outcome?.analysis.unwrap_or_else(|| DocumentAnalysis::new(None));
+            let mut headings_by_level = BTreeMap::new();
+            for level in analysis.sections.iter().filter_map(|section| section.heading_level) {
+                *headings_by_level.entry(level).or_default() += 1;
             }
-            
-            let cleaned_text = Self::clean_extracted_text(&page_text);
-            if !cleaned_text.is_empty() {
-                full_text.push_str(&cleaned_text);
-                full_text.push_str("\n\n");
-                page_texts.push((page_num as usize + 1, cleaned_text));
+            documents.push(DocumentStructure {
+                document: doc.filename.clone(),
+                pages_extracted: analysis.pages_extracted,
+                characters: analysis.total_chars,
+                tagged_pages: analysis.tagged_pages,
+                headings: analysis.sections.len(),
+                headings_by_level,
+                sample_titles: analysis.sections.iter().take(STRUCTURE_SAMPLE_TITLES).map(|s| s.section_title.clone()).collect(),
+                paragraphs: analysis.paragraphs,
+                list_items: analysis.list_items,
+            });
+        }
+        Ok(StructureReport { documents })
+    }
+
+    // Where a collection's documents are read from: `shared_pdf_dir`, else the
+    // `pdf_subdir` next to the input, else the input's own directory when that
+    // subdirectory doesn't exist. Also returns the directories passed over, for
+    // the error when a document can't be found.
+    fn pdf_dir(&self, input_path: &Path) -> (PathBuf, Vec<PathBuf>) {
+        if let Some(dir) = &self.options.shared_pdf_dir {
+            return (dir.clone(), Vec::new());
+        }
+        let collection_dir = input_path.parent().unwrap_or(Path::new(""));
+        let subdir = collection_dir.join(&self.options.pdf_subdir);
+        if subdir.is_dir() {
+            (subdir, Vec::new())
+        } else {
+            (collection_dir.to_path_buf(), vec![subdir])
+        }
+    }
+
+    fn check_documents_exist(input: &InputJson, pdf_dir: &Path, tried: &[PathBuf]) -> Result<()> {
+        for doc in &input.documents {
+            let pdf_path = pdf_dir.join(&doc.filename);
+            if !pdf_path.exists() {
+                let attempts: Vec<String> = tried.iter().map(|dir| dir.join(&doc.filename))
+                    .chain(std::iter::once(pdf_path))
+                    .map(|path| path.display().to_string())
+                    .collect();
+                return Err(anyhow::anyhow!("PDF {:?} not found; looked at: {}", doc.filename, attempts.join(", ")));
             }
         }
-        
-        if full_text.trim().is_empty() {
-            return Err(anyhow::anyhow!("No text extracted from PDF - will try OCR"));
+        Ok(())
+    }
+
+    // Recency weights and IDFs are filled in by the caller where they apply.
+    fn query(&self, input: &InputJson, pdf_dir: &Path) -> Query {
+        let language = Language::detect(&format!("{} {}", input.persona.role, input.job_to_be_done.task));
+        Query {
+            persona_keywords: self.extract_keywords_from_text(&input.persona.role, language, self.options.max_keywords),
+            task_keywords: self.extract_keywords_from_text(&input.job_to_be_done.task, language, self.options.max_keywords),
+            phrase_keywords: if self.options.phrase_keywords {
+                let mut phrases = self.extract_phrases_from_text(&input.persona.role, language);
+                for phrase in self.extract_phrases_from_text(&input.job_to_be_done.task, language) {
+                    if !phrases.contains(&phrase) {
+                        phrases.push(phrase);
+                    }
+                }
+                phrases
+            } else {
+                Vec::new()
+            },
+            phrase_tokens: scoring::tokenize(&self.normalize_text(&format!("{} {}", input.persona.role, input.job_to_be_done.task)), self.options.case_folding),
+            case_folding: self.options.case_folding,
+            stemmer: self.options.stemming.then(|| language.stemmer()),
+            title_keywords: if self.options.title_term_boost != 0.0 {
+                input.documents.iter()
+                    .map(|doc| {
+                        let title = Self::document_title(doc, &pdf_dir.join(&doc.filename), self.pdf_password(doc));
+                        (doc.filename.clone(), self.extract_keywords_from_text(&title.title, language, None))
+                    })
+                    .collect()
+            } else {
+                HashMap::new()
+            },
+            recency_weights: HashMap::new(),
+            keyword_idf: HashMap::new(),
         }
-        
-        Ok((full_text, page_texts))
     }
 
-    fn clean_extracted_text(raw_text: &str) -> String {
-        let cleaned = raw_text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ");
-        let re = Regex::new(r"\s+").unwrap();
-        re.replace_all(&cleaned, " ").to_string()
+    // The collection directory's name, used to key non-filesystem outputs and
+    // to identify Atom feeds.
+    fn collection_name(input_path: &str) -> String {
+        Path::new(input_path).parent()
+            .and_then(|dir| dir.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
     }
 
-    fn extract_text_from_content(resolver: &impl Resolve, content: &Content, text: &mut String) -> Result<()> {
-        for op in content.operations(resolver)? {
-            match op {
-                Op::TextDraw { text: t } => {
-                    text.push_str(&t.to_string_lossy());
-                }
-                Op::TextDrawAdjusted { array } => {
-                    for item in array {
-                        if let pdf::content::TextDrawAdjusted::Text(text_str) = item {
-                            text.push_str(&text_str.to_string_lossy());
+    // Spreads the documents over worker threads. Results come back in input
+    // order, so the output doesn't depend on scheduling.
+    fn process_documents(&self, documents: &[Document], pdf_dir: &Path, query: &Query) -> Vec<Result<DocumentOutcome>> {
+        let workers = self.options.parallel_documents
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+            .clamp(1, documents.len().max(1));
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<Option<Result<DocumentOutcome>>> = documents.iter().map(|_| None).collect();
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let Some(doc) = documents.get(i) else {
+                        break;
+                    };
+                    done.push((i, self.process_one(doc, &pdf_dir.join(&doc.filename), query)));
+                }
+                done
+            })).collect();
+            for handle in handles {
+                match handle.join() {
+                    Ok(done) => {
+                        for (i, result) in done {
+                            results[i] = Some(result);
                         }
                     }
+                    Err(panic) => std::panic::resume_unwind(panic),
                 }
-                Op::TextNewline => {
-                    text.push('\n');
+            }
+        });
+        results.into_iter().map(|result| result.expect("every document was claimed by a worker")).collect()
+    }
+
+    fn process_one(&self, doc: &Document, pdf_path: &Path, query: &Query) -> Result<DocumentOutcome> {
+        let source_hash = if self.options.record_source_hashes {
+            Some(SourceHash {
+                document: doc.filename.clone(),
+                sha256: crate::utils::sha256_file(pdf_path)?,
+            })
+        } else {
+            None
+        };
+
+        // The PDF's size stands in for the text its extraction will hold.
+        let _reservation = match &self.memory_budget {
+            Some(budget) => Some(budget.reserve(std::fs::metadata(pdf_path)?.len() as usize)),
+            None => None,
+        };
+        let analysis = if self.options.catch_panics {
+            match std::panic::catch_unwind(AssertUnwindSafe(|| (self.extractor)(self, doc, pdf_path, query))) {
+                Ok(processed) => processed?,
+                Err(panic) => {
+                    warn!("Processing {} panicked ({}), skipping the document", pdf_path.display(), Self::panic_message(&*panic));
+                    None
                 }
-                _ => {}
             }
+        } else {
+            (self.extractor)(self, doc, pdf_path, query)?
+        };
+        Ok(DocumentOutcome { source_hash, analysis })
+    }
+
+    fn panic_message(panic: &(dyn Any + Send)) -> &str {
+        match panic.downcast_ref::<&str>() {
+            Some(message) => message,
+            None => panic.downcast_ref::<String>().map_or("no message", String::as_str),
         }
-        Ok(())
     }
 
-    fn extract_with_ocr(path: &Path) -> Result<String> {
-        let output = Command::new("pdftotext")
-            .arg("-layout")
-            .arg(path)
-            .arg("-")
-            .output()
-            .with_context(|| "Failed to execute pdftotext. Is poppler-utils installed?")?;
-        
-        if !output.status.success() {
-            return Err(anyhow::anyhow!("OCR failed: {}", String::from_utf8_lossy(&output.stderr)));
+    // Tries each backend in `backend_order` until one yields text. Returns `None`
+    // when none of them did. Text and Markdown documents are read directly instead.
+    fn process_document(&self, doc: &Document, pdf_path: &Path, query: &Query) -> Result<Option<DocumentAnalysis>> {
+        if Self::is_text_document(pdf_path) {
+            let analysis = self.analyze_text_file(doc, pdf_path, query)?;
+            if analysis.is_some() {
+                info!("Read {} as text", pdf_path.display());
+            } else {
+                warn!("No text found in {}", pdf_path.display());
+            }
+            return Ok(analysis);
         }
-        
-        String::from_utf8(output.stdout).with_context(|| "OCR output not valid UTF-8")
+        // Documents known to be scanned skip the native backend entirely.
+        let skip_native = doc.force_ocr || self.options.force_ocr;
+        if skip_native {
+            info!("OCR forced for {}", pdf_path.display());
+        }
+        for &backend in &self.options.backend_order {
+            let analysis = match backend {
+                Backend::Native if skip_native => continue,
+                Backend::Native => {
+                    let pages = Self::page_selection(self.options.pages.as_deref(), doc.pages.as_deref());
+                    if self.options.streaming {
+                        self.analyze_streaming(doc, pdf_path, pages.as_ref(), query)?
+                    } else {
+                        self.analyze_batch(doc, pdf_path, pages.as_ref(), query)?
+                    }
+                }
+                Backend::Poppler => self.analyze_poppler(doc, pdf_path, query)?,
+            };
+            if analysis.is_some() {
+                info!("Extracted {} with the {:?} backend", pdf_path.display(), backend);
+                return Ok(analysis);
+            }
+        }
+        warn!("No extraction backend produced text for {}", pdf_path.display());
+        Ok(None)
     }
 
-    fn extract_keywords_from_text(text: &str) -> Vec<String> {
-        text.to_lowercase()
-            .split_whitespace()
-            .map(|s| s.trim_matches(|c: char| !c.is_alphanumeric()).to_string())
-            .filter(|s| !s.is_empty() && s.len() > 2)
-            .collect()
+    fn is_text_document(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| ["txt", "md", "markdown"].iter().any(|known| extension.eq_ignore_ascii_case(known)))
     }
 
-    fn extract_headings_from_page(page_text: &str) -> Vec<String> {
-        let heading_patterns = [
-            r"(?m)^([A-Z][A-Za-z\s]{3,}):?$",
-            r"(?m)^(\d+\.?\s+[A-Z][A-Za-z\s]+):?$",
-            r"(?m)^(Chapter\s+\d+[^.]*):?$",
-            r"(?m)^([A-Z\s]{4,}):?$",
-        ];
-        let mut headings = Vec::new();
-        for pattern in &heading_patterns {
-            if let Ok(re) = Regex::new(pattern) {
-                for cap in re.captures_iter(page_text) {
-                    if let Some(heading_match) = cap.get(1) {
-                        headings.push(heading_match.as_str().trim().to_string());
-                    }
+    // Splits the file into pages at form feeds, or every `text_page_lines` lines
+    // when it has none, and analyzes them like extracted PDF pages. Markdown
+    // heading markers are dropped so "## Budget Tips" is found as a heading.
+    fn analyze_text_file(&self, doc: &Document, path: &Path, query: &Query) -> Result<Option<DocumentAnalysis>> {
+        let raw = String::from_utf8_lossy(&std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?).into_owned();
+        let markdown = !path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("txt"));
+        let raw = if markdown {
+            static MARKDOWN_HEADING: OnceLock<Regex> = OnceLock::new();
+            let marker = MARKDOWN_HEADING.get_or_init(|| Regex::new(r"(?m)^[ \t]{0,3}#{1,6}[ \t]+(.*?)[ \t#]*$").unwrap());
+            marker.replace_all(&raw, "$1").into_owned()
+        } else {
+            raw
+        };
+        let chunks: Vec<String> = if raw.contains('\x0c') {
+            raw.split('\x0c').map(str::to_string).collect()
+        } else {
+            let lines: Vec<&str> = raw.lines().collect();
+            lines.chunks(self.options.text_page_lines.max(1)).map(|chunk| chunk.join("\n")).collect()
+        };
+        let preserve_whitespace = self.options.preserve_whitespace || doc.preserve_whitespace;
+        let selection = Self::page_selection(self.options.pages.as_deref(), doc.pages.as_deref());
+        let mut pages: Vec<(usize, String)> = chunks.iter().enumerate()
+            .map(|(i, chunk)| (i + 1, chunk))
+            .filter(|(page_num, _)| match &selection {
+                Some(pages) => pages.contains(&(*page_num as u32)),
+                None => true,
+            })
+            .map(|(page_num, chunk)| (page_num, self.clean_extracted_text(chunk, preserve_whitespace)))
+            .filter(|(_, text)| !text.is_empty() || self.options.keep_blank_pages)
+            .collect();
+        if pages.iter().all(|(_, text)| text.is_empty()) {
+            return Ok(None);
+        }
+        if let Some(threshold) = self.options.repeated_line_threshold {
+            Self::strip_repeated_lines(&mut pages, &mut BTreeMap::new(), threshold);
+        }
+        let text = pages.iter().map(|(_, text)| text.as_str()).collect::<Vec<_>>().join(" ");
+        let mut analysis = DocumentAnalysis::new(Some(self.options.language.unwrap_or_else(|| Language::detect(&text))));
+        for (page_num, page_text) in &pages {
+            self.analyze_page(&mut analysis, doc, *page_num, page_text, None, query)?;
+        }
+        Ok(Some(analysis))
+    }
+
+    // Treats the whole `pdftotext` output as a single page. When it has no text,
+    // as with scanned documents, each page is OCRed with tesseract instead.
+    fn analyze_poppler(&self, doc: &Document, pdf_path: &Path, query: &Query) -> Result<Option<DocumentAnalysis>> {
+        let text = match self.extract_with_pdftotext(pdf_path, self.pdf_password(doc)) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("{:?} backend failed for {}: {}", Backend::Poppler, pdf_path.display(), e);
+                return Ok(None);
+            }
+        };
+        let mut pages = vec![(1, text)];
+        if pages[0].1.trim().is_empty() && self.options.tesseract_fallback {
+            let preserve_whitespace = self.options.preserve_whitespace || doc.preserve_whitespace;
+            match self.extract_with_tesseract(pdf_path, self.pdf_password(doc), preserve_whitespace) {
+                Ok(ocr_pages) if !ocr_pages.is_empty() => {
+                    info!("pdftotext found no text in {}, OCRed {} page(s) with tesseract", pdf_path.display(), ocr_pages.len());
+                    pages = ocr_pages;
                 }
+                Ok(_) => warn!("Tesseract found no text in {}", pdf_path.display()),
+                Err(e) => warn!("Tesseract OCR failed for {}: {}", pdf_path.display(), e),
             }
         }
-        headings
+        if let Some(threshold) = self.options.repeated_line_threshold {
+            Self::strip_repeated_lines(&mut pages, &mut BTreeMap::new(), threshold);
+        }
+        let text = pages.iter().map(|(_, text)| text.as_str()).collect::<Vec<_>>().join(" ");
+        let language = self.options.language.unwrap_or_else(|| Language::detect(&text));
+        let mut analysis = DocumentAnalysis::new(Some(language));
+        for (page_index, page_text) in &pages {
+            self.analyze_page(&mut analysis, doc, *page_index, page_text, None, query)?;
+        }
+        Ok(Some(analysis))
     }
 
-    fn rank_sections(sections: &mut [ExtractedSection], analysis: &[SubsectionAnalysis], persona_keywords: &[String], task_keywords: &[String]) {
-        for section in sections.iter_mut() {
-            let mut score = 0;
-            for analyzed_part in analysis {
-                if analyzed_part.document == section.document && analyzed_part.page_number == section.page_number {
-                    let text_lower = analyzed_part.refined_text.to_lowercase();
-                    score += persona_keywords.iter().filter(|k| text_lower.contains(*k)).count();
-                    score += task_keywords.iter().filter(|k| text_lower.contains(*k)).count();
+    // Extracts every page before analyzing any, so the language can be detected
+    // from the whole document.
+    fn analyze_batch(&self, doc: &Document, pdf_path: &Path, pages: Option<&BTreeSet<u32>>, query: &Query) -> Result<Option<DocumentAnalysis>> {
+        let preserve_whitespace = self.options.preserve_whitespace || doc.preserve_whitespace;
+        let mut extracted = match self.extract_pdf_text(pdf_path, self.pdf_password(doc), pages, preserve_whitespace) {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                warn!("{:?} backend failed for {}: {}", Backend::Native, pdf_path.display(), e);
+                return Ok(None);
+            }
+        };
+        if let Some(threshold) = self.options.repeated_line_threshold {
+            Self::strip_repeated_lines(&mut extracted.page_texts, &mut extracted.layouts, threshold);
+        }
+        let text = extracted.page_texts.iter().map(|(_, text)| text.as_str()).collect::<Vec<_>>().join(" ");
+        let mut analysis = DocumentAnalysis::new(Some(self.options.language.unwrap_or_else(|| Language::detect(&text))));
+        analysis.record_visit(doc, extracted.visit)?;
+        for (page_num, page_text) in &extracted.page_texts {
+            self.analyze_page(&mut analysis, doc, *page_num, page_text, extracted.layouts.get(page_num), query)?;
+        }
+        Ok(Some(analysis))
+    }
+
+    // Analyzes each page as soon as it is extracted and drops its text, so memory
+    // stays bounded by one page plus the accumulated results. Without a configured
+    // language, the language is detected from the first page with text.
+    fn analyze_streaming(&self, doc: &Document, pdf_path: &Path, pages: Option<&BTreeSet<u32>>, query: &Query) -> Result<Option<DocumentAnalysis>> {
+        let mut analysis = DocumentAnalysis::new(self.options.language);
+        // Analysis errors are kept apart from extraction errors: only the latter
+        // should send the document to OCR.
+        let mut analysis_error = None;
+        let preserve_whitespace = self.options.preserve_whitespace || doc.preserve_whitespace;
+        let visited = self.visit_pdf_pages(pdf_path, self.pdf_password(doc), pages, preserve_whitespace, |page_num, page_text, layout| {
+            let result = self.analyze_page(&mut analysis, doc, page_num, &page_text, Some(&layout), query);
+            result.map_err(|e| {
+                let message = e.to_string();
+                analysis_error = Some(e);
+                anyhow::anyhow!(message)
+            })
+        });
+        if let Some(e) = analysis_error {
+            return Err(e);
+        }
+        match visited {
+            Ok(visit) => {
+                analysis.record_visit(doc, visit)?;
+                Ok(Some(analysis))
+            }
+            Err(e) => {
+                warn!("{:?} backend failed for {}: {}", Backend::Native, pdf_path.display(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    // Removes running headers and footers: lines found on at least `threshold`
+    // of the pages once case and digits are ignored, so "Page 3" and "Page 4"
+    // count as the same line. Lines come from the page layout where there is
+    // one, else from the text. They are cut from the page text and from the
+    // layout, so neither heading detection nor scoring sees them.
+    fn strip_repeated_lines(page_texts: &mut [(usize, String)], layouts: &mut BTreeMap<usize, PageLayout>, threshold: f64) {
+        if page_texts.len() < MIN_PAGES_FOR_REPEATED_LINES {
+            return;
+        }
+        let key = |line: &str| -> String {
+            line.to_lowercase().chars()
+                .map(|c| if c.is_ascii_digit() { '#' } else { c })
+                .collect::<String>()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let page_lines: Vec<Vec<String>> = page_texts.iter().map(|(page_num, text)| match layouts.get(page_num) {
+            Some(layout) if !layout.runs.is_empty() => layout.lines().into_iter().map(|line| line.text).collect(),
+            _ => text.lines().map(|line| line.split_whitespace().collect::<Vec<_>>().join(" ")).collect(),
+        }).collect();
+        let mut pages_with_line: HashMap<String, usize> = HashMap::new();
+        for lines in &page_lines {
+            let distinct: HashSet<String> = lines.iter().map(|line| key(line)).filter(|key| !key.is_empty()).collect();
+            for key in distinct {
+                *pages_with_line.entry(key).or_default() += 1;
+            }
+        }
+        let min_pages = (threshold * page_texts.len() as f64).ceil().max(2.0) as usize;
+        let repeated: HashSet<&String> = pages_with_line.iter()
+            .filter(|(_, &count)| count >= min_pages)
+            .map(|(key, _)| key)
+            .collect();
+        if repeated.is_empty() {
+            return;
+        }
+        for ((page_num, text), lines) in page_texts.iter_mut().zip(&page_lines) {
+            if let Some(layout) = layouts.get_mut(page_num) {
+                layout.retain_lines(|line| !repeated.contains(&key(&line.text)));
+            }
+            let mut stripped = false;
+            for line in lines.iter().filter(|line| repeated.contains(&key(line))) {
+                if let Some(start) = text.find(line.as_str()) {
+                    text.replace_range(start..start + line.len(), "");
+                    stripped = true;
                 }
             }
-            section.importance_rank = score as u32;
+            if !stripped {
+                continue;
+            }
+            *text = text.split("\n\n")
+                .map(|paragraph| paragraph.trim().to_string())
+                .filter(|paragraph| !paragraph.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n");
         }
-        sections.sort_by(|a, b| b.importance_rank.cmp(&a.importance_rank));
-        for (i, section) in sections.iter_mut().enumerate() {
-            section.importance_rank = (i + 1) as u32;
+    }
+
+    // Runs heading detection and relevance matching on one page, keeping only the
+    // results. `page_index` is the 1-based PDF page index.
+    fn analyze_page(
+        &self,
+        analysis: &mut DocumentAnalysis,
+        doc: &Document,
+        page_index: usize,
+        page_text: &str,
+        layout: Option<&PageLayout>,
+        query: &Query,
+    ) -> Result<()> {
+        let page_num = Self::printed_page_number(doc, page_index)?;
+        let language = *analysis.language.get_or_insert_with(|| Language::detect(page_text));
+        let full_text = page_text;
+
+        // Footnotes are taken out of the body text and scored on their own.
+        let mut body_text = page_text.to_string();
+        if self.options.separate_footnotes {
+            if let Some(layout) = layout {
+                for note in layout.footnotes() {
+                    if let Some(start) = body_text.rfind(&note) {
+                        body_text.replace_range(start..start + note.len(), "");
+                    }
+                    if self.options.structure_report {
+                        continue;
+                    }
+                    let score = self.paragraph_score(&doc.filename, &note, query);
+                    if score > 0.0 {
+                        analysis.footnotes.push(FootnoteAnalysis {
+                            document: doc.filename.clone(),
+                            text: note,
+                            page_number: page_num as u32,
+                            score,
+                        });
+                    }
+                }
+                body_text = body_text.split(' ').filter(|w| !w.is_empty()).collect::<Vec<_>>().join(" ");
+            }
+        }
+        let page_text = body_text.as_str();
+
+        // Tagged pages take their headings from the structure tree alone.
+        let mut headings = match layout.and_then(|layout| layout.blocks.as_ref()) {
+            Some(blocks) => blocks.iter()
+                .filter_map(|block| match block.role {
+                    StructRole::Heading(level) => Some(Heading { title: block.text.clone(), level }),
+                    _ => None,
+                })
+                .collect(),
+            None if self.options.merge_wrapped_headings => {
+                let merged = Self::merge_wrapped_heading_lines(page_text, language, layout);
+                self.extract_headings_from_page(&merged, language)
+            }
+            None => self.extract_headings_from_page(page_text, language),
+        };
+        if headings.is_empty() && self.options.infer_titles_from_fonts {
+            if let Some(layout) = layout.filter(|layout| layout.blocks.is_none()) {
+                headings = layout.block_titles(self.options.merge_wrapped_headings).into_iter()
+                    .map(|(title, level)| Heading { title, level })
+                    .collect();
+            }
+        }
+        if let Some(max_level) = self.options.max_heading_level {
+            headings.retain(|heading| heading.level <= max_level);
+        }
+        // Overlapping patterns can find the same heading more than once.
+        let mut seen = HashSet::new();
+        headings.retain(|heading| seen.insert(heading.title.clone()));
+        let by_position = self.options.section_ordering == SectionOrdering::ByPosition;
+        let spans = if self.options.section_bodies || self.options.parent_sections || by_position {
+            Self::heading_spans(page_text, &headings)
+        } else {
+            Vec::new()
+        };
+        let mut bodies = if self.options.section_bodies {
+            Self::section_bodies(page_text, &spans)
+        } else {
+            Vec::new()
+        }.into_iter();
+        let mut located: Vec<_> = headings.into_iter()
+            .enumerate()
+            .map(|(i, heading)| (heading, bodies.next().flatten(), spans.get(i).copied().flatten()))
+            .collect();
+        // Sections are reported in extraction order, so this sets their order
+        // on the page; headings that weren't located go last.
+        if by_position {
+            located.sort_by_key(|(_, _, span)| span.map_or(usize::MAX, |(start, _)| start));
+        }
+        let mut page_headings: Vec<(usize, String)> = Vec::new();
+        for (heading, section_body, span) in located {
+            let normalization = &self.options.title_normalization;
+            let section_title = Self::normalize_title(&heading.title, normalization);
+            if let Some((start, _)) = span {
+                page_headings.push((start, section_title.clone()));
+            }
+            // Still a parent for this page's subsections, just not reported again.
+            if self.options.dedupe_document_headings && analysis.sections.iter().any(|section| {
+                section.page_number != page_num as u32 && Self::same_heading(&section.section_title, &section_title)
+            }) {
+                continue;
+            }
+            let original_title = (normalization.keep_original && section_title != heading.title).then_some(heading.title);
+            analysis.sections.push(ExtractedSection {
+                document: doc.filename.clone(),
+                section_title,
+                original_title,
+                importance_rank: 0, // Placeholder, will be updated later
+                page_number: page_num as u32,
+                heading_level: Some(heading.level),
+                term_frequencies: None,
+                section_body,
+            });
+        }
+
+        // The structure report stops short of relevance matching.
+        if self.options.structure_report {
+            analysis.paragraphs += page_text.split("\n\n").filter(|paragraph| !paragraph.trim().is_empty()).count();
+            analysis.list_items += layout.map_or(0, |layout| layout.list_items().len());
+            if layout.is_some_and(|layout| layout.blocks.is_some()) {
+                analysis.tagged_pages += 1;
+            }
+            analysis.pages_extracted += 1;
+            analysis.total_chars += full_text.chars().count();
+            analysis.total_words += full_text.split_whitespace().count();
+            return Ok(());
+        }
+
+        let page_texts = [(page_num, page_text.to_string())];
+        let mut relevant_content = self.find_relevant_content(&doc.filename, &page_texts, query);
+        if self.options.record_bboxes {
+            if let Some(layout) = layout {
+                Self::attach_bboxes(&mut relevant_content, page_text, layout);
+            }
+        }
+        if self.options.detect_lists {
+            if let Some(layout) = layout {
+                Self::attach_list_items(&mut relevant_content, layout);
+            }
+        }
+        if self.options.parent_sections {
+            Self::attach_parent_sections(&mut relevant_content, page_text, &page_headings);
+        }
+
+        if self.options.document_links || self.options.export_matrix {
+            for (term, count) in scoring::term_frequencies(language.content_tokens(full_text, &self.options.stopword_exemptions)) {
+                *analysis.term_counts.entry(term).or_default() += count;
+            }
+        }
+        if self.options.scoring_mode == ScoringMode::TfIdf {
+            let folded = query.fold(full_text);
+            for keyword in query.persona_keywords.iter().chain(&query.task_keywords).collect::<HashSet<_>>() {
+                if scoring::contains_word(&folded, keyword) {
+                    *analysis.keyword_pages.entry(keyword.clone()).or_default() += 1;
+                }
+            }
         }
+        if self.options.report_page_confidence {
+            analysis.page_confidence.push(Self::page_confidence(&doc.filename, page_num as u32, full_text));
+        }
+        analysis.pages_extracted += 1;
+        analysis.total_chars += full_text.chars().count();
+        analysis.total_words += full_text.split_whitespace().count();
+        if !relevant_content.is_empty() {
+            analysis.pages_with_results += 1;
+        }
+        analysis.subsections.extend(relevant_content);
+        Ok(())
     }
 
-    fn find_relevant_content(
-        doc_name: &str,
-        page_texts: &[(usize, String)],
-        persona_keywords: &[String],
-        task_keywords: &[String],
-    ) -> Vec<SubsectionAnalysis> {
-        let mut relevant_sections = Vec::new();
-        for (page_num, text) in page_texts {
-            let paragraphs: Vec<String> = text.split("\n\n").map(|s| s.to_string()).collect();
-            for para in paragraphs {
-                let para_lower = para.to_lowercase();
-                let persona_matches = persona_keywords.iter().any(|k| para_lower.contains(k));
-                let task_matches = task_keywords.iter().any(|k| para_lower.contains(k));
-
-                if persona_matches && task_matches {
-                    println!("[DEBUG] Found relevant paragraph on page {} of {}: '{}'", page_num, doc_name, para.chars().take(100).collect::<String>());
-                    relevant_sections.push(SubsectionAnalysis {
-                        document: doc_name.to_string(),
-                        refined_text: para.trim().to_string(),
-                        page_number: *page_num as u32,
-                    });
+    // Combines three signals: the share of tokens that look like words (letters
+    // with a vowel, or numbers), the share of U+FFFD replacement characters left
+    // by lossy decoding, and how much text the page has at all.
+    fn page_confidence(document: &str, page_number: u32, text: &str) -> PageConfidence {
+        let chars = text.chars().count();
+        let replacement_chars = text.chars().filter(|&c| c == char::REPLACEMENT_CHARACTER).count();
+        let tokens: Vec<&str> = text.split_whitespace()
+            .map(|token| token.trim_matches(|c: char| c.is_ascii_punctuation()))
+            .filter(|token| !token.is_empty())
+            .collect();
+        let word_like = tokens.iter().filter(|token| {
+            let letters = token.chars().all(|c| c.is_alphabetic() || c == '\'' || c == '-');
+            let vowel = token.chars().any(|c| "aeiouyäöüàâéèêëîïôûù".contains(c.to_lowercase().next().unwrap_or(c)));
+            (letters && vowel) || token.chars().all(|c| c.is_ascii_digit() || c == '.' || c == ',')
+        }).count();
+        let ratio = |part: usize, whole: usize| if whole == 0 { 0.0 } else { part as f64 / whole as f64 };
+        let word_ratio = ratio(word_like, tokens.len());
+        let clean = 1.0 - (ratio(replacement_chars, chars) / MAX_REPLACEMENT_SHARE).min(1.0);
+        let density = (chars as f64 / CONFIDENT_PAGE_CHARS).min(1.0);
+        PageConfidence {
+            document: document.to_string(),
+            page_number,
+            confidence: word_ratio * clean * density,
+            chars: chars as u32,
+            word_ratio,
+            replacement_chars: replacement_chars as u32,
+        }
+    }
+
+    // Maps a 1-based PDF page index to the number printed on the page.
+    fn printed_page_number(doc: &Document, page_index: usize) -> Result<usize> {
+        let printed = page_index as i64 + doc.page_offset as i64;
+        if printed < 1 {
+            return Err(anyhow::anyhow!(
+                "page_offset {} gives page {} of {} the printed number {}; printed page numbers must be at least 1",
+                doc.page_offset, page_index, doc.filename, printed
+            ));
+        }
+        Ok(printed as usize)
+    }
+
+    // Combines the global and per-document page lists; when both are set only
+    // pages in both are extracted.
+    fn page_selection(global: Option<&[u32]>, document: Option<&[u32]>) -> Option<BTreeSet<u32>> {
+        match (global, document) {
+            (None, None) => None,
+            (Some(pages), None) | (None, Some(pages)) => Some(pages.iter().copied().collect()),
+            (Some(global), Some(document)) => Some(global.iter().copied().filter(|p| document.contains(p)).collect()),
+        }
+    }
+
+    fn extract_pdf_text(&self, path: &Path, password: Option<&str>, pages: Option<&BTreeSet<u32>>, preserve_whitespace: bool) -> Result<ExtractedText> {
+        let mut page_texts = Vec::new();
+        let mut layouts = BTreeMap::new();
+        let visit = self.visit_pdf_pages(path, password, pages, preserve_whitespace, |page_num, text, layout| {
+            page_texts.push((page_num, text));
+            layouts.insert(page_num, layout);
+            Ok(())
+        })?;
+        Ok(ExtractedText { page_texts, layouts, visit })
+    }
+
+    // Extracts pages one at a time, handing each page with text to `visit` along
+    // with its 1-based index. Pages are found by walking the page tree rather than
+    // trusting its /Count, which malformed files get wrong. With
+    // `parallel_pages`, the pages are extracted on worker threads first and then
+    // visited in page order.
+    fn visit_pdf_pages(
+        &self,
+        path: &Path,
+        password: Option<&str>,
+        pages: Option<&BTreeSet<u32>>,
+        preserve_whitespace: bool,
+        mut visit: impl FnMut(usize, String, PageLayout) -> Result<()>,
+    ) -> Result<PageVisit> {
+        let file = Self::open_pdf(path, password)?;
+        let mut found_text = false;
+        let mut skipped_pages = Vec::new();
+
+        let structure = if self.options.use_structure_tags {
+            match StructureMap::load(&file, file.trailer.root.get_ref().get_inner()) {
+                Ok(structure) => structure,
+                Err(e) => {
+                    warn!("Ignoring unreadable structure tree in {}: {}", path.display(), e);
+                    None
                 }
             }
+        } else {
+            None
+        };
+
+        let mut leaves = Vec::new();
+        Self::page_tree_leaves(&file, &file.get_root().pages, 0, &mut leaves);
+        let declared_count = file.num_pages();
+        let page_count = leaves.len() as u32;
+        let page_count_mismatch = (declared_count != page_count).then(|| {
+            warn!("{} declares {} pages but its page tree holds {}", path.display(), declared_count, page_count);
+            (declared_count, page_count)
+        });
+        let page_indices: Vec<u32> = match pages {
+            Some(selected) => selected.iter().filter_map(|&page| {
+                if (1..=page_count).contains(&page) {
+                    Some(page - 1)
+                } else {
+                    warn!("Page {} is out of range for {} ({} pages), skipping", page, path.display(), page_count);
+                    None
+                }
+            }).collect(),
+            None => (0..page_count).collect(),
+        };
+
+        let workers = self.options.parallel_pages.unwrap_or(1).clamp(1, page_indices.len().max(1));
+        let extracted: Box<dyn Iterator<Item = (u32, Result<ExtractedPage>)>> = if workers > 1 {
+            let structure = structure.as_ref();
+            Box::new(self.extract_pages_in_parallel(path, password, &page_indices, workers, structure, preserve_whitespace)?.into_iter())
+        } else {
+            Box::new(page_indices.into_iter().map(|page_num| {
+                let page = self.extract_page(&file, &leaves[page_num as usize], page_num, path, structure.as_ref(), preserve_whitespace);
+                (page_num, page)
+            }))
+        };
+
+        for (page_num, page) in extracted {
+            match page? {
+                ExtractedPage::Text(cleaned_text, layout) => {
+                    found_text = true;
+                    visit(page_num as usize + 1, cleaned_text, layout)?;
+                }
+                // Still visited when kept, so the document's pages stay contiguous.
+                ExtractedPage::Empty if self.options.keep_blank_pages => visit(page_num as usize + 1, String::new(), PageLayout::default())?,
+                ExtractedPage::Empty => {}
+                ExtractedPage::Unreadable(e) => skipped_pages.push((page_num as usize + 1, e)),
+            }
+        }
+        
+        if !found_text {
+            return Err(anyhow::anyhow!("No text extracted from PDF"));
         }
+        
+        Ok(PageVisit { skipped_pages, page_count_mismatch })
+    }
 
-        relevant_sections
+    // Splits `page_indices` into one contiguous run per worker. The `pdf` crate's
+    // objects are reference-counted and can't cross threads, so each worker
+    // opens the file and walks its page tree itself; only the structure map,
+    // plain data, is shared. The pages come back in page order. Unreadable
+    // pages are returned like any other. An error fails the document: the
+    // worker that hit it stops there, the others still finish their runs, and
+    // the caller returns the first error in page order, as the sequential walk does.
+    fn extract_pages_in_parallel(
+        &self,
+        path: &Path,
+        password: Option<&str>,
+        page_indices: &[u32],
+        workers: usize,
+        structure: Option<&StructureMap>,
+        preserve_whitespace: bool,
+    ) -> Result<Vec<(u32, Result<ExtractedPage>)>> {
+        let chunk_size = page_indices.len().div_ceil(workers);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = page_indices.chunks(chunk_size).map(|chunk| scope.spawn(move || -> Result<_> {
+                let file = Self::open_pdf(path, password)?;
+                let mut leaves = Vec::new();
+                Self::page_tree_leaves(&file, &file.get_root().pages, 0, &mut leaves);
+                let mut pages = Vec::with_capacity(chunk.len());
+                for &page_num in chunk {
+                    let page = self.extract_page(&file, &leaves[page_num as usize], page_num, path, structure, preserve_whitespace);
+                    let failed = page.is_err();
+                    pages.push((page_num, page));
+                    if failed {
+                        break;
+                    }
+                }
+                Ok(pages)
+            })).collect();
+            let mut pages = Vec::with_capacity(page_indices.len());
+            for handle in handles {
+                pages.extend(handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))?);
+            }
+            Ok(pages)
+        })
+    }
+
+    // Extracts and cleans the text of the page at the 0-based `page_num`.
+    fn extract_page(
+        &self,
+        file: &impl Resolve,
+        leaf: &Result<RcRef<PagesNode>>,
+        page_num: u32,
+        path: &Path,
+        structure: Option<&StructureMap>,
+        preserve_whitespace: bool,
+    ) -> Result<ExtractedPage> {
+        // A page that fails to resolve or parse is skipped instead of failing
+        // the whole document.
+        let node = match leaf {
+            Ok(node) => node,
+            Err(e) => {
+                warn!("Skipping page {} of {}: {}", page_num + 1, path.display(), e);
+                return Ok(ExtractedPage::Unreadable(e.to_string()));
+            }
+        };
+        let PagesNode::Leaf(page) = &**node else { unreachable!("page tree leaves are pages") };
+        let mut page_text = String::new();
+        let mut layout = PageLayout::default();
+        
+        let crop_box = if self.options.respect_cropbox {
+            match page.crop_box() {
+                Ok(rect) => Some(rect),
+                Err(e) => {
+                    warn!("Ignoring unreadable CropBox on page {} of {}: {}", page_num + 1, path.display(), e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if let Some(content) = &page.contents {
+            let mut state = TextState::new(Self::bold_font_names(page));
+            if let Err(e) = Self::extract_text_from_content(file, content, &mut state, crop_box.as_ref(), &mut page_text, &mut layout) {
+                warn!("Skipping page {} of {}: {:#}", page_num + 1, path.display(), e);
+                return Ok(ExtractedPage::Unreadable(format!("{:#}", e)));
+            }
+        }
+        // Keeps run text comparable to the cleaned page text it is located in.
+        if self.options.normalize_unicode {
+            for run in &mut layout.runs {
+                run.text = crate::utils::normalize_text(&run.text);
+            }
+        }
+        
+        let tagged_blocks = structure
+            .and_then(|structure| structure.page_blocks(node.get_ref().get_inner(), &layout.runs));
+        let mut cleaned_text = match tagged_blocks {
+            // Each tagged block becomes its own paragraph.
+            Some(blocks) => {
+                let blocks: Vec<TaggedBlock> = blocks.into_iter()
+                    .map(|block| TaggedBlock {
+                        label: self.clean_extracted_text(&block.label, preserve_whitespace),
+                        text: self.clean_extracted_text(&block.text, preserve_whitespace),
+                        ..block
+                    })
+                    .filter(|block| !block.text.is_empty())
+                    .collect();
+                let text = blocks.iter().map(TaggedBlock::full_text).collect::<Vec<_>>().join("\n\n");
+                layout.blocks = Some(blocks);
+                text
+            }
+            None => self.clean_extracted_text(&page_text, preserve_whitespace),
+        };
+        if let Some(max_length) = self.options.max_token_length {
+            let (guarded, long_tokens) = Self::guard_long_tokens(&cleaned_text, max_length, self.options.long_token_action);
+            if long_tokens > 0 {
+                warn!(
+                    "Page {} of {} has {} token(s) over {} characters, likely an extraction defect ({:?})",
+                    page_num + 1, path.display(), long_tokens, max_length, self.options.long_token_action
+                );
+                cleaned_text = guarded;
+            }
+        }
+        if cleaned_text.is_empty() {
+            return Ok(ExtractedPage::Empty);
+        }
+        Ok(ExtractedPage::Text(cleaned_text, layout))
+    }
+
+    // Collects the page tree's leaves in document order, or the error for each
+    // kid that doesn't resolve.
+    fn page_tree_leaves(resolver: &impl Resolve, tree: &PageTree, depth: usize, leaves: &mut Vec<Result<RcRef<PagesNode>>>) {
+        for &kid in &tree.kids {
+            match resolver.get(kid) {
+                Ok(node) => {
+                    if let PagesNode::Tree(subtree) = &*node {
+                        if depth < MAX_PAGE_TREE_DEPTH {
+                            Self::page_tree_leaves(resolver, subtree, depth + 1, leaves);
+                        } else {
+                            leaves.push(Err(anyhow::anyhow!("page tree nested deeper than {} levels", MAX_PAGE_TREE_DEPTH)));
+                        }
+                    } else {
+                        leaves.push(Ok(node));
+                    }
+                }
+                Err(e) => leaves.push(Err(e.into())),
+            }
+        }
+    }
+
+    // Form feeds mark explicit page or paragraph breaks; other control
+    // characters are treated as whitespace. Blank lines separate paragraphs,
+    // which are kept apart with "\n\n" for `find_relevant_content`; whitespace
+    // inside a paragraph is collapsed. With `preserve_whitespace`, line breaks
+    // and runs of spaces within lines are kept for code and tables.
+    fn clean_extracted_text(&self, raw_text: &str, preserve_whitespace: bool) -> String {
+        let separator = match self.options.form_feed {
+            FormFeed::ParagraphBreak => "\n\n",
+            FormFeed::Space => " ",
+        };
+        static WHITESPACE: OnceLock<Regex> = OnceLock::new();
+        let re = WHITESPACE.get_or_init(|| Regex::new(r"\s+").unwrap());
+        static BLANK_LINE: OnceLock<Regex> = OnceLock::new();
+        let blank_line = BLANK_LINE.get_or_init(|| Regex::new(r"\n\s*\n").unwrap());
+        self.normalize_text(raw_text).split('\x0c')
+            .map(|segment| {
+                let mut segment: String = segment.chars().map(|c| if c.is_control() && c != '\n' { ' ' } else { c }).collect();
+                if self.options.dehyphenate {
+                    segment = Self::dehyphenate(&segment);
+                }
+                blank_line.split(&segment)
+                    .map(|paragraph| {
+                        if preserve_whitespace {
+                            return paragraph.lines().map(|line| line.trim_end()).filter(|line| !line.is_empty()).collect::<Vec<_>>().join("\n");
+                        }
+                        re.replace_all(paragraph.trim(), " ").to_string()
+                    })
+                    .filter(|paragraph| !paragraph.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    fn normalize_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.options.normalize_unicode {
+            Cow::Owned(crate::utils::normalize_text(text))
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+
+    // Rejoins words split across a line break by a hyphen, such as "inter-" and
+    // "national", moving the rest of the word up onto the first line. Only a
+    // lowercase continuation is joined, so "Paris-\nBerlin" stays as it is, and a
+    // compound the page also hyphenates mid-line, such as "well-known", keeps
+    // its hyphen.
+    fn dehyphenate(text: &str) -> String {
+        static BROKEN_WORD: OnceLock<Regex> = OnceLock::new();
+        let broken = BROKEN_WORD.get_or_init(|| Regex::new(r"(\p{L}+)-[ \t]*\n[ \t]*(\p{Ll}[\p{L}\p{M}]*)(\S*)[^\S\n]*\n?").unwrap());
+        if !broken.is_match(text) {
+            return text.to_string();
+        }
+        static COMPOUND: OnceLock<Regex> = OnceLock::new();
+        let compound = COMPOUND.get_or_init(|| Regex::new(r"\p{L}+-\p{L}+").unwrap());
+        let compounds: HashSet<String> = compound.find_iter(text).map(|m| m.as_str().to_lowercase()).collect();
+        broken.replace_all(text, |caps: &regex::Captures| {
+            let (head, tail) = (&caps[1], &caps[2]);
+            if compounds.contains(&format!("{}-{}", head, tail).to_lowercase()) {
+                format!("{}-{}{}\n", head, tail, &caps[3])
+            } else {
+                format!("{}{}{}\n", head, tail, &caps[3])
+            }
+        }).into_owned()
+    }
+
+    // Splits or drops whitespace-free tokens longer than `max_length` characters,
+    // keeping paragraph breaks. Returns the new text and how many tokens were hit.
+    fn guard_long_tokens(text: &str, max_length: usize, action: LongTokenAction) -> (String, usize) {
+        let max_length = max_length.max(1);
+        let mut long_tokens = 0;
+        let paragraphs: Vec<String> = text.split("\n\n").map(|paragraph| {
+            paragraph.split(' ').flat_map(|token| {
+                if token.chars().count() <= max_length {
+                    return vec![token.to_string()];
+                }
+                long_tokens += 1;
+                match action {
+                    LongTokenAction::Drop => Vec::new(),
+                    LongTokenAction::Split => Self::split_fused_token(token)
+                        .into_iter()
+                        .flat_map(|piece| {
+                            let chars: Vec<char> = piece.chars().collect();
+                            chars.chunks(max_length).map(|chunk| chunk.iter().collect()).collect::<Vec<String>>()
+                        })
+                        .collect(),
+                }
+            }).collect::<Vec<_>>().join(" ")
+        }).filter(|paragraph| !paragraph.is_empty()).collect();
+        (paragraphs.join("\n\n"), long_tokens)
+    }
+
+    // Breaks a fused token where a lowercase letter is followed by an uppercase one
+    // ("endNext") or sentence punctuation by a letter ("end.Next").
+    fn split_fused_token(token: &str) -> Vec<String> {
+        let mut pieces = Vec::new();
+        let mut current = String::new();
+        let mut previous: Option<char> = None;
+        for c in token.chars() {
+            let boundary = previous.is_some_and(|p| {
+                (p.is_lowercase() && c.is_uppercase()) || (".,;:!?".contains(p) && c.is_alphabetic())
+            });
+            if boundary && !current.is_empty() {
+                pieces.push(std::mem::take(&mut current));
+            }
+            current.push(c);
+            previous = Some(c);
+        }
+        if !current.is_empty() {
+            pieces.push(current);
+        }
+        pieces
+    }
+
+    fn bold_font_names(page: &Page) -> HashSet<String> {
+        let Ok(resources) = page.resources() else {
+            return HashSet::new();
+        };
+        resources.fonts.iter()
+            .filter(|(_, font)| font.name.as_ref().is_some_and(|base| {
+                let base = base.as_str().to_lowercase();
+                base.contains("bold") || base.contains("black") || base.contains("heavy")
+            }))
+            .map(|(name, _)| name.as_str().to_string())
+            .collect()
+    }
+
+    // Text-showing operators starting outside `clip`, when given, are dropped
+    // from both the text and the layout.
+    fn extract_text_from_content(
+        resolver: &impl Resolve,
+        content: &Content,
+        state: &mut TextState,
+        clip: Option<&Rect>,
+        text: &mut String,
+        layout: &mut PageLayout,
+    ) -> Result<()> {
+        for op in content.operations(resolver)? {
+            state.apply(&op);
+            let runs = state.draw(&op);
+            // Only the first run's position is exact; later ones are advanced by
+            // estimated glyph widths, so an operator is kept or dropped as a whole.
+            if let (Some(rect), Some(first)) = (clip, runs.first()) {
+                if !Self::rect_contains(rect, first.x, first.y) {
+                    continue;
+                }
+            }
+            if let (Some(previous), Some(first)) = (layout.runs.last(), runs.first()) {
+                text.push_str(Self::line_break(previous, first, state.line_spacing()));
+            }
+            for run in runs {
+                text.push_str(&run.text);
+                layout.runs.push(run);
+            }
+        }
+        Ok(())
+    }
+
+    // What separates two runs drawn one after the other: nothing on the same
+    // baseline (matching `PageLayout::lines`), a blank line when the baseline
+    // moved by more than one and a half lines, and a line break otherwise.
+    fn line_break(previous: &TextRun, next: &TextRun, line_spacing: f32) -> &'static str {
+        let offset = (previous.y - next.y).abs();
+        if offset < next.font_size.max(1.0) * 0.5 {
+            ""
+        } else if offset > line_spacing * 1.5 {
+            "\n\n"
+        } else {
+            "\n"
+        }
+    }
+
+    fn rect_contains(rect: &Rect, x: f32, y: f32) -> bool {
+        let (left, right) = (rect.left.min(rect.right), rect.left.max(rect.right));
+        let (bottom, top) = (rect.bottom.min(rect.top), rect.bottom.max(rect.top));
+        (left..=right).contains(&x) && (bottom..=top).contains(&y)
+    }
+
+    fn extract_with_pdftotext(&self, path: &Path, password: Option<&str>) -> Result<String> {
+        let timeout = self.options.ocr_timeout_secs.map(Duration::from_secs);
+        let program = &self.options.ocr_command;
+        let mut command = Command::new(program);
+        if let Some(password) = password {
+            command.arg("-upw").arg(password);
+        }
+        let input = path.to_string_lossy();
+        if self.options.ocr_args.iter().any(|arg| arg.contains("{input}")) {
+            command.args(self.options.ocr_args.iter().map(|arg| arg.replace("{input}", &input)));
+        } else {
+            command.args(&self.options.ocr_args).arg(path).arg("-");
+        }
+        let output = crate::utils::run_bounded(&mut command, timeout, self.options.ocr_max_output_bytes)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::TimedOut => anyhow::anyhow!("{} timed out on {}: {}", program, path.display(), e),
+                _ => anyhow::Error::new(e).context(format!("Failed to execute {}. Is poppler-utils installed, or ocr_command set?", program)),
+            })?;
+
+        let mut stdout = output.stdout;
+        if output.truncated {
+            warn!(
+                "{} output for {} exceeded {} bytes and was truncated ({})",
+                program, path.display(), stdout.len(), output.status
+            );
+            // Drop a character cut in half at the limit.
+            if let Err(e) = std::str::from_utf8(&stdout) {
+                if e.error_len().is_none() {
+                    stdout.truncate(e.valid_up_to());
+                }
+            }
+        } else if !output.status.success() {
+            return Err(anyhow::anyhow!("OCR failed: {}", String::from_utf8_lossy(&output.stderr)));
+        }
+        
+        String::from_utf8(stdout).with_context(|| "OCR output not valid UTF-8")
+    }
+
+    // Rasterizes every page with pdftoppm into a scratch directory, then OCRs
+    // the images one at a time. Returns the cleaned text of each page tesseract
+    // read anything from, with its 1-based PDF page index.
+    fn extract_with_tesseract(&self, path: &Path, password: Option<&str>, preserve_whitespace: bool) -> Result<Vec<(usize, String)>> {
+        let timeout = self.options.ocr_timeout_secs.map(Duration::from_secs);
+        let scratch = crate::utils::ScratchDir::new()?;
+        let mut pdftoppm = Command::new("pdftoppm");
+        if let Some(password) = password {
+            pdftoppm.arg("-upw").arg(password);
+        }
+        let rendered = crate::utils::run_bounded(
+            pdftoppm.arg("-r").arg(self.options.ocr_dpi.to_string()).arg("-png").arg(path).arg(scratch.path().join("page")),
+            timeout,
+            None,
+        ).map_err(|e| match e.kind() {
+            std::io::ErrorKind::TimedOut => anyhow::anyhow!("pdftoppm timed out on {}: {}", path.display(), e),
+            _ => anyhow::Error::new(e).context("Failed to execute pdftoppm. Is poppler-utils installed?"),
+        })?;
+        if !rendered.status.success() {
+            return Err(anyhow::anyhow!("pdftoppm failed: {}", String::from_utf8_lossy(&rendered.stderr)));
+        }
+
+        // pdftoppm names the images page-1.png, or page-01.png and so on when
+        // there are more pages, so the order comes from the number.
+        let mut images: Vec<(usize, PathBuf)> = std::fs::read_dir(scratch.path())?
+            .filter_map(|entry| {
+                let image = entry.ok()?.path();
+                let number = image.file_stem()?.to_str()?.rsplit('-').next()?.parse().ok()?;
+                Some((number, image))
+            })
+            .collect();
+        images.sort();
+
+        let mut pages = Vec::new();
+        for (page_index, image) in images {
+            let mut command = Command::new("tesseract");
+            command.arg(&image).arg("-");
+            if let Some(language) = &self.options.tesseract_language {
+                command.arg("-l").arg(language);
+            }
+            let output = crate::utils::run_bounded(&mut command, timeout, None).map_err(|e| match e.kind() {
+                std::io::ErrorKind::TimedOut => anyhow::anyhow!("tesseract timed out on page {} of {}: {}", page_index, path.display(), e),
+                _ => anyhow::Error::new(e).context("Failed to execute tesseract. Is it installed?"),
+            })?;
+            if !output.status.success() {
+                warn!("Tesseract failed on page {} of {}: {}", page_index, path.display(), String::from_utf8_lossy(&output.stderr).trim());
+                continue;
+            }
+            let text = self.clean_extracted_text(&String::from_utf8_lossy(&output.stdout), preserve_whitespace);
+            if !text.is_empty() || self.options.keep_blank_pages {
+                pages.push((page_index, text));
+            }
+        }
+        if pages.iter().all(|(_, text)| text.is_empty()) {
+            pages.clear();
+        }
+        Ok(pages)
+    }
+
+    // Words longer than two bytes, minus the query language's stopwords (other
+    // than exempted ones) and `extra_stopwords`.
+    fn extract_keywords_from_text(&self, text: &str, language: Language, cap: Option<usize>) -> Vec<String> {
+        let stemmer = self.options.stemming.then(|| language.stemmer());
+        let keywords: Vec<String> = scoring::fold_case(&self.normalize_text(text), self.options.case_folding)
+            .split_whitespace()
+            .map(|s| s.trim_matches(|c: char| !c.is_alphanumeric()).to_string())
+            .filter(|s| self.is_keyword(s, language))
+            .map(|s| match &stemmer {
+                Some(stemmer) => scoring::stem_words(&s, stemmer),
+                None => s,
+            })
+            .collect();
+        match cap {
+            Some(cap) => Self::top_keywords(keywords, cap),
+            None => keywords,
+        }
+    }
+
+    fn is_keyword(&self, word: &str, language: Language) -> bool {
+        let lower = word.to_lowercase();
+        word.len() > 2
+            && !language.is_stopword(&lower, &self.options.stopword_exemptions)
+            && !self.options.extra_stopwords.iter().any(|stopword| stopword.to_lowercase() == lower)
+    }
+
+    // Every two- and three-word span of adjacent keywords, so "a vegetarian
+    // buffet menu" gives "vegetarian buffet", "buffet menu" and "vegetarian
+    // buffet menu". Stopwords and punctuation between words break a run.
+    fn extract_phrases_from_text(&self, text: &str, language: Language) -> Vec<String> {
+        let mut runs: Vec<Vec<String>> = vec![Vec::new()];
+        for raw in scoring::fold_case(&self.normalize_text(text), self.options.case_folding).split_whitespace() {
+            let word = raw.trim_matches(|c: char| !c.is_alphanumeric());
+            let keyword = self.is_keyword(word, language);
+            if !keyword || !raw.starts_with(|c: char| c.is_alphanumeric()) {
+                runs.push(Vec::new());
+            }
+            if keyword {
+                runs.last_mut().unwrap().push(word.to_string());
+            }
+            if !raw.ends_with(|c: char| c.is_alphanumeric()) {
+                runs.push(Vec::new());
+            }
+        }
+        let stemmer = self.options.stemming.then(|| language.stemmer());
+        let mut phrases = Vec::new();
+        for run in &runs {
+            for n in 2..=3 {
+                for window in run.windows(n) {
+                    let phrase = window.join(" ");
+                    let phrase = match &stemmer {
+                        Some(stemmer) => scoring::stem_words(&phrase, stemmer),
+                        None => phrase,
+                    };
+                    if !phrases.contains(&phrase) {
+                        phrases.push(phrase);
+                    }
+                }
+            }
+        }
+        phrases
+    }
+
+    // Keeps the `cap` distinct keywords repeated most often in the text, longer
+    // words first among equals, in their original order.
+    fn top_keywords(keywords: Vec<String>, cap: usize) -> Vec<String> {
+        let mut distinct: Vec<(usize, &String)> = Vec::new();
+        for keyword in &keywords {
+            if !distinct.iter().any(|(_, k)| *k == keyword) {
+                distinct.push((keywords.iter().filter(|k| *k == keyword).count(), keyword));
+            }
+        }
+        let mut ranked = distinct.clone();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.chars().count().cmp(&a.1.chars().count())));
+        ranked.truncate(cap);
+        distinct.into_iter()
+            .filter(|entry| ranked.contains(entry))
+            .map(|(_, keyword)| keyword.clone())
+            .collect()
+    }
+
+    fn same_heading(a: &str, b: &str) -> bool {
+        a.split_whitespace().map(str::to_lowercase).eq(b.split_whitespace().map(str::to_lowercase))
+    }
+
+    // A pattern without a capture group takes its whole match as the title.
+    fn extract_headings_from_page(&self, page_text: &str, language: Language) -> Vec<Heading> {
+        let mut headings = Vec::new();
+        for re in self.heading_patterns.get(&language).into_iter().flatten() {
+            for cap in re.captures_iter(page_text) {
+                if let Some(heading_match) = cap.get(1).or_else(|| cap.get(0)) {
+                    let title = heading_match.as_str().trim().to_string();
+                    if title.is_empty() {
+                        continue;
+                    }
+                    let level = Self::infer_heading_level(&title);
+                    headings.push(Heading { title, level });
+                }
+            }
+        }
+        headings
+    }
+
+    // Applies the configured clean-ups in order: leading numbering, trailing
+    // punctuation, whitespace, then casing, so "3. Overview:" can become "Overview".
+    fn normalize_title(title: &str, rules: &TitleNormalization) -> String {
+        let mut title = title.trim().to_string();
+        if rules.strip_numbering {
+            static TITLE_NUMBERING: OnceLock<Regex> = OnceLock::new();
+            let numbering = TITLE_NUMBERING.get_or_init(|| Regex::new(r"^(?:\d+(?:\.\d+)*\.?|[IVXLC]+\.|[A-Z][.)])\s+").unwrap());
+            title = numbering.replace(&title, "").to_string();
+        }
+        if rules.strip_trailing_punctuation {
+            title = title.trim_end_matches(|c: char| c.is_whitespace() || ":;,.-–—".contains(c)).to_string();
+        }
+        if rules.collapse_whitespace {
+            title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+        match rules.case {
+            TitleCase::Preserve => title,
+            TitleCase::Lower => title.to_lowercase(),
+            TitleCase::Title => title.split(' ')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    // Where each heading sits in the page text, in byte offsets. Headings are
+    // located in order of appearance, ignoring how whitespace and line breaks
+    // fall inside them, and each occurrence is claimed by one heading only.
+    fn heading_spans(page_text: &str, headings: &[Heading]) -> Vec<Option<(usize, usize)>> {
+        let mut spans: Vec<Option<(usize, usize)>> = Vec::with_capacity(headings.len());
+        for heading in headings {
+            let pattern = heading.title.split_whitespace().map(regex::escape).collect::<Vec<_>>().join(r"\s+");
+            let span = Regex::new(&pattern).ok().and_then(|re| {
+                re.find_iter(page_text)
+                    .map(|m| (m.start(), m.end()))
+                    .find(|&(start, _)| !spans.iter().flatten().any(|&(taken, _)| taken == start))
+            });
+            spans.push(span.filter(|_| !heading.title.trim().is_empty()));
+        }
+        spans
+    }
+
+    // The text between each heading and the next heading found after it on the
+    // page, or the end of the page. A heading that couldn't be located, or that
+    // is directly followed by another, has no body.
+    fn section_bodies(page_text: &str, spans: &[Option<(usize, usize)>]) -> Vec<Option<String>> {
+        let mut starts: Vec<usize> = spans.iter().flatten().map(|&(start, _)| start).collect();
+        starts.sort_unstable();
+        spans.iter().map(|span| {
+            let (_, end) = (*span)?;
+            let next = starts.iter().copied().find(|&start| start >= end).unwrap_or(page_text.len());
+            let body = page_text[end..next].trim();
+            (!body.is_empty()).then(|| body.to_string())
+        }).collect()
+    }
+
+    // Joins each line with the next when the second continues a heading begun
+    // on the first. With a page layout both lines must stand out from body text
+    // in the same style; without one, both must be short and unpunctuated, and
+    // the first must end on a stopword or connector or the second be lowercase.
+    fn merge_wrapped_heading_lines(page_text: &str, language: Language, layout: Option<&PageLayout>) -> String {
+        let wrapped: Option<HashSet<(String, String)>> = layout
+            .filter(|layout| !layout.runs.is_empty())
+            .map(|layout| layout.wrapped_title_lines().into_iter().collect());
+        let continues = |first: &str, second: &str| match &wrapped {
+            Some(pairs) => {
+                let normalize = |line: &str| line.split_whitespace().collect::<Vec<_>>().join(" ");
+                pairs.contains(&(normalize(first), normalize(second)))
+            }
+            None => {
+                let heading_like = |line: &str| {
+                    line.chars().count() <= MAX_WRAPPED_HEADING_LINE
+                        && line.chars().next().is_some_and(char::is_alphanumeric)
+                        && !line.ends_with(['.', '!', '?', ';', ':'])
+                };
+                let last_word = first.rsplit(' ').next().unwrap_or_default().to_lowercase();
+                heading_like(first)
+                    && heading_like(second)
+                    && (language.stopwords().contains(&last_word.as_str())
+                        || first.ends_with(['&', ',', '-', '–'])
+                        || second.starts_with(char::is_lowercase))
+            }
+        };
+        let mut merged: Vec<String> = Vec::new();
+        let mut previous: Option<&str> = None;
+        for line in page_text.split('\n') {
+            let trimmed = line.trim();
+            match (previous, merged.last_mut()) {
+                (Some(prev), Some(last)) if continues(prev, trimmed) => {
+                    last.push(' ');
+                    last.push_str(trimmed);
+                }
+                _ => merged.push(line.to_string()),
+            }
+            previous = Some(trimmed);
+        }
+        merged.join("\n")
+    }
+
+    // Chapter markers and all-caps headings are treated as top level, numbered
+    // headings take their depth from the numbering ("2.1" is H2), anything else is H2.
+    fn infer_heading_level(title: &str) -> u8 {
+        if ["Chapter", "Kapitel", "Chapitre"].iter().any(|marker| title.starts_with(marker)) {
+            return 1;
+        }
+        static HEADING_NUMBERING: OnceLock<Regex> = OnceLock::new();
+        let numbering = HEADING_NUMBERING.get_or_init(|| Regex::new(r"^(\d+(?:\.\d+)*)\.?\s").unwrap());
+        if let Some(cap) = numbering.captures(title) {
+            return cap[1].split('.').count().min(u8::MAX as usize) as u8;
+        }
+        if !title.chars().any(|c| c.is_lowercase()) {
+            return 1;
+        }
+        2
+    }
+
+    fn heading_level_weight(&self, level: u8) -> f64 {
+        let weights = &self.options.heading_level_weights;
+        match weights.last() {
+            Some(last) => *weights.get(level.saturating_sub(1) as usize).unwrap_or(last),
+            None => 1.0,
+        }
+    }
+
+    // Persona and task keywords are matched literally, so documents in another
+    // language than the query can only match on shared words.
+    fn language_report(documents: &[CollectedDocument], query_language: Language) -> (Vec<DocumentLanguage>, Option<String>) {
+        if documents.iter().all(|doc| doc.language == query_language) {
+            return (Vec::new(), None);
+        }
+        let languages = documents.iter()
+            .map(|doc| DocumentLanguage { document: doc.filename.clone(), language: doc.language })
+            .collect();
+        let note = format!(
+            "Collection mixes languages; persona and task keywords ({:?}) are not translated, so documents in other languages only match on shared terms",
+            query_language
+        );
+        (languages, Some(note))
+    }
+
+    fn term_matrix(document_texts: &[CollectedDocument]) -> TermMatrix {
+        let terms: Vec<String> = document_texts.iter()
+            .flat_map(|doc| doc.term_counts.keys().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let counts: Vec<Vec<u32>> = document_texts.iter()
+            .map(|doc| terms.iter().map(|term| doc.term_counts.get(term).copied().unwrap_or(0.0) as u32).collect())
+            .collect();
+        let document_frequency = (0..terms.len())
+            .map(|t| counts.iter().filter(|row| row[t] > 0).count() as u32)
+            .collect();
+        TermMatrix {
+            documents: document_texts.iter().map(|doc| doc.filename.clone()).collect(),
+            terms,
+            counts,
+            document_frequency,
+        }
+    }
+
+    fn term_matrix_file(&self, matrix: &TermMatrix, output_path: &Path) -> Result<(PathBuf, String)> {
+        let (contents, extension) = match self.options.matrix_format {
+            MatrixFormat::Json => (serde_json::to_string_pretty(matrix)?, "json"),
+            MatrixFormat::Csv => (matrix.to_csv()?, "csv"),
+        };
+        Ok((output_path.with_file_name(format!("{}.{}", TERM_MATRIX_STEM, extension)), contents))
+    }
+
+    // Pairs of documents whose TF-IDF term vectors are at least
+    // `document_link_threshold` similar, most similar first.
+    fn link_documents(&self, document_texts: &[CollectedDocument]) -> Vec<DocumentLink> {
+        let counts: Vec<_> = document_texts.iter().map(|doc| doc.term_counts.clone()).collect();
+        let vectors = scoring::tf_idf_vectors(&counts);
+        let mut links = Vec::new();
+        for i in 0..vectors.len() {
+            for j in i + 1..vectors.len() {
+                let similarity = scoring::cosine(&vectors[i], &vectors[j]);
+                if similarity >= self.options.document_link_threshold {
+                    links.push(DocumentLink {
+                        source: document_texts[i].filename.clone(),
+                        target: document_texts[j].filename.clone(),
+                        similarity,
+                    });
+                }
+            }
+        }
+        links.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        links
+    }
+
+    // Pairs each ranked section with its highest-scoring subsection from the same
+    // document and page; sections without one are left out.
+    fn bundle_report(&self, sections: &[ExtractedSection], subsections: &[SubsectionAnalysis], query: &Query) -> Vec<BundleEntry> {
+        sections.iter().filter_map(|section| {
+            let best = subsections.iter()
+                .filter(|s| s.document == section.document && s.page_number == section.page_number)
+                .map(|s| (self.paragraph_score(&s.document, &s.refined_text, query), s))
+                .reduce(|best, next| if next.0 > best.0 { next } else { best })?;
+            Some(BundleEntry {
+                rank: section.importance_rank,
+                section_title: section.section_title.clone(),
+                excerpt: best.1.refined_text.clone(),
+                source: SourceAnchor {
+                    document: section.document.clone(),
+                    page_number: section.page_number,
+                    bbox: best.1.bbox,
+                },
+            })
+        }).collect()
+    }
+
+    // Inverts the subsections into keyword -> pages, one entry per document and
+    // page, highest score first.
+    fn keyword_index(&self, subsections: &[SubsectionAnalysis], query: &Query) -> BTreeMap<String, Vec<KeywordLocation>> {
+        let mut index: BTreeMap<String, Vec<KeywordLocation>> = BTreeMap::new();
+        for subsection in subsections {
+            let folded = query.fold(&subsection.refined_text);
+            let score = self.paragraph_score(&subsection.document, &subsection.refined_text, query);
+            let keywords: BTreeSet<&String> = query.persona_keywords.iter().chain(&query.task_keywords)
+                .filter(|k| scoring::contains_word(&folded, k))
+                .collect();
+            for keyword in keywords {
+                let locations = index.entry(keyword.clone()).or_default();
+                match locations.iter_mut().find(|l| l.document == subsection.document && l.page_number == subsection.page_number) {
+                    Some(location) => location.score = location.score.max(score),
+                    None => locations.push(KeywordLocation {
+                        document: subsection.document.clone(),
+                        page_number: subsection.page_number,
+                        score,
+                    }),
+                }
+            }
+        }
+        for locations in index.values_mut() {
+            locations.sort_by(|a, b| b.score.total_cmp(&a.score));
+        }
+        index
+    }
+
+    // Scores every input document by aggregating its subsections' paragraph
+    // scores; documents without subsections score zero. Ties keep input order.
+    fn rank_documents(&self, documents: &[Document], subsections: &[SubsectionAnalysis], query: &Query) -> Vec<DocumentRanking> {
+        let mut scored: Vec<(String, f64)> = documents.iter().map(|doc| {
+            let scores: Vec<f64> = subsections.iter()
+                .filter(|s| s.document == doc.filename)
+                .map(|s| self.paragraph_score(&s.document, &s.refined_text, query))
+                .collect();
+            // An empty float sum is -0.0, which would be reported as such.
+            let score = match self.options.document_score_aggregation {
+                _ if scores.is_empty() => 0.0,
+                ScoreAggregation::Sum => scores.iter().sum(),
+                ScoreAggregation::Max => scores.iter().copied().reduce(f64::max).unwrap_or(0.0),
+                ScoreAggregation::Mean => scores.iter().sum::<f64>() / scores.len() as f64,
+            };
+            (doc.filename.clone(), score)
+        }).collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.into_iter().enumerate()
+            .map(|(i, (document, score))| DocumentRanking { document, score, rank: (i + 1) as u32 })
+            .collect()
+    }
+
+    // The document's own password wins over the collection-wide `pdf_password`.
+    fn pdf_password<'a>(&'a self, doc: &'a Document) -> Option<&'a str> {
+        doc.password.as_deref().or(self.options.pdf_password.as_deref())
+    }
+
+    // Opens a PDF, decrypting it with `password` (or the empty user password).
+    fn open_pdf(path: &Path, password: Option<&str>) -> Result<CachedFile<Vec<u8>>> {
+        FileOptions::cached().password(password.unwrap_or_default().as_bytes()).open(path).map_err(|e| {
+            if !Self::is_invalid_password(&e) {
+                return e.into();
+            }
+            match password {
+                Some(_) => anyhow::anyhow!("{} is encrypted and the password given doesn't unlock it", path.display()),
+                None => anyhow::anyhow!(
+                    "{} is encrypted; set a `password` on the document in the input or the `pdf_password` option", path.display()
+                ),
+            }
+        })
+    }
+
+    // The `pdf` crate wraps the error in one layer per call it passed through.
+    fn is_invalid_password(error: &PdfError) -> bool {
+        match error {
+            PdfError::InvalidPassword => true,
+            PdfError::Try { source, .. } => Self::is_invalid_password(source),
+            PdfError::Shared { source } => Self::is_invalid_password(source),
+            _ => false,
+        }
+    }
+
+    // The input's title when it isn't blank, else the PDF's /Title, else the
+    // filename without its extension. An unreadable PDF falls through to the
+    // filename.
+    fn document_title(doc: &Document, pdf_path: &Path, password: Option<&str>) -> DocumentTitle {
+        let resolved = |title: String, source| DocumentTitle { document: doc.filename.clone(), title, source };
+        if !doc.title.trim().is_empty() {
+            return resolved(doc.title.trim().to_string(), TitleSource::Input);
+        }
+        let metadata_title = Self::open_pdf(pdf_path, password).ok().and_then(|file| {
+            let value = file.trailer.info_dict.as_ref()?.get("Title")?.clone().resolve(&file).ok()?;
+            let title = value.as_string().ok()?.to_string_lossy();
+            Some(title.split_whitespace().collect::<Vec<_>>().join(" "))
+        });
+        match metadata_title.filter(|title| !title.is_empty()) {
+            Some(title) => resolved(title, TitleSource::PdfMetadata),
+            None => {
+                let stem = Path::new(&doc.filename).file_stem().map_or_else(|| doc.filename.clone(), |stem| stem.to_string_lossy().to_string());
+                resolved(stem, TitleSource::Filename)
+            }
+        }
+    }
+
+    // The input's `date` wins over the PDF's ModDate, then its CreationDate.
+    // Unparseable PDF dates are ignored; an unparseable input date is an error.
+    fn document_date(doc: &Document, pdf_path: &Path, password: Option<&str>) -> Result<Option<DateTime<Utc>>> {
+        if let Some(date) = &doc.date {
+            let parsed = DateTime::parse_from_rfc3339(date).map(|date| date.with_timezone(&Utc)).ok()
+                .or_else(|| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok().map(|day| day.and_time(NaiveTime::MIN).and_utc()));
+            return parsed.map(Some).ok_or_else(|| anyhow::anyhow!(
+                "Invalid date {:?} for {}; use YYYY-MM-DD or RFC 3339", date, doc.filename
+            ));
+        }
+        if Self::is_text_document(pdf_path) {
+            return Ok(None);
+        }
+        let file = Self::open_pdf(pdf_path, password)?;
+        let Some(info) = &file.trailer.info_dict else {
+            return Ok(None);
+        };
+        Ok(["ModDate", "CreationDate"].iter()
+            .filter_map(|key| info.get(key))
+            .filter_map(|value| value.clone().resolve(&file).ok()?.as_string().ok().map(|s| s.to_string_lossy()))
+            .find_map(|value| Self::parse_pdf_date(&value)))
+    }
+
+    // Parses a PDF date string, "D:YYYYMMDDHHmmSSOHH'mm'", where everything
+    // after the year is optional.
+    fn parse_pdf_date(value: &str) -> Option<DateTime<Utc>> {
+        let value = value.trim().trim_start_matches("D:");
+        let digits: String = value.chars().take_while(char::is_ascii_digit).collect();
+        if digits.len() < 4 {
+            return None;
+        }
+        let field = |start: usize, len: usize, default: u32| -> Option<u32> {
+            match digits.get(start..start + len) {
+                Some(field) => field.parse().ok(),
+                None => Some(default),
+            }
+        };
+        let date = NaiveDate::from_ymd_opt(field(0, 4, 0)? as i32, field(4, 2, 1)?, field(6, 2, 1)?)?;
+        let time = NaiveTime::from_hms_opt(field(8, 2, 0)?, field(10, 2, 0)?, field(12, 2, 0)?)?;
+        let zone = &value[digits.len()..];
+        let offset_minutes = match zone.chars().next() {
+            Some(sign @ ('+' | '-')) => {
+                let parts: Vec<i32> = zone[1..].split('\'').filter_map(|part| part.parse().ok()).collect();
+                let minutes = parts.first().copied().unwrap_or(0) * 60 + parts.get(1).copied().unwrap_or(0);
+                if sign == '-' { -minutes } else { minutes }
+            }
+            _ => 0,
+        };
+        Some(date.and_time(time).and_utc() - chrono::Duration::minutes(offset_minutes as i64))
+    }
+
+    // Ages are measured from the newest dated document, so the same collection
+    // always ranks the same way. Undated documents get the minimum weight, and
+    // a collection without any dates is left unweighted.
+    fn recency_weights(weighting: &RecencyWeighting, dates: &[(String, Option<DateTime<Utc>>)]) -> HashMap<String, f64> {
+        let Some(newest) = dates.iter().filter_map(|(_, date)| *date).max() else {
+            warn!("No document has a date, so recency weighting is skipped");
+            return HashMap::new();
+        };
+        let min_weight = weighting.min_weight.clamp(0.0, 1.0);
+        let scale = weighting.scale_days.max(f64::MIN_POSITIVE);
+        dates.iter().map(|(document, date)| {
+            let weight = match date {
+                Some(date) => {
+                    let age_days = (newest - *date).num_seconds() as f64 / 86_400.0;
+                    let weight = match weighting.decay {
+                        RecencyDecay::Exponential => 0.5f64.powf(age_days / scale),
+                        RecencyDecay::Linear => 1.0 - (1.0 - min_weight) * age_days / scale,
+                        RecencyDecay::Step if age_days <= scale => 1.0,
+                        RecencyDecay::Step => min_weight,
+                    };
+                    weight.max(min_weight)
+                }
+                None => min_weight,
+            };
+            (document.clone(), weight)
+        }).collect()
+    }
+
+    fn paragraph_score(&self, document: &str, text: &str, query: &Query) -> f64 {
+        let score = match self.options.scoring_mode {
+            ScoringMode::Keyword => {
+                let proximity_bonus = match self.options.proximity_window {
+                    Some(window) => {
+                        let keywords: Vec<String> = query.persona_keywords.iter().chain(&query.task_keywords).cloned().collect();
+                        scoring::proximity_pairs(&query.fold(text), &keywords, window, query.case_folding) as f64 * self.options.proximity_bonus
+                    }
+                    None => 0.0,
+                };
+                self.keyword_score(text, query) + proximity_bonus
+            }
+            ScoringMode::Phrase => scoring::phrase_overlap(&query.phrase_tokens, text, query.case_folding),
+            ScoringMode::TfIdf => Self::tf_idf_score(text, query),
+        };
+        let weight = query.recency_weights.get(document).copied().unwrap_or(1.0);
+        (score + self.title_boost(document, text, query)) * weight
+    }
+
+    // A topicality prior: paragraphs echoing their document's title score higher.
+    fn title_boost(&self, document: &str, text: &str, query: &Query) -> f64 {
+        let Some(title_keywords) = query.title_keywords.get(document) else {
+            return 0.0;
+        };
+        let folded = query.fold(text);
+        let hits = title_keywords.iter()
+            .filter(|k| scoring::contains_word(&folded, k))
+            .collect::<HashSet<_>>()
+            .len();
+        hits as f64 * self.options.title_term_boost
+    }
+
+    // Sums 1 + ln(tf) for each distinct keyword in the text, weighted by its
+    // IDF. Before the IDFs are known every keyword weighs 1.
+    fn tf_idf_score(text: &str, query: &Query) -> f64 {
+        let folded = query.fold(text);
+        query.persona_keywords.iter().chain(&query.task_keywords)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|keyword| {
+                let tf = scoring::word_matches(&folded, keyword).count();
+                if tf == 0 {
+                    return 0.0;
+                }
+                let idf = if query.keyword_idf.is_empty() { 1.0 } else { query.keyword_idf.get(keyword).copied().unwrap_or(0.0) };
+                (1.0 + (tf as f64).ln()) * idf
+            })
+            .sum()
+    }
+
+    fn keyword_score(&self, text: &str, query: &Query) -> f64 {
+        let text_lower = query.fold(text);
+        let persona_hits = query.persona_keywords.iter().filter(|k| scoring::contains_word(&text_lower, k));
+        let task_hits = query.task_keywords.iter().filter(|k| scoring::contains_word(&text_lower, k));
+        let phrase_hits = query.phrase_keywords.iter().filter(|phrase| scoring::contains_phrase(&text_lower, phrase)).count();
+        let phrase_score = phrase_hits as f64 * self.options.phrase_weight;
+        phrase_score + match self.options.keyword_overlap {
+            KeywordOverlap::Additive => (persona_hits.count() + task_hits.count()) as f64,
+            KeywordOverlap::DedupAcross => persona_hits.chain(task_hits).collect::<HashSet<_>>().len() as f64,
+            KeywordOverlap::Weighted(shared_weight) => {
+                let persona_hits: HashSet<_> = persona_hits.collect();
+                let task_hits: HashSet<_> = task_hits.collect();
+                persona_hits.union(&task_hits)
+                    .map(|k| if persona_hits.contains(k) && task_hits.contains(k) { shared_weight } else { 1.0 })
+                    .sum()
+            }
+        }
+    }
+
+    fn rank_sections(&self, sections: &mut Vec<ExtractedSection>, analysis: &[SubsectionAnalysis], query: &Query) {
+        let mut scored: Vec<(f64, usize, ExtractedSection)> = sections.drain(..).enumerate().map(|(position, mut section)| {
+            let mut score = 0.0;
+            let mut term_frequencies = BTreeMap::new();
+            for analyzed_part in analysis {
+                if analyzed_part.document == section.document && analyzed_part.page_number == section.page_number {
+                    score += self.paragraph_score(&analyzed_part.document, &analyzed_part.refined_text, query);
+                    if self.options.section_term_frequencies {
+                        Self::count_keywords(&analyzed_part.refined_text, query, &mut term_frequencies);
+                    }
+                }
+            }
+            if self.options.section_term_frequencies {
+                section.term_frequencies = Some(term_frequencies);
+            }
+            (score * section.heading_level.map_or(1.0, |level| self.heading_level_weight(level)), position, section)
+        }).collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| self.tie_break(&a.2, &b.2)));
+        // Sections tied at the cutoff are already ordered by `tie_break`, so the
+        // first of them make it in.
+        if let Some(top) = self.options.top_sections {
+            scored.truncate(top);
+        }
+        for (i, (_, _, section)) in scored.iter_mut().enumerate() {
+            section.importance_rank = (i + 1) as u32;
+        }
+        // Extraction order follows the input's documents, their pages and the
+        // headings' places on each page.
+        if self.options.section_ordering == SectionOrdering::ByPosition {
+            scored.sort_by_key(|(_, position, _)| *position);
+        }
+        sections.extend(scored.into_iter().map(|(_, _, section)| section));
+    }
+
+    // Adds how often each persona or task keyword occurs in `text`.
+    fn count_keywords(text: &str, query: &Query, counts: &mut BTreeMap<String, u32>) {
+        let text_lower = query.fold(text);
+        // A keyword in both lists is still counted once per occurrence.
+        for keyword in query.persona_keywords.iter().chain(&query.task_keywords).collect::<HashSet<_>>() {
+            let occurrences = scoring::word_matches(&text_lower, keyword).count() as u32;
+            if occurrences > 0 {
+                *counts.entry(keyword.clone()).or_default() += occurrences;
+            }
+        }
+    }
+
+    // Orders equally scored sections by the configured keys in turn; sections
+    // equal on every key keep their extraction order.
+    fn tie_break(&self, a: &ExtractedSection, b: &ExtractedSection) -> Ordering {
+        self.options.tie_break.iter().fold(Ordering::Equal, |order, key| {
+            order.then_with(|| match key {
+                TieBreakKey::Document => a.document.cmp(&b.document),
+                TieBreakKey::PageNumber => a.page_number.cmp(&b.page_number),
+                TieBreakKey::Title => a.section_title.cmp(&b.section_title),
+                TieBreakKey::HeadingLevel => a.heading_level.cmp(&b.heading_level),
+            })
+        })
+    }
+
+    fn attach_bboxes(subsections: &mut [SubsectionAnalysis], page_text: &str, layout: &PageLayout) {
+        for subsection in subsections {
+            if let Some(start) = page_text.find(&subsection.refined_text) {
+                subsection.bbox = layout.bbox_for_span(page_text, start, start + subsection.refined_text.len());
+            }
+        }
+    }
+
+    // Files each subsection under the last heading that starts before it on the
+    // page. Subsections above the page's first heading get no parent.
+    fn attach_parent_sections(subsections: &mut [SubsectionAnalysis], page_text: &str, headings: &[(usize, String)]) {
+        for subsection in subsections {
+            if let Some(start) = page_text.find(&subsection.refined_text) {
+                subsection.parent_section_title = headings.iter()
+                    .filter(|(heading_start, _)| *heading_start <= start)
+                    .max_by_key(|(heading_start, _)| *heading_start)
+                    .map(|(_, title)| title.clone());
+            }
+        }
+    }
+
+    fn attach_list_items(subsections: &mut [SubsectionAnalysis], layout: &PageLayout) {
+        let items = layout.list_items();
+        for subsection in subsections {
+            subsection.list_items = items.iter()
+                .filter(|(line, _)| subsection.refined_text.contains(line.as_str()))
+                .map(|(_, item)| item.clone())
+                .collect();
+        }
+    }
+
+    fn rank_subsections(&self, subsections: &mut Vec<SubsectionAnalysis>, query: &Query) {
+        let mut scored: Vec<(f64, SubsectionAnalysis)> = subsections.drain(..)
+            .map(|subsection| (self.paragraph_score(&subsection.document, &subsection.refined_text, query), subsection))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        subsections.extend(scored.into_iter().map(|(_, subsection)| subsection));
+    }
+
+    // Keeps the `top` highest-scoring subsections in their current order. Among
+    // equal scores at the cutoff, the earlier subsections are kept.
+    fn keep_top_subsections(&self, subsections: &mut Vec<SubsectionAnalysis>, top: usize, query: &Query) {
+        if subsections.len() <= top {
+            return;
+        }
+        let mut scored: Vec<(usize, f64)> = subsections.iter()
+            .map(|subsection| self.paragraph_score(&subsection.document, &subsection.refined_text, query))
+            .enumerate()
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        let kept: HashSet<usize> = scored.into_iter().take(top).map(|(i, _)| i).collect();
+        *subsections = std::mem::take(subsections).into_iter()
+            .enumerate()
+            .filter(|(i, _)| kept.contains(i))
+            .map(|(_, subsection)| subsection)
+            .collect();
+    }
+
+    // The number of distinct persona keywords and task keywords in the text.
+    fn split_scores(text: &str, query: &Query) -> (f64, f64) {
+        let folded = query.fold(text);
+        let hits = |keywords: &[String]| keywords.iter()
+            .filter(|k| scoring::contains_word(&folded, k))
+            .collect::<HashSet<_>>()
+            .len() as f64;
+        (hits(&query.persona_keywords), hits(&query.task_keywords))
+    }
+
+    fn explain_match(&self, document: &str, text: &str, query: &Query) -> MatchExplanation {
+        let folded = query.fold(text);
+        // Positions are character offsets into the case-folded (and, with
+        // `stemming`, stemmed) text.
+        let matches = |keywords: &[String]| -> Vec<KeywordMatch> {
+            keywords.iter()
+                .filter_map(|keyword| {
+                    let positions: Vec<usize> = scoring::word_matches(&folded, keyword)
+                        .map(|i| folded[..i].chars().count())
+                        .collect();
+                    (!positions.is_empty()).then(|| KeywordMatch { keyword: keyword.clone(), positions })
+                })
+                .collect()
+        };
+        let persona_matches = matches(&query.persona_keywords);
+        let task_matches = matches(&query.task_keywords);
+        let (persona_score, task_score) = Self::split_scores(text, query);
+        let score = self.paragraph_score(document, text, query);
+        let list = |found: &[KeywordMatch]| found.iter().map(|m| m.keyword.as_str()).collect::<Vec<_>>().join(", ");
+        let summary = format!(
+            "Matched persona keywords [{}] and task keywords [{}]; score {:.2} ({} persona, {} task)",
+            list(&persona_matches), list(&task_matches), score, persona_score, task_score
+        );
+        MatchExplanation {
+            summary,
+            persona_score,
+            task_score,
+            score,
+            persona_matches,
+            task_matches,
+        }
+    }
+
+    // Keeps the sentences with the most distinct persona and task keywords,
+    // earlier ones first among equals, in their original order, while there
+    // are at most `max_sentences` of them and they fit in `max_chars`. Sentences
+    // from one passage are joined by a space, and passages by a blank line.
+    fn summarize(text: &str, max_sentences: Option<usize>, max_chars: Option<usize>, query: &Query) -> String {
+        let sentences: Vec<(usize, &str)> = text.split("\n\n").enumerate()
+            .flat_map(|(passage, paragraph)| Self::sentences(paragraph).into_iter().map(move |sentence| (passage, sentence)))
+            .collect();
+        let max_sentences = max_sentences.unwrap_or(usize::MAX);
+        let max_chars = max_chars.unwrap_or(usize::MAX);
+        if sentences.len() <= max_sentences && text.chars().count() <= max_chars {
+            return text.to_string();
+        }
+        let mut scored: Vec<(usize, f64)> = sentences.iter()
+            .map(|(_, sentence)| {
+                let (persona, task) = Self::split_scores(sentence, query);
+                persona + task
+            })
+            .enumerate()
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        // The best sentence is kept even when it alone is over `max_chars`; a
+        // sentence that doesn't fit makes room for shorter, lower-scoring ones.
+        let mut kept: Vec<usize> = Vec::new();
+        let mut chars = 0;
+        for (i, _) in scored {
+            if kept.len() == max_sentences {
+                break;
+            }
+            let length = sentences[i].1.chars().count();
+            // Counting each separator as a blank line keeps the summary in budget.
+            let separator = if kept.is_empty() { 0 } else { 2 };
+            if separator > 0 && chars + separator + length > max_chars {
+                continue;
+            }
+            chars += separator + length;
+            kept.push(i);
+        }
+        kept.sort_unstable();
+        let mut summary = String::new();
+        for (n, &i) in kept.iter().enumerate() {
+            if n > 0 {
+                summary.push_str(if sentences[kept[n - 1]].0 == sentences[i].0 { " " } else { "\n\n" });
+            }
+            summary.push_str(sentences[i].1);
+        }
+        summary
+    }
+
+    // A sentence ends at `.`, `!` or `?`, with any closing quotes or brackets,
+    // followed by whitespace, so "3.5" and "e.g.," don't end one.
+    fn sentences(text: &str) -> Vec<&str> {
+        static SENTENCE_BOUNDARY: OnceLock<Regex> = OnceLock::new();
+        let boundary = SENTENCE_BOUNDARY.get_or_init(|| Regex::new(r#"[.!?]+["')\]]*\s+"#).unwrap());
+        let mut sentences = Vec::new();
+        let mut start = 0;
+        for m in boundary.find_iter(text) {
+            sentences.push(&text[start..m.start() + m.as_str().trim_end().len()]);
+            start = m.end();
+        }
+        sentences.push(&text[start..]);
+        sentences.into_iter().map(str::trim).filter(|sentence| !sentence.is_empty()).collect()
+    }
+
+    // With `merge_adjacent`, consecutive relevant paragraphs on a page become
+    // one subsection, keeping the blank line between them.
+    fn find_relevant_content(
+        &self,
+        doc_name: &str,
+        page_texts: &[(usize, String)],
+        query: &Query,
+    ) -> Vec<SubsectionAnalysis> {
+        let mut relevant_sections = Vec::new();
+        for (page_num, text) in page_texts {
+            let mut passages: Vec<String> = Vec::new();
+            let mut previous_relevant = false;
+            for para in text.split("\n\n") {
+                let para_lower = query.fold(para);
+                let persona_matches = scoring::meets_requirement(&para_lower, &query.persona_keywords, self.options.persona_match);
+                let task_matches = scoring::meets_requirement(&para_lower, &query.task_keywords, self.options.task_match);
+                let phrase_matches = self.options.scoring_mode == ScoringMode::Phrase
+                    && scoring::phrase_overlap(&query.phrase_tokens, para, query.case_folding) > 0.0;
+
+                let relevant = (persona_matches && task_matches) || phrase_matches;
+                if relevant {
+                    trace!("Found relevant paragraph on page {} of {}: '{}'", page_num, doc_name, para.chars().take(100).collect::<String>());
+                    match passages.last_mut() {
+                        Some(passage) if previous_relevant && self.options.merge_adjacent => {
+                            passage.push_str("\n\n");
+                            passage.push_str(para.trim());
+                        }
+                        _ => passages.push(para.trim().to_string()),
+                    }
+                }
+                previous_relevant = relevant;
+            }
+            for refined_text in passages {
+                let (persona_score, task_score) = if self.options.split_scores {
+                    let (persona, task) = Self::split_scores(&refined_text, query);
+                    (Some(persona), Some(task))
+                } else {
+                    (None, None)
+                };
+                relevant_sections.push(SubsectionAnalysis {
+                    persona_score,
+                    task_score,
+                    document: doc_name.to_string(),
+                    explanation: self.options.explain.then(|| self.explain_match(doc_name, &refined_text, query)),
+                    refined_text,
+                    page_number: *page_num as u32,
+                    bbox: None,
+                    list_items: Vec::new(),
+                    parent_section_title: None,
+                    relevance_score: 0.0,
+                });
+            }
+        }
+
+        relevant_sections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CaseFolding, KeywordRequirement};
+    use crate::test_pdf::TestPdf;
+
+    fn input(role: &str, task: &str, documents: &[&str]) -> InputJson {
+        serde_json::from_value(serde_json::json!({
+            "challenge_info": { "challenge_id": "test", "test_case_name": "test" },
+            "documents": documents.iter().map(|filename| serde_json::json!({ "filename": filename })).collect::<Vec<_>>(),
+            "persona": { "role": role },
+            "job_to_be_done": { "task": task },
+        })).unwrap()
+    }
+
+    fn section(document: &str, title: &str, page_number: u32, level: u8) -> ExtractedSection {
+        ExtractedSection {
+            document: document.to_string(),
+            section_title: title.to_string(),
+            original_title: None,
+            importance_rank: 0,
+            page_number,
+            heading_level: Some(level),
+            term_frequencies: None,
+            section_body: None,
+        }
+    }
+
+    fn subsection(document: &str, text: &str, page_number: u32) -> SubsectionAnalysis {
+        SubsectionAnalysis {
+            document: document.to_string(),
+            refined_text: text.to_string(),
+            page_number,
+            bbox: None,
+            list_items: Vec::new(),
+            persona_score: None,
+            task_score: None,
+            explanation: None,
+            parent_section_title: None,
+            relevance_score: 0.0,
+        }
+    }
+
+    fn ranked_titles(options: ProcessingOptions, sections: Vec<ExtractedSection>, subsections: &[SubsectionAnalysis], input: &InputJson) -> Vec<String> {
+        let processor = PdfProcessor::new(options).unwrap();
+        let mut sections = sections;
+        processor.rank_sections(&mut sections, subsections, &processor.query(input, Path::new("")));
+        sections.into_iter().map(|section| section.section_title).collect()
+    }
+
+    // Writes each (filename, contents) pair into a fresh directory and analyzes
+    // the collection for `input`.
+    fn analyze_input(options: ProcessingOptions, input: &InputJson, files: &[(&str, Vec<u8>)]) -> Result<OutputJson> {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.path().join(name), contents).unwrap();
+        }
+        PdfProcessor::new(options)?.analyze(input, dir.path())
+    }
+
+    // `analyze_input` for a travel planner planning a beach trip, with the
+    // files as the input's documents in the order given.
+    fn analyze_with(options: ProcessingOptions, files: &[(&str, Vec<u8>)]) -> OutputJson {
+        let documents: Vec<&str> = files.iter().map(|(name, _)| *name).collect();
+        analyze_input(options, &input("Travel planner", "Plan a beach trip", &documents), files).unwrap()
+    }
+
+    fn text_pdf(text: &str) -> Vec<u8> {
+        TestPdf::new().plain_text(text).build()
+    }
+
+    fn refined_texts(output: &OutputJson) -> Vec<&str> {
+        output.subsection_analysis.iter().map(|subsection| subsection.refined_text.as_str()).collect()
+    }
+
+    fn section_titles(output: &OutputJson) -> Vec<&str> {
+        output.extracted_sections.iter().map(|section| section.section_title.as_str()).collect()
+    }
+
+    #[test]
+    fn heading_level_weights_rank_h1_above_an_equally_scored_h3() {
+        let input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+        let sections = || vec![section("guide.pdf", "Beach Day Trips", 1, 3), section("guide.pdf", "BEACHES", 2, 1)];
+        let text = "A beach trip for the travel planner.";
+        let subsections = [subsection("guide.pdf", text, 1), subsection("guide.pdf", text, 2)];
+
+        let flat = ranked_titles(ProcessingOptions::default(), sections(), &subsections, &input);
+        assert_eq!(flat, ["Beach Day Trips", "BEACHES"]);
+        let weighted = ProcessingOptions { heading_level_weights: vec![2.0, 1.5, 1.0], ..Default::default() };
+        assert_eq!(ranked_titles(weighted, sections(), &subsections, &input), ["BEACHES", "Beach Day Trips"]);
+    }
+
+    #[test]
+    fn heading_level_is_only_serialized_when_levels_are_configured() {
+        let first_section = |options: ProcessingOptions| {
+            let output = analyze_with(options, &[("guide.pdf", text_pdf("BEACH TRIPS\n\nA beach trip for the travel planner.\n"))]);
+            serde_json::to_value(&output).unwrap()["extracted_sections"][0].clone()
+        };
+
+        let section = first_section(ProcessingOptions::default());
+        assert_eq!(section["section_title"], "BEACH TRIPS");
+        assert!(section.get("heading_level").is_none());
+        let section = first_section(ProcessingOptions { max_heading_level: Some(2), ..Default::default() });
+        assert_eq!(section["heading_level"], 1);
+    }
+
+    #[test]
+    fn normalize_title_strips_numbering_and_trailing_colon() {
+        let rules = TitleNormalization { strip_numbering: true, strip_trailing_punctuation: true, ..Default::default() };
+        assert_eq!(PdfProcessor::normalize_title("3. Overview:", &rules), "Overview");
+        assert_eq!(PdfProcessor::normalize_title("2.1 Getting   Started", &rules), "Getting   Started");
+        assert_eq!(PdfProcessor::normalize_title("3. Overview:", &TitleNormalization::default()), "3. Overview:");
+    }
+
+    #[test]
+    fn normalize_title_collapses_whitespace_and_changes_case() {
+        let rules = TitleNormalization { collapse_whitespace: true, case: TitleCase::Title, ..Default::default() };
+        assert_eq!(PdfProcessor::normalize_title("  BUDGET\n  tips ", &rules), "Budget Tips");
+        let rules = TitleNormalization { case: TitleCase::Lower, ..Default::default() };
+        assert_eq!(PdfProcessor::normalize_title("Budget Tips", &rules), "budget tips");
+    }
+
+    #[test]
+    fn original_title_is_kept_when_normalization_changes_it() {
+        let title_normalization = TitleNormalization {
+            strip_numbering: true,
+            strip_trailing_punctuation: true,
+            keep_original: true,
+            ..Default::default()
+        };
+        let options = ProcessingOptions { title_normalization, ..Default::default() };
+        let output = analyze_with(options, &[(
+            "guide.pdf",
+            text_pdf("1. Beach Trips:\n\nA beach trip for the travel planner.\n\x0cHOTELS\n\nA hotel for the travel planner's beach trip.\n"),
+        )]);
+        let titles: Vec<(&str, Option<&str>)> = output.extracted_sections.iter()
+            .map(|section| (section.section_title.as_str(), section.original_title.as_deref()))
+            .collect();
+        // A title the rules leave alone has no original to report.
+        assert_eq!(titles, [("Beach Trips", Some("1. Beach Trips")), ("HOTELS", None)]);
+    }
+
+    #[test]
+    fn vertical_moves_become_line_and_paragraph_breaks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("guide.pdf");
+        TestPdf::new()
+            .page(concat!(
+                "BT /F1 12 Tf 72 700 Td (Beaches near Nice) Tj 0 -14 Td (are close to the station.) Tj\n",
+                "0 -30 Td (Markets open early.) Tj ET\n",
+                "BT /F1 12 Tf 14 TL 72 500 Td (Book hotels ahead) Tj T* (in summer.) Tj T* T* (Trains run hourly.) Tj ET",
+            ))
+            .write(&path);
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let extracted = processor.extract_pdf_text(&path, None, None, false).unwrap();
+        let paragraphs: Vec<&str> = extracted.page_texts[0].1.split("\n\n").collect();
+        assert_eq!(paragraphs.len(), 4, "{:?}", extracted.page_texts);
+        assert!(paragraphs[0].starts_with("Beaches near Nice") && paragraphs[0].ends_with("are close to the station."));
+        assert_eq!(paragraphs[1], "Markets open early.");
+        assert!(paragraphs[2].starts_with("Book hotels ahead") && paragraphs[2].ends_with("in summer."));
+        assert_eq!(paragraphs[3], "Trains run hourly.");
+    }
+
+    #[test]
+    fn built_in_heading_patterns_stop_at_line_breaks() {
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let text = "File Type\n\nAny format\n\nThe converter accepts any format you can print.";
+        let titles: Vec<String> = processor.extract_headings_from_page(text, Language::English)
+            .into_iter().map(|heading| heading.title).collect();
+        assert!(titles.contains(&"File Type".to_string()), "{:?}", titles);
+        assert!(titles.iter().all(|title| !title.contains('\n')), "{:?}", titles);
+    }
+
+    #[test]
+    fn cleaned_paragraphs_become_separate_subsections() {
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let raw = "Beach   trips for the\ntravel  planner.\n\n\nThe weather is mild.\n\nA second beach  trip for the travel planner.";
+        let cleaned = processor.clean_extracted_text(raw, false);
+        assert_eq!(cleaned, "Beach trips for the travel planner.\n\nThe weather is mild.\n\nA second beach trip for the travel planner.");
+
+        let input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+        let query = processor.query(&input, Path::new(""));
+        let subsections = processor.find_relevant_content("guide.pdf", &[(1, cleaned)], &query);
+        let texts: Vec<&str> = subsections.iter().map(|subsection| subsection.refined_text.as_str()).collect();
+        assert_eq!(texts, ["Beach trips for the travel planner.", "A second beach trip for the travel planner."]);
+    }
+
+    #[test]
+    fn configured_heading_patterns_replace_the_built_in_ones() {
+        let options = ProcessingOptions {
+            heading_patterns: Some(vec![r"(?m)^(§\d+\s+\p{Lu}.*)$".to_string()]),
+            ..Default::default()
+        };
+        let processor = PdfProcessor::new(options).unwrap();
+        let text = "OVERVIEW\n\n§3 Introduction\n\nThe guide starts here.";
+        for language in [Language::English, Language::German] {
+            let titles: Vec<String> = processor.extract_headings_from_page(text, language)
+                .into_iter().map(|heading| heading.title).collect();
+            assert_eq!(titles, ["§3 Introduction"]);
+        }
+    }
+
+    #[test]
+    fn an_invalid_heading_pattern_is_an_error() {
+        let options = ProcessingOptions { heading_patterns: Some(vec!["(unclosed".to_string()]), ..Default::default() };
+        let error = PdfProcessor::new(options).err().unwrap();
+        assert!(error.to_string().contains("Invalid heading pattern \"(unclosed\""), "{}", error);
+    }
+
+    fn summary_query() -> Query {
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        processor.query(&input("Travel planner", "Plan a beach trip", &["guide.pdf"]), Path::new(""))
+    }
+
+    #[test]
+    fn summarize_keeps_the_two_most_relevant_sentences_in_order() {
+        let paragraph = "The region has a long history. The travel planner should book a beach trip early. \
+            Museums close on Mondays. Trains are frequent. A beach day suits every planner. Bakeries open at seven.";
+        let summary = PdfProcessor::summarize(paragraph, Some(2), None, &summary_query());
+        assert_eq!(summary, "The travel planner should book a beach trip early. A beach day suits every planner.");
+        assert_eq!(PdfProcessor::summarize("Short. Text.", Some(2), None, &summary_query()), "Short. Text.");
+    }
+
+    #[test]
+    fn summarize_keeps_blank_lines_between_merged_paragraphs() {
+        let passage = "Museums close on Mondays. Plan a beach trip.\n\nBakeries open at seven. The travel planner books hotels.";
+        let summary = PdfProcessor::summarize(passage, Some(2), None, &summary_query());
+        assert_eq!(summary, "Plan a beach trip.\n\nThe travel planner books hotels.");
+    }
+
+    #[test]
+    fn summarize_fits_the_character_budget() {
+        let paragraph = "The travel planner should book a beach trip well before the summer season starts. \
+            A beach trip needs sunscreen. Plan the trip. Museums close on Mondays.";
+        let summary = PdfProcessor::summarize(paragraph, None, Some(50), &summary_query());
+        assert_eq!(summary, "The travel planner should book a beach trip well before the summer season starts.");
+        let summary = PdfProcessor::summarize(paragraph, None, Some(100), &summary_query());
+        assert!(summary.chars().count() <= 100, "{}", summary);
+        assert_eq!(summary, "The travel planner should book a beach trip well before the summer season starts. Plan the trip.");
+    }
+
+    #[test]
+    fn a_page_that_fails_to_resolve_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("damaged.pdf");
+        TestPdf::new()
+            .page("BT /F1 12 Tf 72 700 Td (Page one text.) Tj ET")
+            .unresolvable_page()
+            .page("BT /F1 12 Tf 72 700 Td (Page three text.) Tj ET")
+            .write(&path);
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let extracted = processor.extract_pdf_text(&path, None, None, false).unwrap();
+        assert_eq!(extracted.page_texts, [(1, "Page one text.".to_string()), (3, "Page three text.".to_string())]);
+        let skipped: Vec<usize> = extracted.visit.skipped_pages.iter().map(|(page, _)| *page).collect();
+        assert_eq!(skipped, [2]);
+        assert!(extracted.visit.page_count_mismatch.is_none());
+    }
+
+    #[test]
+    fn skipped_pages_are_recorded_in_the_metadata() {
+        let damaged = TestPdf::new()
+            .page("BT /F1 12 Tf 72 700 Td (A beach trip for the travel planner.) Tj ET")
+            .unresolvable_page()
+            .build();
+        let output = analyze_with(ProcessingOptions::default(), &[("damaged.pdf", damaged)]);
+        let skipped: Vec<(&str, u32)> = output.metadata.skipped_pages.iter()
+            .map(|page| (page.document.as_str(), page.page_number))
+            .collect();
+        assert_eq!(skipped, [("damaged.pdf", 2)]);
+        assert_eq!(output.subsection_analysis.len(), 1);
+    }
+
+    #[test]
+    fn a_large_first_line_becomes_the_title_when_no_pattern_matches() {
+        let guide = TestPdf::new()
+            .text_page(&[
+                (700.0, 18.0, "Top 10 places to eat, ranked"),
+                (670.0, 11.0, "Every beach trip for the travel planner"),
+                (656.0, 11.0, "starts with a good lunch, so book early."),
+            ])
+            // A pattern heading wins over the larger line above it.
+            .text_page(&[
+                (700.0, 18.0, "Where to swim, by season"),
+                (670.0, 11.0, "BEACH TRIPS"),
+                (640.0, 11.0, "A beach trip for the travel planner in June."),
+            ])
+            .build();
+        let titles = |infer_titles_from_fonts| {
+            let options = ProcessingOptions { infer_titles_from_fonts, ..Default::default() };
+            let output = analyze_with(options, &[("guide.pdf", guide.clone())]);
+            output.extracted_sections.into_iter().map(|section| (section.page_number, section.section_title)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(titles(false), [(2, "BEACH TRIPS".to_string())]);
+        assert_eq!(titles(true), [(1, "Top 10 places to eat, ranked".to_string()), (2, "BEACH TRIPS".to_string())]);
+    }
+
+    #[test]
+    fn a_subsection_bbox_encloses_its_lines() {
+        let guide = TestPdf::new()
+            .text_page(&[
+                (700.0, 12.0, "Museums close on Mondays."),
+                (500.0, 12.0, "A beach trip"),
+                (486.0, 12.0, "for the travel planner."),
+            ])
+            .build();
+        let output = analyze_with(ProcessingOptions { record_bboxes: true, ..Default::default() }, &[("guide.pdf", guide.clone())]);
+        assert_eq!(output.subsection_analysis.len(), 1);
+        let bbox = output.subsection_analysis[0].bbox.as_ref().unwrap();
+        // Widths are estimated at half the font size per character.
+        assert_eq!((bbox.x, bbox.y), (72.0, 486.0));
+        assert_eq!(bbox.width, "for the travel planner.".len() as f32 * 6.0);
+        assert_eq!(bbox.height, 26.0);
+
+        let output = analyze_with(ProcessingOptions::default(), &[("guide.pdf", guide)]);
+        assert!(output.subsection_analysis[0].bbox.is_none());
+    }
+
+    #[test]
+    fn phrase_scoring_prefers_a_paragraph_echoing_the_query() {
+        let options = ProcessingOptions { scoring_mode: ScoringMode::Phrase, ..Default::default() };
+        let processor = PdfProcessor::new(options).unwrap();
+        let input = input("Travel planner", "Plan a trip of four days for a group of college friends", &["guide.pdf"]);
+        let query = processor.query(&input, Path::new(""));
+        let echo = "Plan a trip of four days for a group of college friends along the coast.";
+        let scattered = "Friends from college rarely plan. A group needs days to agree on four things for a trip.";
+        let echo_score = processor.paragraph_score("guide.pdf", echo, &query);
+        let scattered_score = processor.paragraph_score("guide.pdf", scattered, &query);
+        assert!(echo_score > scattered_score * 2.0, "{} vs {}", echo_score, scattered_score);
+    }
+
+    #[test]
+    fn page_offset_shifts_reported_page_numbers() {
+        let guide = TestPdf::new()
+            .text_page(&[(700.0, 12.0, "Cover")])
+            .text_page(&[(700.0, 12.0, "Contents")])
+            .text_page(&[(700.0, 18.0, "BEACHES"), (670.0, 12.0, "A beach trip for the travel planner.")])
+            .build();
+        let pages = |page_offset| {
+            let mut input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+            input.documents[0].page_offset = page_offset;
+            analyze_input(ProcessingOptions::default(), &input, &[("guide.pdf", guide.clone())])
+                .map(|output| (output.extracted_sections[0].page_number, output.subsection_analysis[0].page_number))
+        };
+
+        assert_eq!(pages(0).unwrap(), (3, 3));
+        assert_eq!(pages(10).unwrap(), (13, 13));
+        // The cover would be printed as page 0.
+        let error = pages(-1).unwrap_err();
+        assert!(format!("{:#}", error).contains("page_offset -1 gives page 1 of guide.pdf the printed number 0"), "{:#}", error);
+    }
+
+    // An executable standing in for pdftotext that prints `text`.
+    fn fake_ocr_command(dir: &Path, text: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join("fake_pdftotext");
+        std::fs::write(&path, format!("#!/bin/sh\necho '{}'\n", text)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn force_ocr_skips_native_extraction() {
+        let tools = tempfile::tempdir().unwrap();
+        let ocr_command = fake_ocr_command(tools.path(), "A beach trip for the travel planner, read by OCR.");
+        let scan = TestPdf::new()
+            .page("BT /F1 12 Tf 72 700 Td (A beach trip for the travel planner, from the text layer.) Tj ET")
+            .build();
+        let text = |force_ocr_globally, force_ocr_document| {
+            let options = ProcessingOptions { ocr_command: ocr_command.clone(), force_ocr: force_ocr_globally, ..Default::default() };
+            let mut input = input("Travel planner", "Plan a beach trip", &["scan.pdf"]);
+            input.documents[0].force_ocr = force_ocr_document;
+            let output = analyze_input(options, &input, &[("scan.pdf", scan.clone())]).unwrap();
+            refined_texts(&output).concat()
+        };
+
+        // The text layer is good, so only forcing OCR reaches the OCR backend.
+        assert_eq!(text(false, false), "A beach trip for the travel planner, from the text layer.");
+        assert_eq!(text(true, false), "A beach trip for the travel planner, read by OCR.");
+        assert_eq!(text(false, true), "A beach trip for the travel planner, read by OCR.");
+    }
+
+    #[test]
+    fn a_keyword_in_both_persona_and_task_counts_once_under_dedup_across() {
+        let input = input("Travel planner", "Organise travel itinerary", &["guide.pdf"]);
+        let score = |keyword_overlap| {
+            let processor = PdfProcessor::new(ProcessingOptions { keyword_overlap, ..Default::default() }).unwrap();
+            let query = processor.query(&input, Path::new(""));
+            processor.keyword_score("Travel tips for everyone.", &query)
+        };
+        assert_eq!(score(KeywordOverlap::Additive), 2.0);
+        assert_eq!(score(KeywordOverlap::DedupAcross), 1.0);
+        assert_eq!(score(KeywordOverlap::Weighted(1.5)), 1.5);
+    }
+
+    #[test]
+    fn similar_documents_are_linked_and_unrelated_ones_are_not() {
+        let files = [
+            ("beaches.pdf", text_pdf("Sandy beaches, beach clubs and coastal swimming spots near Nice for a beach trip.")),
+            ("coast.pdf", text_pdf("Coastal swimming, sandy beaches and beach clubs along the Riviera for a beach trip.")),
+            ("taxes.pdf", text_pdf("Corporate accounting rules, quarterly filings and payroll deductions.")),
+        ];
+        let links = |document_links, document_link_threshold| {
+            let options = ProcessingOptions { document_links, document_link_threshold, ..Default::default() };
+            analyze_with(options, &files).document_links.into_iter()
+                .map(|link| (link.source, link.target, link.similarity))
+                .collect::<Vec<_>>()
+        };
+
+        let linked = links(true, 0.5);
+        assert_eq!(linked.len(), 1, "{:?}", linked);
+        let (source, target, similarity) = &linked[0];
+        assert_eq!((source.as_str(), target.as_str()), ("beaches.pdf", "coast.pdf"));
+        assert!((0.5..1.0).contains(similarity), "{}", similarity);
+        assert_eq!(links(true, similarity - 1e-9).len(), 1);
+        assert!(links(true, similarity + 1e-9).is_empty());
+        assert!(links(false, 0.5).is_empty());
+    }
+
+    #[test]
+    fn selected_pages_are_the_only_ones_extracted_across_documents() {
+        let files = [("north.pdf", "north"), ("south.pdf", "south")].map(|(filename, name)| {
+            let mut pdf = TestPdf::new();
+            for page in 1..=3 {
+                pdf = pdf.text_page(&[(700.0, 12.0, &format!("Beach trip {} page {} for the travel planner.", name, page))]);
+            }
+            (filename, pdf.build())
+        });
+        let mut input = input("Travel planner", "Plan a beach trip", &["north.pdf", "south.pdf"]);
+        input.documents[1].pages = Some(vec![2, 3]);
+        // Page 9 is out of range for both documents and is skipped.
+        let options = ProcessingOptions { pages: Some(vec![1, 2, 9]), ..Default::default() };
+        let output = analyze_input(options, &input, &files).unwrap();
+
+        let mut texts = refined_texts(&output);
+        texts.sort();
+        // South only keeps page 2, the one both selections include.
+        assert_eq!(texts, [
+            "Beach trip north page 1 for the travel planner.",
+            "Beach trip north page 2 for the travel planner.",
+            "Beach trip south page 2 for the travel planner.",
+        ]);
+    }
+
+    #[test]
+    fn keywords_close_together_outscore_the_same_keywords_far_apart() {
+        let input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+        let filler = "the harbour is quiet in winter and the cafes close early so ".repeat(4);
+        let close = format!("A beach trip is easy from town. {}", filler);
+        let far = format!("A beach day is easy from town. {}Every trip starts at the station.", filler);
+        let options = ProcessingOptions { proximity_window: Some(5), proximity_bonus: 2.0, ..Default::default() };
+        let processor = PdfProcessor::new(options).unwrap();
+        let query = processor.query(&input, Path::new(""));
+
+        assert_eq!(processor.keyword_score(&close, &query), processor.keyword_score(&far, &query));
+        // "beach" followed by "trip" is the one pair inside the window.
+        let bonus = processor.paragraph_score("guide.pdf", &close, &query) - processor.paragraph_score("guide.pdf", &far, &query);
+        assert_eq!(bonus, 2.0);
+        let plain = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        assert_eq!(plain.paragraph_score("guide.pdf", &close, &query), plain.paragraph_score("guide.pdf", &far, &query));
+
+        // The window counts tokens from one keyword to the next, inclusive.
+        let bonus_at = |gap: usize| {
+            let text = format!("beach {}trip", "day ".repeat(gap - 1));
+            processor.paragraph_score("guide.pdf", &text, &query) - processor.keyword_score(&text, &query)
+        };
+        assert_eq!(bonus_at(5), 2.0);
+        assert_eq!(bonus_at(6), 0.0);
+    }
+
+    #[test]
+    fn coverage_counts_the_pages_that_contributed_results() {
+        let pages = [
+            "A beach trip for the travel planner.",
+            "Quarterly filings and payroll deductions.",
+            "Another beach trip for the travel planner, along the coast.",
+            "Corporate accounting rules.",
+        ];
+        let guide = pages.iter().fold(TestPdf::new(), |pdf, text| pdf.text_page(&[(700.0, 12.0, text)])).build();
+        let payroll = TestPdf::new().text_page(&[(700.0, 12.0, "Payroll deductions for employees.")]).build();
+        let options = ProcessingOptions { report_coverage: true, ..Default::default() };
+        let output = analyze_with(options, &[("guide.pdf", guide), ("payroll.pdf", payroll)]);
+
+        let coverage = &output.metadata.document_coverage;
+        assert_eq!(coverage.len(), 2);
+        assert_eq!((coverage[0].document.as_str(), coverage[0].pages_extracted, coverage[0].pages_with_results), ("guide.pdf", 4, 2));
+        assert_eq!(coverage[0].page_coverage, 0.5);
+        // Pages 1 and 3 are kept whole as refined text.
+        let length = |text: &str| text.chars().count() as f64;
+        let total: f64 = pages.iter().map(|text| length(text)).sum();
+        assert_eq!(coverage[0].text_coverage, (length(pages[0]) + length(pages[2])) / total);
+        assert_eq!((coverage[1].document.as_str(), coverage[1].pages_extracted, coverage[1].pages_with_results), ("payroll.pdf", 1, 0));
+        assert_eq!((coverage[1].page_coverage, coverage[1].text_coverage), (0.0, 0.0));
+    }
+
+    #[test]
+    fn each_document_in_a_mixed_collection_uses_its_own_language() {
+        let english = ("english.pdf", text_pdf("Beach Trips\n\nA beach trip for the travel planner and the family."));
+        let german = ("german.pdf", text_pdf("Übersicht über Strandausflüge\n\nDer beach trip ist für die Familie und den travel planner."));
+        let output = analyze_with(ProcessingOptions::default(), &[english.clone(), german]);
+
+        let languages: Vec<(&str, Language)> = output.metadata.document_languages.iter()
+            .map(|doc| (doc.document.as_str(), doc.language))
+            .collect();
+        assert_eq!(languages, [("english.pdf", Language::English), ("german.pdf", Language::German)]);
+        assert!(output.metadata.language_note.is_some());
+        // Only the German patterns recognize the German heading.
+        assert!(output.extracted_sections.iter()
+            .any(|section| section.document == "german.pdf" && section.section_title == "Übersicht über Strandausflüge"),
+            "{:?}", output.extracted_sections);
+
+        let output = analyze_with(ProcessingOptions::default(), &[english]);
+        assert!(output.metadata.document_languages.is_empty() && output.metadata.language_note.is_none());
+    }
+
+    #[test]
+    fn explain_reports_the_filtered_and_stemmed_keywords() {
+        let input = input("Travel planner", "Planning the beach trips", &["guide.pdf"]);
+        let keywords = |options: ProcessingOptions| {
+            let output = analyze_input(options, &input, &[("guide.pdf", text_pdf("Planning beach trips for travel planners."))]).unwrap();
+            output.metadata.effective_keywords.map(|keywords| (keywords.persona, keywords.task))
+        };
+
+        assert!(keywords(ProcessingOptions::default()).is_none());
+        let (persona, task) = keywords(ProcessingOptions { explain: true, ..Default::default() }).unwrap();
+        assert_eq!(persona, ["travel", "planner"]);
+        assert_eq!(task, ["planning", "beach", "trips"]);
+        let options = ProcessingOptions {
+            explain: true,
+            stemming: true,
+            extra_stopwords: vec!["beach".to_string()],
+            ..Default::default()
+        };
+        let (persona, task) = keywords(options).unwrap();
+        assert_eq!(persona, ["travel", "planner"]);
+        assert_eq!(task, ["plan", "trip"]);
+    }
+
+    #[test]
+    fn streaming_matches_batch_extraction() {
+        let topics = ["Beaches", "Markets", "Museums", "Trains", "Hotels", "Food"];
+        let mut pdf = TestPdf::new();
+        for page in 1..=12 {
+            let topic = topics[page % topics.len()];
+            pdf = pdf.text_page(&[
+                (720.0, 18.0, &topic.to_uppercase()),
+                (690.0, 12.0, &format!("A beach trip for the travel planner, with {} on day {}.", topic.to_lowercase(), page)),
+                (600.0, 12.0, &format!("Quarterly filings and payroll deductions, page {}.", page)),
+            ]);
+        }
+        let guide = pdf.build();
+        let results = |streaming| {
+            let options = ProcessingOptions { streaming, report_coverage: true, ..Default::default() };
+            let output = analyze_with(options, &[("guide.pdf", guide.clone())]);
+            let mut output = serde_json::to_value(&output).unwrap();
+            output["metadata"]["processing_timestamp"].take();
+            output
+        };
+
+        let batch = results(false);
+        assert_eq!(batch["extracted_sections"].as_array().unwrap().len(), 12);
+        assert_eq!(results(true), batch);
+    }
+
+    #[test]
+    fn the_first_backend_in_the_configured_order_that_yields_text_is_used() {
+        let tools = tempfile::tempdir().unwrap();
+        let ocr_command = fake_ocr_command(tools.path(), "A beach trip for the travel planner, from pdftotext.");
+        let first_text = |document: &str, backend_order: Vec<Backend>| {
+            let contents = match document {
+                "guide.pdf" => TestPdf::new()
+                    .page("BT /F1 12 Tf 72 700 Td (A beach trip for the travel planner, from the text layer.) Tj ET")
+                    .build(),
+                _ => b"not a pdf".to_vec(),
+            };
+            let options = ProcessingOptions { ocr_command: ocr_command.clone(), backend_order, ..Default::default() };
+            let output = analyze_with(options, &[(document, contents)]);
+            output.subsection_analysis.first().map(|subsection| subsection.refined_text.clone())
+        };
+
+        assert_eq!(first_text("guide.pdf", vec![Backend::Native, Backend::Poppler]).unwrap(), "A beach trip for the travel planner, from the text layer.");
+        assert_eq!(first_text("guide.pdf", vec![Backend::Poppler, Backend::Native]).unwrap(), "A beach trip for the travel planner, from pdftotext.");
+        assert_eq!(first_text("broken.pdf", vec![Backend::Native, Backend::Poppler]).unwrap(), "A beach trip for the travel planner, from pdftotext.");
+        assert_eq!(first_text("broken.pdf", vec![Backend::Native]), None);
+    }
+
+    #[test]
+    fn section_term_frequencies_count_keywords_across_the_sections_subsections() {
+        let input = input("Travel planner", "Plan a beach trip for travel", &["guide.pdf"]);
+        let options = ProcessingOptions { section_term_frequencies: true, ..Default::default() };
+        let processor = PdfProcessor::new(options).unwrap();
+        let mut sections = vec![section("guide.pdf", "Beaches", 1, 1), section("guide.pdf", "Payroll", 2, 1)];
+        let subsections = [
+            subsection("guide.pdf", "A beach trip: the beach is a short trip away.", 1),
+            subsection("guide.pdf", "Travel light to the beach.", 1),
+            subsection("guide.pdf", "Quarterly filings and payroll deductions.", 2),
+        ];
+        processor.rank_sections(&mut sections, &subsections, &processor.query(&input, Path::new("")));
+
+        let frequencies = |title: &str| {
+            let section = sections.iter().find(|section| section.section_title == title).unwrap();
+            section.term_frequencies.clone().unwrap().into_iter().collect::<Vec<_>>()
+        };
+        let counts = |pairs: &[(&str, u32)]| pairs.iter().map(|&(term, count)| (term.to_string(), count)).collect::<Vec<_>>();
+        assert_eq!(frequencies("Beaches"), counts(&[("beach", 3), ("travel", 1), ("trip", 2)]));
+        assert_eq!(frequencies("Payroll"), []);
+    }
+
+    #[test]
+    fn full_case_folding_matches_strasse_against_strasse_with_sharp_s() {
+        let input = input("Stadtplaner", "STRASSE sanieren", &["plan.pdf"]);
+        let score = |case_folding| {
+            let processor = PdfProcessor::new(ProcessingOptions { case_folding, ..Default::default() }).unwrap();
+            processor.keyword_score("Die Straße wird gesperrt.", &processor.query(&input, Path::new("")))
+        };
+        assert_eq!(score(CaseFolding::Lowercase), 0.0);
+        assert_eq!(score(CaseFolding::Full), 1.0);
+    }
+
+    #[test]
+    fn nested_list_items_get_levels_from_their_indentation() {
+        let line = |x: u32, y: u32, text: &str| format!("BT /F1 12 Tf {} {} Td ({}) Tj ET\n", x, y, text);
+        let content = [
+            line(72, 700, "Beach trip packing for the travel planner:"),
+            line(72, 686, "- Beach gear"),
+            line(90, 672, "- Towels for the trip"),
+            line(90, 658, "- Sunscreen"),
+            line(72, 644, "- Travel documents"),
+            line(91, 630, "1. Passport"),
+        ].concat();
+        let guide = TestPdf::new().page(&content).build();
+        let items = |detect_lists| {
+            let output = analyze_with(ProcessingOptions { detect_lists, ..Default::default() }, &[("guide.pdf", guide.clone())]);
+            output.subsection_analysis.into_iter()
+                .flat_map(|subsection| subsection.list_items)
+                .map(|item| (item.text, item.level))
+                .collect::<Vec<_>>()
+        };
+        let expected = |items: &[(&str, u8)]| items.iter().map(|&(text, level)| (text.to_string(), level)).collect::<Vec<_>>();
+
+        // A numbered item nests by its indentation like a bulleted one.
+        assert_eq!(items(true), expected(&[
+            ("Beach gear", 1),
+            ("Towels for the trip", 2),
+            ("Sunscreen", 2),
+            ("Travel documents", 1),
+            ("Passport", 2),
+        ]));
+        assert!(items(false).is_empty());
+    }
+
+    #[test]
+    fn bundle_entries_pair_sections_with_their_best_subsection_on_the_same_page() {
+        let input = input("Travel planner", "Plan a beach trip", &["north.pdf", "south.pdf"]);
+        let processor = PdfProcessor::new(ProcessingOptions { report_bundle: true, ..Default::default() }).unwrap();
+        let mut sections = vec![section("north.pdf", "Beaches", 1, 1), section("north.pdf", "Index", 4, 1)];
+        sections[0].importance_rank = 1;
+        sections[1].importance_rank = 2;
+        let subsections = [
+            subsection("north.pdf", "The harbour is quiet.", 1),
+            subsection("north.pdf", "A beach trip for the travel planner.", 1),
+            subsection("north.pdf", "A beach trip for the travel planner, with a beach plan.", 2),
+            subsection("south.pdf", "A beach trip for the travel planner, with a beach plan.", 1),
+        ];
+        let bundle = processor.bundle_report(&sections, &subsections, &processor.query(&input, Path::new("")));
+
+        assert_eq!(bundle.len(), 1);
+        assert_eq!((bundle[0].rank, bundle[0].section_title.as_str()), (1, "Beaches"));
+        assert_eq!(bundle[0].excerpt, "A beach trip for the travel planner.");
+        assert_eq!((bundle[0].source.document.as_str(), bundle[0].source.page_number), ("north.pdf", 1));
+    }
+
+    #[test]
+    fn every_page_in_the_tree_is_extracted_when_the_declared_count_is_wrong() {
+        let files = [("undercount.pdf", 2), ("overcount.pdf", 5), ("exact.pdf", 3)].map(|(name, declared_count)| {
+            let mut pdf = TestPdf::new().declared_count(declared_count);
+            for page in 1..=3 {
+                pdf = pdf.text_page(&[(700.0, 12.0, &format!("A beach trip for the travel planner, page {}.", page))]);
+            }
+            (name, pdf.build())
+        });
+        let output = analyze_with(ProcessingOptions::default(), &files);
+
+        for document in ["undercount.pdf", "overcount.pdf", "exact.pdf"] {
+            let mut pages: Vec<u32> = output.subsection_analysis.iter()
+                .filter(|subsection| subsection.document == document)
+                .map(|subsection| subsection.page_number)
+                .collect();
+            pages.sort();
+            assert_eq!(pages, [1, 2, 3], "{}", document);
+        }
+        let mismatches: Vec<(&str, u32, u32)> = output.metadata.page_count_mismatches.iter()
+            .map(|mismatch| (mismatch.document.as_str(), mismatch.declared, mismatch.actual))
+            .collect();
+        assert_eq!(mismatches, [("undercount.pdf", 2, 3), ("overcount.pdf", 5, 3)]);
+    }
+
+    #[test]
+    fn max_keywords_keeps_the_most_repeated_task_keywords() {
+        let task = "Plan a beach trip with beach hotels, beach bars, sailing trips, museum visits, \
+                    market tours, vineyard tours, cycling routes and beach picnics for the trip";
+        let input = input("Travel planner", task, &["guide.pdf"]);
+        let task_keywords = |max_keywords| {
+            let processor = PdfProcessor::new(ProcessingOptions { max_keywords, ..Default::default() }).unwrap();
+            processor.query(&input, Path::new("")).task_keywords
+        };
+
+        assert_eq!(task_keywords(None).len(), 20);
+        assert_eq!(task_keywords(Some(3)), ["beach", "trip", "tours"]);
+    }
+
+    #[test]
+    fn explanations_list_the_matched_keywords_and_their_contributions() {
+        let guide = [("guide.pdf", text_pdf("Every beach trip needs a travel plan; the beach is close."))];
+        let output = analyze_with(ProcessingOptions { explain: true, ..Default::default() }, &guide);
+
+        let explanation = output.subsection_analysis[0].explanation.as_ref().unwrap();
+        let matched = |found: &[KeywordMatch]| found.iter().map(|m| (m.keyword.clone(), m.positions.clone())).collect::<Vec<_>>();
+        assert_eq!(matched(&explanation.persona_matches), [("travel".to_string(), vec![25])]);
+        assert_eq!(matched(&explanation.task_matches), [
+            ("plan".to_string(), vec![32]),
+            ("beach".to_string(), vec![6, 42]),
+            ("trip".to_string(), vec![12]),
+        ]);
+        assert_eq!((explanation.persona_score, explanation.task_score, explanation.score), (1.0, 3.0, 4.0));
+        assert!(explanation.summary.contains("persona keywords [travel] and task keywords [plan, beach, trip]"), "{}", explanation.summary);
+
+        let output = analyze_with(ProcessingOptions::default(), &guide);
+        assert!(output.subsection_analysis[0].explanation.is_none());
+    }
+
+    #[test]
+    fn subsections_below_min_relevance_are_dropped_and_the_rest_carry_their_score() {
+        let guide = [("guide.pdf", text_pdf("Every beach trip needs a travel plan.\n\nA travel beach day."))];
+        let scored = |min_relevance| {
+            let output = analyze_with(ProcessingOptions { min_relevance, ..Default::default() }, &guide);
+            output.subsection_analysis.iter()
+                .map(|subsection| (subsection.refined_text.clone(), subsection.relevance_score))
+                .collect::<Vec<_>>()
+        };
+
+        // Four distinct keywords against two.
+        assert_eq!(scored(None), [
+            ("Every beach trip needs a travel plan.".to_string(), 4.0),
+            ("A travel beach day.".to_string(), 2.0),
+        ]);
+        assert_eq!(scored(Some(3.0)), [("Every beach trip needs a travel plan.".to_string(), 4.0)]);
+    }
+
+    #[test]
+    fn form_feeds_end_paragraphs_unless_configured_as_spaces() {
+        let raw = "Beach trips for the\x0ctravel planner.\x0c\x0cThe weather is mild.";
+        let clean = |form_feed| PdfProcessor::new(ProcessingOptions { form_feed, ..Default::default() }).unwrap()
+            .clean_extracted_text(raw, false);
+        assert_eq!(clean(FormFeed::ParagraphBreak), "Beach trips for the\n\ntravel planner.\n\nThe weather is mild.");
+        assert_eq!(clean(FormFeed::Space), "Beach trips for the travel planner. The weather is mild.");
+    }
+
+    #[test]
+    fn footnotes_are_scored_apart_from_the_body_text() {
+        let guide = TestPdf::new()
+            .text_page(&[
+                (700.0, 12.0, "A beach trip for the travel planner."),
+                (686.0, 12.0, "Hotels near the sand fill up early."),
+                (400.0, 12.0, "Book the beach hotels in spring."),
+                (90.0, 8.0, "1 Beach trip prices for the travel planner rise in July."),
+                (80.0, 8.0, "2 See the annual report."),
+            ])
+            .build();
+        let options = ProcessingOptions { separate_footnotes: true, ..Default::default() };
+        let output = analyze_with(options, &[("guide.pdf", guide.clone())]);
+
+        // The irrelevant second note is dropped like an irrelevant paragraph.
+        let footnotes: Vec<(&str, u32)> = output.footnotes.iter().map(|note| (note.text.as_str(), note.page_number)).collect();
+        let note = "1 Beach trip prices for the travel planner rise in July.";
+        assert_eq!(footnotes, [(note, 1)]);
+        // The note is scored on its own text, not the page around it.
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let query = processor.query(&input("Travel planner", "Plan a beach trip", &["guide.pdf"]), Path::new(""));
+        assert_eq!(output.footnotes[0].score, processor.paragraph_score("guide.pdf", note, &query));
+        assert!(!output.subsection_analysis.is_empty());
+        assert!(output.subsection_analysis.iter().all(|subsection| !subsection.refined_text.contains("July")
+            && !subsection.refined_text.contains("annual report")));
+
+        let output = analyze_with(ProcessingOptions::default(), &[("guide.pdf", guide)]);
+        assert!(output.footnotes.is_empty());
+        assert!(output.subsection_analysis.iter().any(|subsection| subsection.refined_text.contains("July")));
+    }
+
+    #[test]
+    fn tied_sections_follow_the_tie_break_keys_whatever_their_input_order() {
+        let input = input("Travel planner", "Plan a beach trip", &["a.pdf", "b.pdf"]);
+        let sections = [("b.pdf", "Markets", 1, 2), ("a.pdf", "Museums", 2, 1), ("a.pdf", "Beaches", 2, 2), ("a.pdf", "Trains", 1, 1)];
+        let orders = [[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1]];
+        let ranked = |tie_break: Vec<TieBreakKey>| -> Vec<Vec<String>> {
+            orders.iter().map(|order| {
+                let sections = order.iter()
+                    .map(|&i| sections[i])
+                    .map(|(document, title, page, level)| section(document, title, page, level))
+                    .collect();
+                ranked_titles(ProcessingOptions { tie_break: tie_break.clone(), ..Default::default() }, sections, &[], &input)
+            }).collect()
+        };
+
+        for titles in ranked(ProcessingOptions::default().tie_break) {
+            assert_eq!(titles, ["Trains", "Beaches", "Museums", "Markets"]);
+        }
+        for titles in ranked(vec![TieBreakKey::HeadingLevel, TieBreakKey::Title]) {
+            assert_eq!(titles, ["Museums", "Trains", "Beaches", "Markets"]);
+        }
+    }
+
+    #[test]
+    fn recorded_source_hashes_match_sha256sum() {
+        let dir = tempfile::tempdir().unwrap();
+        let sha256sum = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            let output = Command::new("sha256sum").arg(&path).output().unwrap();
+            String::from_utf8(output.stdout).unwrap().split_whitespace().next().unwrap().to_string()
+        };
+        let guide = TestPdf::new().text_page(&[(700.0, 12.0, "A beach trip for the travel planner.")]).build();
+        let offtopic = TestPdf::new().text_page(&[(700.0, 12.0, "Quarterly filings and payroll deductions.")]).build();
+        // Documents are hashed whether or not anything in them matched.
+        let expected = [("guide.pdf", sha256sum("guide.pdf", &guide)), ("ledger.pdf", sha256sum("ledger.pdf", &offtopic))]
+            .map(|(document, hash)| (document.to_string(), hash));
+        let hashes = |record_source_hashes| {
+            let options = ProcessingOptions { record_source_hashes, ..Default::default() };
+            analyze_with(options, &[("guide.pdf", guide.clone()), ("ledger.pdf", offtopic.clone())]).metadata.source_hashes.into_iter()
+                .map(|hash| (hash.document, hash.sha256))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(hashes(true), expected);
+        assert!(hashes(false).is_empty());
+    }
+
+    #[test]
+    fn headings_below_max_heading_level_are_left_out() {
+        let guide = TestPdf::new()
+            .text_page(&[(700.0, 12.0, "1. Beach Trips"), (660.0, 12.0, "A beach trip for the travel planner.")])
+            .text_page(&[(700.0, 12.0, "1.1 Beach Hotels"), (660.0, 12.0, "Hotels for a beach trip.")])
+            .text_page(&[(700.0, 12.0, "1.1.1 Beach Hotel Prices"), (660.0, 12.0, "Prices for a beach trip.")])
+            .build();
+        let levels = |max_heading_level| {
+            let options = ProcessingOptions {
+                heading_patterns: Some(vec![r"(?m)^(\d+(?:\.\d+)*\.?[ \t]+[A-Z][A-Za-z \t]+)$".to_string()]),
+                max_heading_level,
+                ..Default::default()
+            };
+            let output = analyze_with(options, &[("guide.pdf", guide.clone())]);
+            let mut levels: Vec<(String, Option<u8>)> = output.extracted_sections.into_iter()
+                .map(|section| (section.section_title, section.heading_level))
+                .collect();
+            levels.sort();
+            levels
+        };
+
+        assert_eq!(levels(None).len(), 3);
+        assert_eq!(levels(Some(2)), [("1. Beach Trips".to_string(), Some(1)), ("1.1 Beach Hotels".to_string(), Some(2))]);
+    }
+
+    #[test]
+    fn a_paragraph_matching_only_task_keywords_has_no_persona_score() {
+        let input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+        let processor = PdfProcessor::new(ProcessingOptions { split_scores: true, ..Default::default() }).unwrap();
+        let query = processor.query(&input, Path::new(""));
+        assert_eq!(PdfProcessor::split_scores("A beach trip is easy from town.", &query), (0.0, 2.0));
+        assert_eq!(PdfProcessor::split_scores("The travel planner stays in town.", &query), (2.0, 0.0));
+
+        let guide = TestPdf::new().text_page(&[(700.0, 12.0, "The travel planner books the beach trip.")]).build();
+        let subsection = |split_scores| {
+            let output = analyze_with(ProcessingOptions { split_scores, ..Default::default() }, &[("guide.pdf", guide.clone())]);
+            let subsection = &output.subsection_analysis[0];
+            (subsection.persona_score, subsection.task_score)
+        };
+        assert_eq!(subsection(true), (Some(2.0), Some(2.0)));
+        assert_eq!(subsection(false), (None, None));
+    }
+
+    #[test]
+    fn respect_cropbox_drops_text_outside_the_crop_box() {
+        let content = concat!(
+            "BT /F1 12 Tf 72 770 Td (Crop mark above the beach trip for the travel planner.) Tj ET\n",
+            "BT /F1 12 Tf 72 700 Td (A beach trip for the travel planner.) Tj ET\n",
+            "BT /F1 12 Tf 20 400 Td (Bleed left of the beach trip for the travel planner.) Tj ET\n",
+            "BT /F1 12 Tf 36 36 Td (On the corner, a beach trip for the travel planner.) Tj ET\n",
+            "BT /F1 6 Tf 72 20 Td (Printer proof below the beach trip for the travel planner.) Tj ET",
+        );
+        let cropped = TestPdf::new().crop_box([36, 36, 576, 756]).page(content).build();
+        // Without a CropBox the page is cropped to its MediaBox.
+        let uncropped = TestPdf::new().page(content).build();
+        let texts = |document: &str, respect_cropbox| {
+            let pdf = if document == "cropped.pdf" { cropped.clone() } else { uncropped.clone() };
+            let output = analyze_with(ProcessingOptions { respect_cropbox, ..Default::default() }, &[(document, pdf)]);
+            let mut texts: Vec<String> = output.subsection_analysis.into_iter().map(|subsection| subsection.refined_text).collect();
+            texts.sort();
+            texts
+        };
+
+        let everything = texts("cropped.pdf", false);
+        assert_eq!(everything.len(), 5, "{:?}", everything);
+        assert_eq!(texts("uncropped.pdf", true), everything);
+        // A run starting on the box's edge is inside it.
+        assert_eq!(texts("cropped.pdf", true), [
+            "A beach trip for the travel planner.",
+            "On the corner, a beach trip for the travel planner.",
+        ]);
+    }
+
+    #[test]
+    fn keyword_index_lists_exactly_the_pages_each_keyword_matched_on() {
+        let north = TestPdf::new()
+            .text_page(&[(700.0, 12.0, "A beach day for the travel planner.")])
+            .text_page(&[(700.0, 12.0, "Sailing lessons for the travel planner.")])
+            .text_page(&[(700.0, 12.0, "Quarterly filings and payroll deductions.")])
+            .build();
+        let south = TestPdf::new()
+            .text_page(&[(700.0, 12.0, "Sailing and beach days for the travel planner.")])
+            .build();
+        let input = input("Travel planner", "Beach days with sailing", &["north.pdf", "south.pdf"]);
+        let options = ProcessingOptions { keyword_index: true, ..Default::default() };
+        let output = analyze_input(options, &input, &[("north.pdf", north), ("south.pdf", south)]).unwrap();
+
+        let pages = |keyword: &str| {
+            let mut pages: Vec<(String, u32)> = output.keyword_index[keyword].iter()
+                .map(|location| (location.document.clone(), location.page_number))
+                .collect();
+            pages.sort();
+            pages
+        };
+        let expected = |pages: &[(&str, u32)]| pages.iter().map(|&(document, page)| (document.to_string(), page)).collect::<Vec<_>>();
+        assert_eq!(pages("beach"), expected(&[("north.pdf", 1), ("south.pdf", 1)]));
+        assert_eq!(pages("sailing"), expected(&[("north.pdf", 2), ("south.pdf", 1)]));
+        assert_eq!(pages("travel"), expected(&[("north.pdf", 1), ("north.pdf", 2), ("south.pdf", 1)]));
+        // "day" on north's first page is not the keyword "days".
+        assert_eq!(pages("days"), expected(&[("south.pdf", 1)]));
+        let mut keywords: Vec<&str> = output.keyword_index.keys().map(String::as_str).collect();
+        keywords.sort();
+        assert_eq!(keywords, ["beach", "days", "planner", "sailing", "travel"]);
+    }
+
+    #[test]
+    fn a_five_thousand_character_fused_token_is_split_or_dropped() {
+        let fused = format!("beachTrip.Travel{}", "x".repeat(4984));
+        assert_eq!(fused.len(), 5000);
+        let text = format!("A beach trip {} for the planner.\n\n{}", fused, fused);
+
+        let (split, long_tokens) = PdfProcessor::guard_long_tokens(&text, 100, LongTokenAction::Split);
+        assert_eq!(long_tokens, 2);
+        assert!(split.split_whitespace().all(|token| token.chars().count() <= 100));
+        assert!(split.starts_with("A beach trip beach Trip. Travel"), "{}", &split[..60]);
+        assert_eq!(split.split("\n\n").count(), 2);
+
+        let (dropped, long_tokens) = PdfProcessor::guard_long_tokens(&text, 100, LongTokenAction::Drop);
+        assert_eq!(long_tokens, 2);
+        assert_eq!(dropped, "A beach trip for the planner.");
+        assert_eq!(PdfProcessor::guard_long_tokens("A beach trip.", 100, LongTokenAction::Drop), ("A beach trip.".to_string(), 0));
+    }
+
+    #[test]
+    fn a_paragraph_echoing_the_document_title_ranks_higher() {
+        let mut input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+        input.documents[0].title = "Riviera Coastline Guide".to_string();
+        let sections = || vec![section("guide.pdf", "Inland Beaches", 1, 1), section("guide.pdf", "Coastline Beaches", 2, 1)];
+        let subsections = [
+            subsection("guide.pdf", "A beach trip for the travel planner, inland.", 1),
+            subsection("guide.pdf", "A beach trip for the travel planner, on the coastline.", 2),
+        ];
+
+        assert_eq!(ranked_titles(ProcessingOptions::default(), sections(), &subsections, &input), ["Inland Beaches", "Coastline Beaches"]);
+        let boosted = ProcessingOptions { title_term_boost: 0.5, ..Default::default() };
+        assert_eq!(ranked_titles(boosted, sections(), &subsections, &input), ["Coastline Beaches", "Inland Beaches"]);
+    }
+
+    #[test]
+    fn tagged_headings_come_from_the_structure_tree() {
+        // "BEACH TRIPS" reads as a heading to the patterns but is tagged as a
+        // paragraph, and neither tagged heading matches a pattern.
+        let tagged = TestPdf::new()
+            .page(concat!(
+                "/H1 << /MCID 0 >> BDC BT /F1 12 Tf 72 700 Td (Where to swim, by season) Tj ET EMC\n",
+                "/P << /MCID 1 >> BDC BT /F1 12 Tf 72 660 Td (BEACH TRIPS) Tj ET EMC\n",
+                "/P << /MCID 2 >> BDC BT /F1 12 Tf 72 620 Td (A beach trip for the travel planner.) Tj ET EMC\n",
+                "/H2 << /MCID 3 >> BDC BT /F1 12 Tf 72 580 Td (what to pack, and when) Tj ET EMC",
+            ))
+            .structure(&[("H1", 1, 0), ("P", 1, 1), ("P", 1, 2), ("H2", 1, 3)])
+            .build();
+        let untagged = TestPdf::new()
+            .text_page(&[(700.0, 12.0, "BEACH TRIPS"), (660.0, 12.0, "A beach trip for the travel planner, untagged.")])
+            .build();
+        let sections = |use_structure_tags| {
+            let options = ProcessingOptions { use_structure_tags, max_heading_level: Some(6), ..Default::default() };
+            let output = analyze_with(options, &[("tagged.pdf", tagged.clone()), ("untagged.pdf", untagged.clone())]);
+            let mut sections: Vec<(String, String, Option<u8>)> = output.extracted_sections.into_iter()
+                .map(|section| (section.document, section.section_title, section.heading_level))
+                .collect();
+            sections.sort();
+            sections
+        };
+        let expected = |sections: &[(&str, &str, u8)]| sections.iter()
+            .map(|&(document, title, level)| (document.to_string(), title.to_string(), Some(level)))
+            .collect::<Vec<_>>();
+
+        // The untagged document falls back to the patterns.
+        assert_eq!(sections(true), expected(&[
+            ("tagged.pdf", "Where to swim, by season", 1),
+            ("tagged.pdf", "what to pack, and when", 2),
+            ("untagged.pdf", "BEACH TRIPS", 1),
+        ]));
+        assert_eq!(sections(false), expected(&[("tagged.pdf", "BEACH TRIPS", 1), ("untagged.pdf", "BEACH TRIPS", 1)]));
+    }
+
+    #[test]
+    fn a_panicking_document_is_skipped_and_the_others_are_still_written() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["panics.pdf", "guide.pdf"] {
+            std::fs::write(dir.path().join(name), text_pdf("A beach trip for the travel planner.")).unwrap();
+        }
+        let input = input("Travel planner", "Plan a beach trip", &["panics.pdf", "guide.pdf"]);
+
+        let options = ProcessingOptions { catch_panics: true, ..Default::default() };
+        let output = PdfProcessor::new(options).unwrap()
+            .with_extractor(|processor, doc, pdf_path, query| {
+                if doc.filename == "panics.pdf" {
+                    panic!("mock extractor failure");
+                }
+                processor.process_document(doc, pdf_path, query)
+            })
+            .analyze(&input, dir.path())
+            .unwrap();
+        let documents: Vec<&str> = output.subsection_analysis.iter().map(|subsection| subsection.document.as_str()).collect();
+        assert_eq!(documents, ["guide.pdf"]);
+    }
+
+    #[test]
+    fn the_document_with_the_most_relevant_content_ranks_first() {
+        let input = input("Travel planner", "Plan a beach trip", &["thin.pdf", "rich.pdf", "off_topic.pdf"]);
+        let subsections = [
+            subsection("thin.pdf", "A beach trip for the travel planner, with a sunny beach plan.", 1),
+            subsection("rich.pdf", "A beach trip for the travel planner.", 1),
+            subsection("rich.pdf", "The beach trip with the travel planner.", 2),
+            subsection("rich.pdf", "A beach trip for the travel planner, by train.", 3),
+        ];
+        let ranking = |document_score_aggregation| {
+            let options = ProcessingOptions { document_rankings: true, document_score_aggregation, ..Default::default() };
+            let processor = PdfProcessor::new(options).unwrap();
+            let query = processor.query(&input, Path::new(""));
+            processor.rank_documents(&input.documents, &subsections, &query).into_iter()
+                .map(|ranking| (ranking.rank, ranking.document))
+                .collect::<Vec<_>>()
+        };
+        let expected = |documents: [&str; 3]| documents.iter().enumerate()
+            .map(|(i, document)| (i as u32 + 1, document.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(ranking(ScoreAggregation::Sum), expected(["rich.pdf", "thin.pdf", "off_topic.pdf"]));
+        // One stronger paragraph wins when documents are judged by their best or average one.
+        assert_eq!(ranking(ScoreAggregation::Max), expected(["thin.pdf", "rich.pdf", "off_topic.pdf"]));
+        assert_eq!(ranking(ScoreAggregation::Mean), expected(["thin.pdf", "rich.pdf", "off_topic.pdf"]));
+    }
+
+    #[test]
+    fn preserve_whitespace_keeps_code_alignment_in_refined_text() {
+        let guide = TestPdf::new()
+            .text_page(&[
+                (700.0, 12.0, "Beach trip planner script for the travel desk:"),
+                (686.0, 12.0, "    trip   = plan(\"beach\")"),
+                (672.0, 12.0, "    hotel  = book(trip)"),
+            ])
+            .build();
+        let refined_text = |preserve_globally, preserve_document| {
+            let options = ProcessingOptions { preserve_whitespace: preserve_globally, ..Default::default() };
+            let mut input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+            input.documents[0].preserve_whitespace = preserve_document;
+            let output = analyze_input(options, &input, &[("guide.pdf", guide.clone())]).unwrap();
+            output.subsection_analysis[0].refined_text.clone()
+        };
+
+        let aligned = "Beach trip planner script for the travel desk:\n    trip   = plan(\"beach\")\n    hotel  = book(trip)";
+        assert_eq!(refined_text(true, false), aligned);
+        assert_eq!(refined_text(false, true), aligned);
+        assert_eq!(refined_text(false, false), "Beach trip planner script for the travel desk: trip = plan(\"beach\") hotel = book(trip)");
+    }
+
+    #[test]
+    fn mojibake_pages_get_lower_confidence_than_clean_ones() {
+        let guide = TestPdf::new()
+            .text_page(&[
+                (700.0, 12.0, "A beach trip for the travel planner. The coast near Nice has sandy beaches, quiet coves and"),
+                (686.0, 12.0, "small harbours, and trains run along the shore every hour in summer. Book hotels early for July."),
+            ])
+            // Each \377 is a byte that isn't UTF-8 and decodes to U+FFFD.
+            .page(r"BT /F1 12 Tf 72 700 Td (A beach trip \377\377 Ã©tÃ© â€™ Ã\377 ÃƒÂ© Ã¨Ã  \377Ã\377 â€œ Ãƒ Â¶Ã§ Ã¼Ã¶ \377\377\377 for the travel planner.) Tj ET")
+            .build();
+        let options = ProcessingOptions { report_page_confidence: true, ..Default::default() };
+        let output = analyze_with(options, &[("guide.pdf", guide)]);
+
+        let confidence = &output.metadata.page_confidence;
+        let pages: Vec<(&str, u32, u32)> = confidence.iter()
+            .map(|page| (page.document.as_str(), page.page_number, page.replacement_chars))
+            .collect();
+        assert_eq!(pages, [("guide.pdf", 1, 0), ("guide.pdf", 2, 8)]);
+        assert!(confidence[0].confidence > 0.9, "{:?}", confidence[0]);
+        assert!(confidence[1].confidence < 0.3, "{:?}", confidence[1]);
+    }
+
+    #[test]
+    fn documents_processed_in_parallel_are_merged_in_input_order() {
+        // Listed out of name order, and large enough to finish out of order.
+        let towns = ["Nice", "Antibes", "Menton", "Cannes", "Monaco", "Eze", "Villefranche", "Juan"];
+        let files: Vec<(String, Vec<u8>)> = towns.iter().enumerate().map(|(i, town)| {
+            let pages = (0..towns.len() - i)
+                .map(|page| format!("BEACHES OF {}\n\nA beach trip to {} for the travel planner, stop {}.\n", town.to_uppercase(), town, page))
+                .collect::<Vec<_>>()
+                .join("\x0c");
+            (format!("{}.pdf", town.to_lowercase()), text_pdf(&pages))
+        }).collect();
+        let files: Vec<(&str, Vec<u8>)> = files.iter().map(|(name, contents)| (name.as_str(), contents.clone())).collect();
+        let results = |parallel_documents| {
+            let options = ProcessingOptions { parallel_documents, ..Default::default() };
+            let mut output = serde_json::to_value(analyze_with(options, &files)).unwrap();
+            output["metadata"]["processing_timestamp"].take();
+            output
+        };
+
+        let sequential = results(Some(1));
+        let documents: Vec<&str> = sequential["subsection_analysis"].as_array().unwrap().iter()
+            .map(|subsection| subsection["document"].as_str().unwrap())
+            .collect();
+        // One subsection per page, and the first document has the most pages.
+        let expected: Vec<&str> = files.iter().enumerate()
+            .flat_map(|(i, (name, _))| (i..towns.len()).map(|_| *name))
+            .collect();
+        assert_eq!(documents, expected);
+        assert_eq!(sequential["metadata"]["input_documents"], serde_json::json!(files.iter().map(|(name, _)| *name).collect::<Vec<_>>()));
+        for parallel_documents in [Some(3), Some(8), None] {
+            assert_eq!(results(parallel_documents), sequential, "{:?}", parallel_documents);
+        }
+    }
+
+    #[test]
+    fn a_heading_wrapped_over_two_lines_becomes_one_section_title() {
+        let pdf = TestPdf::new()
+            .text_page(&[
+                (700.0, 18.0, "Day Trips to the Coastal Villages and"),
+                (678.0, 18.0, "Beaches of Provence"),
+                (640.0, 12.0, "A beach trip for the travel planner, with sandy coves"),
+                (626.0, 12.0, "and quiet harbours a short train ride from Marseille."),
+            ])
+            .build();
+        // Without a layout, line breaks survive only when whitespace is preserved.
+        let text = "Day Trips to the Coastal Villages and\nBeaches of Provence\n\nA beach trip for the travel planner.\n";
+        let titles = |document: &str, merge_wrapped_headings| {
+            let contents = if document == "guide.pdf" { pdf.clone() } else { text.into() };
+            let options = ProcessingOptions { merge_wrapped_headings, preserve_whitespace: true, ..Default::default() };
+            let output = analyze_with(options, &[(document, contents)]);
+            output.extracted_sections.into_iter().map(|section| section.section_title).collect::<Vec<_>>()
+        };
+
+        assert_eq!(titles("guide.pdf", true), ["Day Trips to the Coastal Villages and Beaches of Provence"]);
+        assert_eq!(titles("guide.txt", true), ["Day Trips to the Coastal Villages and Beaches of Provence"]);
+        let mut unmerged = titles("guide.txt", false);
+        unmerged.sort();
+        assert_eq!(unmerged, ["Beaches of Provence", "Day Trips to the Coastal Villages and"]);
+    }
+
+    #[test]
+    fn the_newer_of_two_equally_relevant_documents_ranks_higher_under_recency_weighting() {
+        let pdf = text_pdf("Beach Trips\n\nA beach trip for the travel planner.\n");
+        let files = [("archive.pdf", pdf.clone()), ("undated.pdf", pdf.clone()), ("latest.pdf", pdf)];
+        let mut input = input("Travel planner", "Plan a beach trip", &["archive.pdf", "undated.pdf", "latest.pdf"]);
+        input.documents[0].date = Some("2021-06-01".to_string());
+        input.documents[2].date = Some("2024-06-01".to_string());
+        let analyze = |recency_weighting| {
+            let options = ProcessingOptions { recency_weighting, explain: true, ..Default::default() };
+            let output = analyze_input(options, &input, &files).unwrap();
+            let ranked: Vec<String> = output.extracted_sections.into_iter().map(|section| section.document).collect();
+            let scores: Vec<f64> = output.subsection_analysis.into_iter()
+                .map(|subsection| subsection.explanation.unwrap().score)
+                .collect();
+            (ranked, scores)
+        };
+
+        let (ranked, scores) = analyze(None);
+        assert_eq!(ranked, ["archive.pdf", "latest.pdf", "undated.pdf"]);
+        let base = scores[0];
+        assert_eq!(scores, [base, base, base]);
+        let (ranked, scores) = analyze(Some(RecencyWeighting::default()));
+        assert_eq!(ranked, ["latest.pdf", "archive.pdf", "undated.pdf"]);
+        // The archive is 1096 days older than the newest document, and the
+        // undated one gets the minimum weight.
+        let expected = [base * 0.5f64.powf(1096.0 / 365.0), base * 0.1, base];
+        for (score, expected) in scores.iter().zip(expected) {
+            assert!((score - expected).abs() < 1e-9, "{} != {}", score, expected);
+        }
+    }
+
+    #[test]
+    fn a_keyword_inside_a_longer_word_does_not_make_a_paragraph_relevant() {
+        let guide = text_pdf(concat!(
+            "Particle physics for the startup historian.\n\n",
+            "A historian pushing carts past the start line.\n\n",
+            "Art history tours for the historian.\n\n",
+            "A historian's guide to modern art.\n",
+        ));
+        let output = analyze_input(ProcessingOptions::default(), &input("Historian", "Study art", &["guide.pdf"]), &[("guide.pdf", guide)]).unwrap();
+        assert_eq!(refined_texts(&output), ["Art history tours for the historian.", "A historian's guide to modern art."]);
+    }
+
+    #[test]
+    fn a_keyword_in_every_document_stops_dominating_under_tf_idf() {
+        let files = [
+            ("desk.pdf", text_pdf(concat!(
+                "Desk Hours\n\nThe travel agent will book trains.\n\n",
+                "Every travel agent can book hotels.\n\nA travel agent may book cars.\n",
+            ))),
+            ("coast.pdf", text_pdf("Coast Guide\n\nThe travel agent can book a beach.\n")),
+            ("offices.pdf", text_pdf("Office List\n\nTravel agent offices book flights.\n")),
+        ];
+        let input = input("Travel agent", "Book a beach", &["desk.pdf", "coast.pdf", "offices.pdf"]);
+        let ranked_titles = |scoring_mode| {
+            let options = ProcessingOptions { scoring_mode, ..Default::default() };
+            let output = analyze_input(options, &input, &files).unwrap();
+            section_titles(&output).into_iter().map(str::to_string).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ranked_titles(ScoringMode::Keyword), ["Desk Hours", "Coast Guide", "Office List"]);
+        // "travel", "agent" and "book" are on every page, so only "beach" carries weight.
+        assert_eq!(ranked_titles(ScoringMode::TfIdf), ["Coast Guide", "Desk Hours", "Office List"]);
+    }
+
+    #[test]
+    fn the_exported_term_matrix_matches_the_fixture_term_counts() {
+        let files = [
+            ("coast.pdf", text_pdf("Beach, beach and a hotel.")),
+            ("city.pdf", text_pdf("Hotel trains and the hotel.")),
+        ];
+        let input = input("Travel planner", "Plan a beach trip", &["coast.pdf", "city.pdf"]);
+        let matrix = |export_matrix| {
+            let options = ProcessingOptions { export_matrix, ..Default::default() };
+            analyze_input(options, &input, &files).unwrap().term_matrix
+        };
+
+        let exported = matrix(true).unwrap();
+        assert_eq!(exported.documents, ["coast.pdf", "city.pdf"]);
+        assert_eq!(exported.terms, ["beach", "hotel", "trains"]);
+        assert_eq!(exported.counts, [[2, 1, 0], [0, 2, 1]]);
+        assert_eq!(exported.document_frequency, [1, 2, 1]);
+        assert!(matrix(false).is_none());
+    }
+
+    #[test]
+    fn tf_idf_on_a_single_document_falls_back_to_finite_scores() {
+        let files = [("guide.pdf", text_pdf("Beach Trips\n\nA beach trip for the travel planner, beach after beach.\n"))];
+        let input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+        let score = |min_idf_documents, idf_fallback| {
+            let options = ProcessingOptions {
+                scoring_mode: ScoringMode::TfIdf,
+                min_idf_documents,
+                idf_fallback,
+                explain: true,
+                ..Default::default()
+            };
+            let output = analyze_input(options, &input, &files).unwrap();
+            assert_eq!(output.extracted_sections.len(), 1);
+            output.subsection_analysis[0].explanation.as_ref().unwrap().score
+        };
+
+        let term_frequency = score(2, IdfFallback::TermFrequency);
+        let smoothed = score(2, IdfFallback::Smoothed);
+        assert!(term_frequency.is_finite() && term_frequency > 0.0, "{}", term_frequency);
+        // beach (tf 3), travel, planner and trip, each weighted ln(1 + 1/1).
+        assert!((smoothed - (4.0 + 3f64.ln()) * 2f64.ln()).abs() < 1e-9, "{}", smoothed);
+        // Without the fallback every keyword is on the only page, so ln(N/df) is 0.
+        assert_eq!(score(1, IdfFallback::TermFrequency), 0.0);
+    }
+
+    #[test]
+    fn the_structure_report_lists_headings_without_scoring_relevance() {
+        struct Unreachable;
+        impl OutputPostProcessor for Unreachable {
+            fn process(&self, _output: &mut OutputJson, _input: &InputJson) {
+                panic!("structure reports skip the analysis output");
+            }
+        }
+        let dir = tempfile::tempdir().unwrap();
+        TestPdf::new()
+            .text_page(&[(700.0, 12.0, "Quarterly Filings"), (660.0, 12.0, "Payroll deductions are due in March.")])
+            .text_page(&[(700.0, 12.0, "Audit Checklist"), (660.0, 12.0, "Keep receipts for seven years.")])
+            .write(&dir.path().join("ledger.pdf"));
+        // Nothing in the document matches the persona or the task.
+        let input = serde_json::to_string(&input("Travel planner", "Plan a beach trip", &["ledger.pdf"])).unwrap();
+        let input_path = dir.path().join("challenge1b_input.json");
+        let output_path = dir.path().join("challenge1b_output.json");
+        std::fs::write(&input_path, input).unwrap();
+
+        let options = ProcessingOptions { structure_report: true, ..Default::default() };
+        PdfProcessor::new(options).unwrap().with_post_processor(Unreachable)
+            .process_pdf_collection(input_path.to_str().unwrap(), output_path.to_str().unwrap())
+            .unwrap();
+        assert!(!output_path.exists());
+        let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.path().join(STRUCTURE_REPORT_FILENAME)).unwrap()).unwrap();
+        let document = &report["documents"][0];
+        assert_eq!(document["document"], "ledger.pdf");
+        assert_eq!(document["pages_extracted"], 2);
+        assert_eq!(document["sample_titles"], serde_json::json!(["Quarterly Filings", "Audit Checklist"]));
+    }
+
+    #[test]
+    fn an_exempted_stopword_survives_keyword_extraction() {
+        let input = input("Estate lawyer", "Draft the will for your client", &["guide.pdf"]);
+        let task_keywords = |stopword_exemptions: Vec<String>| {
+            let processor = PdfProcessor::new(ProcessingOptions { stopword_exemptions, ..Default::default() }).unwrap();
+            processor.query(&input, Path::new("")).task_keywords
+        };
+
+        assert_eq!(task_keywords(Vec::new()), ["draft", "client"]);
+        assert_eq!(task_keywords(vec!["Will".to_string()]), ["draft", "will", "client"]);
+    }
+
+    #[test]
+    fn the_configured_ocr_command_gets_the_configured_arguments() {
+        use std::os::unix::fs::PermissionsExt;
+        let tools = tempfile::tempdir().unwrap();
+        // Prints one line naming the file and the arguments around it; fails on
+        // arguments it doesn't expect, as pdftotext would on unknown options.
+        let script = tools.path().join("extract-text");
+        std::fs::write(&script, concat!(
+            "#!/bin/sh\n",
+            "case \"$*\" in\n",
+            "  '-raw -nopgbrk '*' -') echo \"A beach trip for the travel planner, from $(basename \"$3\").\" ;;\n",
+            "  \"--input $2 --stdout\") echo \"A beach trip for the travel planner, read from $(basename \"$2\").\" ;;\n",
+            "  *) echo \"unexpected arguments: $*\" >&2; exit 2 ;;\n",
+            "esac\n",
+        )).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        // Neither file parses as a PDF, so each falls back to the command, on
+        // its own worker thread.
+        let files = [("north.pdf", b"not a pdf".to_vec()), ("south.pdf", b"not a pdf".to_vec())];
+        let texts = |ocr_args: &[&str]| {
+            let options = ProcessingOptions {
+                ocr_command: script.to_string_lossy().into_owned(),
+                ocr_args: ocr_args.iter().map(|arg| arg.to_string()).collect(),
+                parallel_documents: Some(2),
+                ..Default::default()
+            };
+            let output = analyze_with(options, &files);
+            refined_texts(&output).into_iter().map(str::to_string).collect::<Vec<_>>()
+        };
+
+        assert_eq!(texts(&["-raw", "-nopgbrk"]), [
+            "A beach trip for the travel planner, from north.pdf.",
+            "A beach trip for the travel planner, from south.pdf.",
+        ]);
+        assert_eq!(texts(&["--input", "{input}", "--stdout"]), [
+            "A beach trip for the travel planner, read from north.pdf.",
+            "A beach trip for the travel planner, read from south.pdf.",
+        ]);
+        assert_eq!(ProcessingOptions::default().ocr_command, "pdftotext");
+        assert_eq!(ProcessingOptions::default().ocr_args, ["-layout"]);
+    }
+
+    #[test]
+    fn stopwords_are_dropped_from_task_keywords() {
+        let input = input("Travel planner", "plan the trip for the group", &["guide.pdf"]);
+        let task_keywords = |extra_stopwords: Vec<String>| {
+            let processor = PdfProcessor::new(ProcessingOptions { extra_stopwords, ..Default::default() }).unwrap();
+            processor.query(&input, Path::new("")).task_keywords
+        };
+
+        assert_eq!(task_keywords(Vec::new()), ["plan", "trip", "group"]);
+        assert_eq!(task_keywords(vec!["Group".to_string()]), ["plan", "trip"]);
+    }
+
+    #[test]
+    fn parallel_page_extraction_matches_sequential() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pdf = TestPdf::new();
+        for page in 1..=9 {
+            pdf = pdf.text_page(&[
+                (720.0, 18.0, &format!("DAY {}", page)),
+                (690.0, 12.0, &format!("A beach trip for the travel planner, stop {} of the tour.", page)),
+            ]);
+        }
+        pdf.write(&dir.path().join("guide.pdf"));
+        let input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+        let results = |parallel_pages| {
+            let options = ProcessingOptions { parallel_pages, report_coverage: true, ..Default::default() };
+            let output = PdfProcessor::new(options).unwrap().analyze(&input, dir.path()).unwrap();
+            let mut output = serde_json::to_value(&output).unwrap();
+            output["metadata"]["processing_timestamp"].take();
+            output
+        };
+
+        let sequential = results(None);
+        assert_eq!(sequential["subsection_analysis"].as_array().unwrap().len(), 9);
+        assert_eq!(results(Some(4)), sequential);
+        assert_eq!(results(Some(64)), sequential);
+    }
+
+    #[test]
+    fn top_limits_keep_the_highest_ranked_and_break_ties_at_the_cutoff() {
+        // The markets and hotels pages score the same.
+        let guide = text_pdf(concat!(
+            "MARKETS\n\nA beach market for the travel planner.\n\x0c",
+            "BEACHES\n\nA beach trip for the travel planner, beach after beach.\n\x0c",
+            "HOTELS\n\nA beach hotel for the travel planner.\n",
+        ));
+        let analyze = |options| analyze_with(options, &[("guide.pdf", guide.clone())]);
+        let ranked = |output: &OutputJson| output.extracted_sections.iter()
+            .map(|section| (section.section_title.clone(), section.importance_rank))
+            .collect::<Vec<_>>();
+        let owned = |sections: &[(&str, u32)]| sections.iter().map(|&(title, rank)| (title.to_string(), rank)).collect::<Vec<_>>();
+
+        let all = analyze(ProcessingOptions::default());
+        assert_eq!(ranked(&all), owned(&[("BEACHES", 1), ("MARKETS", 2), ("HOTELS", 3)]));
+        assert_eq!(all.subsection_analysis.len(), 3);
+        // The tie goes to the earlier page, or with a title tie break to "HOTELS".
+        let top = analyze(ProcessingOptions { top_sections: Some(2), ..Default::default() });
+        assert_eq!(ranked(&top), owned(&[("BEACHES", 1), ("MARKETS", 2)]));
+        let top = analyze(ProcessingOptions { top_sections: Some(2), tie_break: vec![TieBreakKey::Title], ..Default::default() });
+        assert_eq!(ranked(&top), owned(&[("BEACHES", 1), ("HOTELS", 2)]));
+        assert_eq!(ranked(&analyze(ProcessingOptions { top_sections: Some(5), ..Default::default() })), ranked(&all));
+
+        // Kept subsections stay in page order, the earlier of the tied pair
+        // making the cut, and sections are still ranked from all of them.
+        let top = analyze(ProcessingOptions { top_subsections: Some(2), ..Default::default() });
+        assert_eq!(refined_texts(&top), ["A beach market for the travel planner.", "A beach trip for the travel planner, beach after beach."]);
+        assert_eq!(ranked(&top), ranked(&all));
+    }
+
+    #[test]
+    fn all_mode_requires_every_task_keyword() {
+        let guide = text_pdf(concat!(
+            "Book a beach hotel early, says the travel planner.\n\n",
+            "A beach hotel the travel planner likes.\n\n",
+            "A beach for the travel planner.\n\n",
+            "Book a beach hotel, asks the planner.\n",
+        ));
+        let paragraphs = |task_match, persona_match| {
+            let options = ProcessingOptions { task_match, persona_match, ..Default::default() };
+            let output = analyze_input(options, &input("Travel planner", "Book a beach hotel", &["guide.pdf"]), &[("guide.pdf", guide.clone())]).unwrap();
+            output.subsection_analysis.into_iter().map(|subsection| subsection.refined_text).collect::<Vec<_>>()
+        };
+        let (any, all) = (KeywordRequirement::Any, KeywordRequirement::All);
+
+        assert_eq!(paragraphs(any, any).len(), 4);
+        assert_eq!(paragraphs(KeywordRequirement::AtLeast(2), any), [
+            "Book a beach hotel early, says the travel planner.",
+            "A beach hotel the travel planner likes.",
+            "Book a beach hotel, asks the planner.",
+        ]);
+        let every_task_keyword = ["Book a beach hotel early, says the travel planner.", "Book a beach hotel, asks the planner."];
+        assert_eq!(paragraphs(all, any), every_task_keyword);
+        // More keywords than the task has means all of them.
+        assert_eq!(paragraphs(KeywordRequirement::AtLeast(10), any), every_task_keyword);
+        assert_eq!(paragraphs(all, all), ["Book a beach hotel early, says the travel planner."]);
+    }
+
+    // Keeps every log record, so a test can check what was logged at which level.
+    struct CapturingLogger(std::sync::Mutex<Vec<(log::Level, String)>>);
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    // The process-wide logger; other tests' records land in it too.
+    fn captured_logs() -> &'static CapturingLogger {
+        static LOGGER: OnceLock<&'static CapturingLogger> = OnceLock::new();
+        LOGGER.get_or_init(|| {
+            let logger = Box::leak(Box::new(CapturingLogger(Default::default())));
+            log::set_logger(logger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+            logger
+        })
+    }
+
+    #[test]
+    fn diagnostics_go_to_the_logger_with_paragraphs_at_trace_level() {
+        let logs = captured_logs();
+        let tools = tempfile::tempdir().unwrap();
+        let ocr_command = fake_ocr_command(tools.path(), "A beach trip for the travel planner, read by OCR.");
+        let files = [
+            ("logged-native.pdf", text_pdf("A beach trip for the travel planner.\n")),
+            ("logged-scan.pdf", text_pdf("A beach trip for the travel planner.\n")),
+        ];
+        let mut input = input("Travel planner", "Plan a beach trip", &["logged-native.pdf", "logged-scan.pdf"]);
+        input.documents[1].force_ocr = true;
+        analyze_input(ProcessingOptions { ocr_command, ..Default::default() }, &input, &files).unwrap();
+
+        let records: Vec<(log::Level, String)> = logs.0.lock().unwrap().iter()
+            .filter(|(_, message)| message.contains("logged-"))
+            .cloned()
+            .collect();
+        let level_of = |prefix: &str| records.iter()
+            .filter(|(_, message)| message.starts_with(prefix))
+            .map(|(level, _)| *level)
+            .collect::<Vec<_>>();
+        assert_eq!(level_of("Found relevant paragraph on page 1 of logged-"), [log::Level::Trace, log::Level::Trace]);
+        assert_eq!(level_of("OCR forced for "), [log::Level::Info]);
+        assert_eq!(level_of("Extracted "), [log::Level::Info, log::Level::Info]);
+    }
+
+    #[test]
+    fn collections_resolve_documents_from_a_shared_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let shared = root.path().join("shared");
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(shared.join("beaches.pdf"), text_pdf("A beach trip for the travel planner.")).unwrap();
+        std::fs::write(shared.join("hotels.pdf"), text_pdf("A hotel near the beach for the travel planner.")).unwrap();
+        // Only the unshared collection has its own copy, which is ignored once
+        // a shared directory is set.
+        std::fs::create_dir_all(root.path().join("own/pdfs")).unwrap();
+        std::fs::write(root.path().join("own/pdfs/beaches.pdf"), text_pdf("A beach cove for the travel planner.")).unwrap();
+        let collections = [
+            (Some(shared.clone()), "first", "beaches.pdf", "A beach trip for the travel planner."),
+            (Some(shared), "second", "hotels.pdf", "A hotel near the beach for the travel planner."),
+            (None, "own", "beaches.pdf", "A beach cove for the travel planner."),
+        ];
+        for (shared_pdf_dir, name, document, text) in collections {
+            let processor = PdfProcessor::new(ProcessingOptions { shared_pdf_dir, ..Default::default() }).unwrap();
+            let dir = root.path().join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            let input = serde_json::json!({
+                "challenge_info": { "challenge_id": "test", "test_case_name": name },
+                "documents": [{ "filename": document }],
+                "persona": { "role": "Travel planner" },
+                "job_to_be_done": { "task": "Plan a beach trip" },
+            });
+            let input_path = dir.join("challenge1b_input.json");
+            let output_path = dir.join("challenge1b_output.json");
+            std::fs::write(&input_path, input.to_string()).unwrap();
+
+            processor.process_pdf_collection(input_path.to_str().unwrap(), output_path.to_str().unwrap()).unwrap();
+            let output: OutputJson = serde_json::from_str(&std::fs::read_to_string(&output_path).unwrap()).unwrap();
+            let found: Vec<(&str, &str)> = output.subsection_analysis.iter()
+                .map(|subsection| (subsection.document.as_str(), subsection.refined_text.as_str()))
+                .collect();
+            assert_eq!(found, [(document, text)], "collection {}", name);
+        }
+    }
+
+    #[test]
+    fn missing_output_directories_are_created() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("pdfs")).unwrap();
+        std::fs::write(dir.path().join("pdfs/guide.pdf"), text_pdf("A beach trip for the travel planner.")).unwrap();
+        let input = serde_json::json!({
+            "challenge_info": { "challenge_id": "test", "test_case_name": "test" },
+            "documents": [{ "filename": "guide.pdf" }],
+            "persona": { "role": "Travel planner" },
+            "job_to_be_done": { "task": "Plan a beach trip" },
+        });
+        let input_path = dir.path().join("challenge1b_input.json");
+        let output_path = dir.path().join("tmp/a/b/c/out.json");
+        std::fs::write(&input_path, input.to_string()).unwrap();
+
+        PdfProcessor::new(ProcessingOptions::default()).unwrap()
+            .process_pdf_collection(input_path.to_str().unwrap(), output_path.to_str().unwrap())
+            .unwrap();
+        assert!(dir.path().join("tmp/a/b/c").is_dir());
+        let output: OutputJson = serde_json::from_str(&std::fs::read_to_string(&output_path).unwrap()).unwrap();
+        assert_eq!(refined_texts(&output), ["A beach trip for the travel planner."]);
+    }
+
+    #[test]
+    fn titles_come_from_the_input_then_the_pdf_metadata_then_the_filename() {
+        let page = "A beach trip for the travel planner.\n";
+        let files = [
+            ("titled.pdf", TestPdf::new().plain_text(page).title("Wrong Title").build()),
+            ("described.pdf", TestPdf::new().plain_text(page).title("  Coastal   Walks ").build()),
+            ("blank-title.pdf", TestPdf::new().plain_text(page).title(" ").build()),
+            ("plain-notes.pdf", text_pdf(page)),
+        ];
+        let mut input = input("Travel planner", "Plan a beach trip", &["titled.pdf", "described.pdf", "blank-title.pdf", "plain-notes.pdf"]);
+        input.documents[0].title = " Riviera Guide ".to_string();
+        let titles = |document_titles| {
+            let output = analyze_input(ProcessingOptions { document_titles, ..Default::default() }, &input, &files).unwrap();
+            output.metadata.document_titles.into_iter()
+                .map(|title| (title.document, title.title, title.source))
+                .collect::<Vec<_>>()
+        };
+
+        let owned = |document: &str, title: &str, source| (document.to_string(), title.to_string(), source);
+        assert_eq!(titles(true), [
+            owned("titled.pdf", "Riviera Guide", TitleSource::Input),
+            owned("described.pdf", "Coastal Walks", TitleSource::PdfMetadata),
+            owned("blank-title.pdf", "blank-title", TitleSource::Filename),
+            owned("plain-notes.pdf", "plain-notes", TitleSource::Filename),
+        ]);
+        assert!(titles(false).is_empty());
+    }
+
+    #[test]
+    fn a_footer_on_every_page_is_stripped() {
+        let towns = ["NICE", "CANNES", "ANTIBES", "MENTON", "MONACO"];
+        // The tide line is on three pages of five.
+        let tides = |page| if page <= 3 { " The tide tables are at the back." } else { "" };
+        let pages: Vec<String> = towns.iter().zip(1..)
+            .map(|(town, page)| format!(
+                "RIVIERA GUIDE\n\nBEACHES OF {town}\n\nA beach trip to {town} for the travel planner.{}\nConfidential \u{2014} Page {page}\n",
+                tides(page).replace(" The", "\nThe"),
+            ))
+            .collect();
+        let guide = text_pdf(&pages.join("\x0c"));
+        let analyze = |repeated_line_threshold| {
+            let output = analyze_with(ProcessingOptions { repeated_line_threshold, ..Default::default() }, &[("guide.pdf", guide.clone())]);
+            let mut titles: Vec<String> = section_titles(&output).into_iter().map(str::to_string).collect();
+            titles.sort();
+            titles.dedup();
+            (titles, refined_texts(&output).into_iter().map(str::to_string).collect::<Vec<_>>())
+        };
+        let mut stripped_titles: Vec<String> = towns.iter().map(|town| format!("BEACHES OF {}", town)).collect();
+        stripped_titles.sort();
+
+        let (titles, texts) = analyze(None);
+        assert!(titles.contains(&"RIVIERA GUIDE".to_string()), "{:?}", titles);
+        assert!(texts.iter().all(|text| text.contains("Confidential")), "{:?}", texts);
+        let (titles, texts) = analyze(Some(0.8));
+        assert_eq!(titles, stripped_titles);
+        let expected: Vec<String> = towns.iter().zip(1..)
+            .map(|(town, page)| format!("A beach trip to {} for the travel planner.{}", town, tides(page)))
+            .collect();
+        assert_eq!(texts, expected);
+        // At 60% the tide line counts as repeated too.
+        let (_, texts) = analyze(Some(0.6));
+        assert!(texts.iter().all(|text| !text.contains("tide")), "{:?}", texts);
+    }
+
+    #[test]
+    fn words_hyphenated_across_line_breaks_are_rejoined() {
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let clean = |raw: &str| processor.clean_extracted_text(raw, false);
+
+        assert_eq!(clean("An inter-\nnational beach trip."), "An international beach trip.");
+        assert_eq!(clean("A well-known beach trip."), "A well-known beach trip.");
+        // The page hyphenates the compound mid-line too, so the hyphen stays.
+        assert_eq!(clean("A well-known beach, and a well-\nknown coast."), "A well-known beach, and a well-known coast.");
+        // With no mid-line sighting there's no telling it from a wrapped word.
+        assert_eq!(clean("A well-\nknown beach trip."), "A wellknown beach trip.");
+
+        assert_eq!(clean("The Paris-\nBerlin train."), "The Paris- Berlin train.");
+        let off = PdfProcessor::new(ProcessingOptions { dehyphenate: false, ..Default::default() }).unwrap();
+        assert_eq!(off.clean_extracted_text("An inter-\nnational beach trip.", false), "An inter- national beach trip.");
+    }
+
+    #[test]
+    fn a_section_body_runs_to_the_next_heading_on_its_page() {
+        let guide = text_pdf(concat!(
+            "BEACHES\n\nA beach trip for the travel planner.\n\nSandy coves near Nice.\n\n",
+            "HOTELS\n\nA beach hotel for the travel planner.\n\x0c",
+            "Quiet harbours a short walk from the beach.\n\nMARKETS\n\nA beach market for the travel planner.\n",
+        ));
+        let bodies = |section_bodies| {
+            let output = analyze_with(ProcessingOptions { section_bodies, ..Default::default() }, &[("guide.pdf", guide.clone())]);
+            let mut bodies: Vec<_> = output.extracted_sections.into_iter().map(|section| (section.section_title, section.section_body)).collect();
+            bodies.sort();
+            bodies
+        };
+        let owned = |title: &str, body: &str| (title.to_string(), Some(body.to_string()));
+
+        // The hotels body stops at the page break, before the harbours paragraph.
+        assert_eq!(bodies(true), [
+            owned("BEACHES", "A beach trip for the travel planner.\n\nSandy coves near Nice."),
+            owned("HOTELS", "A beach hotel for the travel planner."),
+            owned("MARKETS", "A beach market for the travel planner."),
+        ]);
+        assert!(bodies(false).iter().all(|(_, body)| body.is_none()));
+    }
+
+    #[test]
+    fn a_paragraph_belongs_to_the_latest_heading_before_it() {
+        let guide = text_pdf(
+            "A beach trip overview for the travel planner.\n\nBEACHES\n\nA sandy beach trip for the travel planner.\n\n\
+             HOTELS\n\nA hotel on the beach trip for the travel planner.\n\x0c\
+             A beach trip across the page break for the travel planner.\n",
+        );
+        let options = ProcessingOptions { parent_sections: true, ..Default::default() };
+        let output = analyze_with(options, &[("guide.pdf", guide)]);
+        let mut parents: Vec<(&str, Option<&str>)> = output.subsection_analysis.iter()
+            .map(|subsection| (subsection.refined_text.as_str(), subsection.parent_section_title.as_deref()))
+            .collect();
+        parents.sort();
+
+        // Headings on earlier pages don't carry over.
+        assert_eq!(parents, [
+            ("A beach trip across the page break for the travel planner.", None),
+            ("A beach trip overview for the travel planner.", None),
+
+            ("A hotel on the beach trip for the travel planner.", Some("HOTELS")),
+            ("A sandy beach trip for the travel planner.", Some("BEACHES")),
+        ]);
+    }
+
+    #[test]
+    fn equal_scores_rank_by_document_then_page_then_title() {
+        let input = input("Travel planner", "Plan a beach trip", &["a.pdf", "b.pdf"]);
+        let sections = [("b.pdf", "Markets", 1), ("a.pdf", "Trains", 3), ("a.pdf", "Museums", 3), ("a.pdf", "Hotels", 3), ("a.pdf", "Food", 4)];
+        let expected = ["Hotels", "Museums", "Trains", "Food", "Markets"];
+        for order in [[0, 1, 2, 3, 4], [4, 3, 2, 1, 0], [2, 4, 0, 3, 1]] {
+            let sections = order.iter()
+                .map(|&i| sections[i])
+                .map(|(document, title, page)| section(document, title, page, 1))
+                .collect();
+            assert_eq!(ranked_titles(ProcessingOptions::default(), sections, &[], &input), expected);
+        }
+    }
+
+    #[test]
+    fn a_text_document_yields_subsections_like_a_pdf() {
+        let output = analyze_with(ProcessingOptions::default(), &[
+            ("notes.txt", concat!(
+                "BEACH TRIPS\n\nA beach trip for the travel planner.\n\nQuarterly filings and payroll deductions.\n\x0c",
+                "A second beach trip for the travel planner.\n",
+            ).into()),
+            ("notes.md", "## Beach Tips\n\nPack light for a beach trip, travel planner.\n".into()),
+        ]);
+        let mut subsections: Vec<(&str, u32, &str)> = output.subsection_analysis.iter()
+            .map(|subsection| (subsection.document.as_str(), subsection.page_number, subsection.refined_text.as_str()))
+            .collect();
+        subsections.sort();
+
+        assert_eq!(subsections, [
+            ("notes.md", 1, "Pack light for a beach trip, travel planner."),
+            ("notes.txt", 1, "A beach trip for the travel planner."),
+            ("notes.txt", 2, "A second beach trip for the travel planner."),
+        ]);
+        let mut titles = section_titles(&output);
+        titles.sort();
+        assert_eq!(titles, ["BEACH TRIPS", "Beach Tips"]);
+    }
+
+    #[test]
+    fn overlapping_patterns_and_repeated_pages_report_a_heading_once() {
+        let guide = ["Nice", "Cannes", "Antibes"]
+            .map(|town| format!("BEACH TRIPS\n\nA beach trip to {} for the travel planner.\n", town))
+            .join("\x0c");
+        let sections = |dedupe_document_headings| {
+            let options = ProcessingOptions {
+                heading_patterns: Some(vec![r"(?m)^([A-Z][A-Z ]+)$".to_string(), r"(?m)^(BEACH [A-Z]+)$".to_string()]),
+                dedupe_document_headings,
+                ..Default::default()
+            };
+            let output = analyze_with(options, &[("guide.txt", guide.clone().into())]);
+            let mut sections: Vec<(u32, String)> = output.extracted_sections.into_iter()
+                .map(|section| (section.page_number, section.section_title))
+                .collect();
+            sections.sort();
+            sections
+        };
+
+        let title = || "BEACH TRIPS".to_string();
+        assert_eq!(sections(false), [(1, title()), (2, title()), (3, title())]);
+        assert_eq!(sections(true), [(1, title())]);
+    }
+
+    #[test]
+    fn an_encrypted_pdf_opens_with_its_password_and_names_the_file_otherwise() {
+        let dir = tempfile::tempdir().unwrap();
+        TestPdf::new()
+            .text_page(&[(700.0, 12.0, "A beach trip for the travel planner, behind a password.")])
+            .password("s3cret")
+            .write(&dir.path().join("locked.pdf"));
+        let analyze = |password: Option<&str>| {
+            let mut input = input("Travel planner", "Plan a beach trip", &["locked.pdf"]);
+            input.documents[0].password = password.map(str::to_string);
+            PdfProcessor::new(ProcessingOptions::default()).unwrap().analyze(&input, dir.path())
+        };
+
+        let output = analyze(Some("s3cret")).unwrap();
+        let texts: Vec<&str> = output.subsection_analysis.iter().map(|subsection| subsection.refined_text.as_str()).collect();
+        assert_eq!(texts, ["A beach trip for the travel planner, behind a password."]);
+        let options = ProcessingOptions { pdf_password: Some("s3cret".to_string()), ..Default::default() };
+        let output = PdfProcessor::new(options).unwrap().analyze(&input("Travel planner", "Plan a beach trip", &["locked.pdf"]), dir.path()).unwrap();
+        assert_eq!(output.subsection_analysis.len(), 1);
+        assert!(analyze(None).unwrap().subsection_analysis.is_empty());
+
+        let path = dir.path().join("locked.pdf");
+        let error = |password| PdfProcessor::open_pdf(&path, password).err().unwrap().to_string();
+        assert_eq!(error(None), format!(
+            "{} is encrypted; set a `password` on the document in the input or the `pdf_password` option", path.display()
+        ));
+        assert_eq!(error(Some("wrong")), format!("{} is encrypted and the password given doesn't unlock it", path.display()));
+    }
+
+    #[test]
+    fn pdfs_are_found_in_the_configured_subdirectory_or_beside_the_input() {
+        let root = tempfile::tempdir().unwrap();
+        // (collection, where its PDF is written, relative to the collection)
+        let layouts = [("documents", Some("documents/guide.pdf")), ("beside", Some("guide.pdf")), ("empty_subdir", Some("guide.pdf")), ("missing", None)];
+        for (name, pdf) in layouts {
+            let dir = root.path().join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            if let Some(pdf) = pdf {
+                std::fs::create_dir_all(dir.join(pdf).parent().unwrap()).unwrap();
+                std::fs::write(dir.join(pdf), text_pdf("A beach trip for the travel planner.\n")).unwrap();
+            }
+            let input = serde_json::to_string(&input("Travel planner", "Plan a beach trip", &["guide.pdf"])).unwrap();
+            std::fs::write(dir.join("challenge1b_input.json"), input).unwrap();
+        }
+        // An existing subdirectory is searched alone, even without the PDF in it.
+        std::fs::create_dir_all(root.path().join("empty_subdir/documents")).unwrap();
+        let processor = PdfProcessor::new(ProcessingOptions { pdf_subdir: "documents".to_string(), ..Default::default() }).unwrap();
+        let process = |name: &str| {
+            let dir = root.path().join(name);
+            processor.process_pdf_collection(
+                dir.join("challenge1b_input.json").to_str().unwrap(),
+                dir.join("challenge1b_output.json").to_str().unwrap(),
+            )
+        };
+
+        assert_eq!(process("documents").unwrap().subsections, 1);
+        assert_eq!(process("beside").unwrap().subsections, 1);
+        let dir = root.path().join("empty_subdir");
+        assert_eq!(process("empty_subdir").unwrap_err().to_string(), format!(
+            "PDF \"guide.pdf\" not found; looked at: {}", dir.join("documents/guide.pdf").display(),
+        ));
+        let dir = root.path().join("missing");
+        assert_eq!(process("missing").unwrap_err().to_string(), format!(
+            "PDF \"guide.pdf\" not found; looked at: {}, {}", dir.join("documents/guide.pdf").display(), dir.join("guide.pdf").display(),
+        ));
+        assert_eq!(ProcessingOptions::default().pdf_subdir, "pdfs");
+    }
+
+    #[test]
+    fn a_keyword_phrase_outscores_the_same_words_scattered() {
+        let input = input("Food contractor", "Prepare a buffet menu for the gala", &["guide.pdf"]);
+        let phrase = "The contractor serves a buffet menu at the gala.";
+        let scattered = "The menu at the gala is long, so the contractor serves a buffet.";
+        let scores = |phrase_keywords| {
+            let processor = PdfProcessor::new(ProcessingOptions { phrase_keywords, ..Default::default() }).unwrap();
+            let query = processor.query(&input, Path::new(""));
+            (processor.paragraph_score("guide.pdf", phrase, &query), processor.paragraph_score("guide.pdf", scattered, &query))
+        };
+
+        let (phrase_score, scattered_score) = scores(false);
+        assert_eq!(phrase_score, scattered_score);
+        let (phrase_score, scattered_score) = scores(true);
+        assert!(phrase_score > scattered_score, "{} vs {}", phrase_score, scattered_score);
+    }
+
+    #[test]
+    fn stemming_matches_inflections_of_a_keyword() {
+        let guide = "The travel agent is planning ahead.\n\nThe travel agent plans ahead.\n\n\
+                     The travel agent planned ahead.\n\nThe travel agent is a planner.\n";
+        let input = input("Travel agent", "Plan a cruise", &["guide.txt"]);
+        let matched = |stemming| {
+            let output = analyze_input(ProcessingOptions { stemming, ..Default::default() }, &input, &[("guide.txt", guide.into())]).unwrap();
+            refined_texts(&output).into_iter().map(str::to_string).collect::<Vec<_>>()
+        };
+
+        assert!(matched(false).is_empty());
+        // "planner" stems to itself, not to "plan".
+        assert_eq!(matched(true), [
+            "The travel agent is planning ahead.",
+            "The travel agent plans ahead.",
+            "The travel agent planned ahead.",
+        ]);
+    }
+
+    #[test]
+    fn ligatures_accents_and_typographic_punctuation_are_normalized() {
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap();
+        let cleaned = processor.clean_extracted_text("\u{FB01}nancial  advice at a nai\u{308}ve cafe\u{301} \u{2014} \u{201C}open\u{201D} it\u{2019}s", false);
+        assert_eq!(cleaned, "financial advice at a na\u{EF}ve caf\u{E9} - \"open\" it's");
+
+        let input = input("Naïve investor", "Read the financial news at a café", &["guide.pdf"]);
+        let query = processor.query(&input, Path::new(""));
+        assert!(processor.paragraph_score("guide.pdf", &cleaned, &query) > 0.0);
+        let raw = PdfProcessor::new(ProcessingOptions { normalize_unicode: false, ..Default::default() }).unwrap()
+            .clean_extracted_text("\u{FB01}nancial advice", false);
+        assert_eq!(raw, "\u{FB01}nancial advice");
+    }
+
+    #[test]
+    fn sections_are_listed_by_rank_or_in_reading_order() {
+        let files = [
+            ("coast.txt", "OVERVIEW\n\nQuarterly filings and payroll deductions.\n\nBEACH TRIPS\n\nA beach trip for the travel planner, with a beach trip plan.\n".into()),
+            ("hotels.txt", "HOTELS\n\nA hotel for the travel planner on a beach trip.\n".into()),
+        ];
+        let sections = |section_ordering| {
+            let output = analyze_with(ProcessingOptions { section_ordering, ..Default::default() }, &files);
+
+            output.extracted_sections.into_iter().map(|section| (section.section_title, section.importance_rank)).collect::<Vec<_>>()
+        };
+        let owned = |sections: &[(&str, u32)]| sections.iter().map(|&(title, rank)| (title.to_string(), rank)).collect::<Vec<_>>();
+
+        assert_eq!(sections(SectionOrdering::ByRank), owned(&[("BEACH TRIPS", 1), ("OVERVIEW", 2), ("HOTELS", 3)]));
+        assert_eq!(sections(SectionOrdering::ByPosition), owned(&[("OVERVIEW", 2), ("BEACH TRIPS", 1), ("HOTELS", 3)]));
+    }
+
+    #[test]
+    fn statistics_count_the_words_and_characters_of_the_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        TestPdf::new()
+            .text_page(&[(700.0, 12.0, "A beach trip for the travel planner.")])
+            .text_page(&[(700.0, 12.0, "Three more words.")])
+            .write(&dir.path().join("guide.pdf"));
+        std::fs::write(dir.path().join("empty.txt"), "").unwrap();
+        let input = input("Travel planner", "Plan a beach trip", &["guide.pdf", "empty.txt"]);
+        let metadata_with = |report_statistics| {
+            let output = PdfProcessor::new(ProcessingOptions { report_statistics, ..Default::default() }).unwrap()
+                .analyze(&input, dir.path())
+                .unwrap();
+            serde_json::to_value(&output.metadata).unwrap()
+        };
+
+        let metadata = metadata_with(true);
+        assert_eq!(metadata["total_pages"], 2);
+        assert_eq!(metadata["total_words"], 10);
+        assert_eq!(metadata["total_characters"], 36 + 17);
+        assert!(metadata["processing_duration_ms"].is_u64());
+        assert_eq!(metadata["document_statistics"], serde_json::json!([
+            { "document": "guide.pdf", "pages": 2, "words": 10, "characters": 53 },
+            { "document": "empty.txt", "pages": 0, "words": 0, "characters": 0 },
+        ]));
+        let metadata = metadata_with(false);
+        for field in ["total_pages", "total_words", "total_characters", "document_statistics", "processing_duration_ms"] {
+            assert!(metadata.get(field).is_none(), "{}", field);
+        }
+    }
+
+    #[test]
+    fn a_page_whose_content_stream_fails_is_skipped_and_blank_pages_can_be_kept() {
+        let guide = TestPdf::new()
+            .page("BT /F1 12 Tf 72 700 Td (A beach trip for the travel planner.) Tj ET")
+            .page("")
+            .undecodable_page("BT /F1 12 Tf 72 700 Td (A broken beach trip for the travel planner.) Tj ET")
+            .page("BT /F1 12 Tf 72 700 Td (A last beach trip for the travel planner.) Tj ET")
+            .build();
+        let analyze = |keep_blank_pages| {
+            let options = ProcessingOptions { keep_blank_pages, report_statistics: true, ..Default::default() };
+            analyze_with(options, &[("guide.pdf", guide.clone())])
+        };
+
+        for keep_blank_pages in [false, true] {
+            let output = analyze(keep_blank_pages);
+            let pages: Vec<u32> = output.subsection_analysis.iter().map(|subsection| subsection.page_number).collect();
+            assert_eq!(pages, [1, 4]);
+            let skipped: Vec<u32> = output.metadata.skipped_pages.iter().map(|page| page.page_number).collect();
+            assert_eq!(skipped, [3]);
+        }
+        // The page without a content stream only counts when blank pages are kept.
+        let pages = |keep_blank_pages| serde_json::to_value(&analyze(keep_blank_pages).metadata).unwrap()["total_pages"].clone();
+        assert_eq!(pages(false), 2);
+        assert_eq!(pages(true), 3);
+    }
+
+    #[test]
+    fn merge_adjacent_joins_back_to_back_matches_but_not_across_a_gap() {
+        let input = input("Travel planner", "Plan a beach trip", &["guide.pdf"]);
+        let page = "A beach trip for the travel planner.\n\nA second beach trip for the travel planner.\n\n\
+                    Quarterly filings and payroll deductions.\n\nA third beach trip for the travel planner.";
+        let passages = |merge_adjacent| {
+            let processor = PdfProcessor::new(ProcessingOptions { merge_adjacent, ..Default::default() }).unwrap();
+            let query = processor.query(&input, Path::new(""));
+            processor.find_relevant_content("guide.pdf", &[(1, page.to_string())], &query)
+                .into_iter().map(|subsection| subsection.refined_text).collect::<Vec<_>>()
+        };
+
+        assert_eq!(passages(true), [
+            "A beach trip for the travel planner.\n\nA second beach trip for the travel planner.",
+            "A third beach trip for the travel planner.",
+        ]);
+        assert_eq!(passages(false).len(), 3);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,56 @@
+use crate::models::{InputJson, OutputJson};
+
+/// Hook for adjusting a collection's output after analysis and before it is
+/// written, e.g. to add company-specific fields. Register implementations with
+/// [`PdfProcessor::with_post_processor`](crate::pdf_processor::PdfProcessor::with_post_processor);
+/// they run in registration order.
+pub trait OutputPostProcessor: Send + Sync {
+    fn process(&self, output: &mut OutputJson, input: &InputJson);
+}
+
+/// Leaves the output unchanged.
+pub struct NoOpPostProcessor;
+
+impl OutputPostProcessor for NoOpPostProcessor {
+    fn process(&self, _output: &mut OutputJson, _input: &InputJson) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessingOptions;
+    use crate::pdf_processor::PdfProcessor;
+    use crate::test_pdf::TestPdf;
+
+    // Tags the persona with the challenge id, as a company-specific field would.
+    struct TagPersona;
+
+    impl OutputPostProcessor for TagPersona {
+        fn process(&self, output: &mut OutputJson, input: &InputJson) {
+            output.metadata.persona = format!("{} [{}]", output.metadata.persona, input.challenge_info.challenge_id);
+        }
+    }
+
+    #[test]
+    fn a_registered_processor_changes_the_written_output() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("pdfs")).unwrap();
+        TestPdf::new().plain_text("A beach trip for the travel planner.\n").write(&dir.path().join("pdfs/guide.pdf"));
+        let input = serde_json::json!({
+            "challenge_info": { "challenge_id": "round_1b_002", "test_case_name": "travel" },
+            "documents": [{ "filename": "guide.pdf", "title": "Guide" }],
+            "persona": { "role": "Travel planner" },
+            "job_to_be_done": { "task": "Plan a beach trip" },
+        });
+        let input_path = dir.path().join("challenge1b_input.json");
+        let output_path = dir.path().join("challenge1b_output.json");
+        std::fs::write(&input_path, input.to_string()).unwrap();
+
+        let processor = PdfProcessor::new(ProcessingOptions::default()).unwrap()
+            .with_post_processor(NoOpPostProcessor)
+            .with_post_processor(TagPersona);
+        processor.process_pdf_collection(input_path.to_str().unwrap(), output_path.to_str().unwrap()).unwrap();
+        let written: OutputJson = serde_json::from_str(&std::fs::read_to_string(&output_path).unwrap()).unwrap();
+        assert_eq!(written.metadata.persona, "Travel planner [round_1b_002]");
+    }
+}
@@ -0,0 +1,199 @@
+use crate::config::ProcessingOptions;
+use crate::utils::{ensure_directory_exists, sanitize_filename, write_atomic};
+use anyhow::{Context, Result};
+use log::info;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// A remote dataset: each collection's input JSON and the PDFs it references.
+// Relative URLs are resolved against the manifest's own URL.
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    pub collections: Vec<RemoteCollection>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoteCollection {
+    pub name: String,
+    pub input_url: String,
+    pub pdfs: Vec<RemotePdf>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemotePdf {
+    pub filename: String,
+    pub url: String,
+    // When given and already cached, the download is skipped.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+// Downloads every collection in the manifest into `cache_dir`, laid out like a
+// local collection (`<name>/<input_filename>`, `<name>/<pdf_subdir>/...`), and
+// returns the collections as (name, input path, output path). A `*` in the
+// configured filenames is replaced with the collection name. PDFs are stored
+// once under `cache_dir/blobs/<sha256>` and copied into each collection.
+pub fn fetch_collections(manifest_url: &str, cache_dir: &Path, options: &ProcessingOptions) -> Result<Vec<(String, PathBuf, PathBuf)>> {
+    // One agent for every request, so connections to the same host are reused.
+    let agent = ureq::AgentBuilder::new().build();
+    let manifest: Manifest = serde_json::from_slice(&download(&agent, manifest_url)?)
+        .with_context(|| format!("Failed to parse manifest at {}", manifest_url))?;
+    let blobs_dir = cache_dir.join("blobs");
+    ensure_directory_exists(&blobs_dir)?;
+
+    let mut collections = Vec::new();
+    for collection in &manifest.collections {
+        let dirname = sanitize_filename(&collection.name);
+        let collection_dir = cache_dir.join(&dirname);
+        let pdf_dir = collection_dir.join(&options.pdf_subdir);
+        ensure_directory_exists(&pdf_dir)?;
+
+        let input_path = collection_dir.join(options.input_filename.replacen('*', &dirname, 1));
+        write_atomic(&input_path, download(&agent, &resolve_url(manifest_url, &collection.input_url))?)?;
+
+        for pdf in &collection.pdfs {
+            if pdf.filename.is_empty() || pdf.filename.contains(['/', '\\']) || pdf.filename == "." || pdf.filename == ".." {
+                return Err(anyhow::anyhow!("Manifest PDF filename {:?} must be a file name without path separators", pdf.filename));
+            }
+            let blob = fetch_blob(&agent, &resolve_url(manifest_url, &pdf.url), pdf.sha256.as_deref(), &blobs_dir)?;
+            std::fs::copy(&blob, pdf_dir.join(&pdf.filename))
+                .with_context(|| format!("Failed to copy {} into {}", blob.display(), pdf_dir.display()))?;
+        }
+        let output_path = collection_dir.join(options.output_filename.replacen('*', &dirname, 1));
+        collections.push((collection.name.clone(), input_path, output_path));
+    }
+    Ok(collections)
+}
+
+fn fetch_blob(agent: &ureq::Agent, url: &str, expected_sha256: Option<&str>, blobs_dir: &Path) -> Result<PathBuf> {
+    if let Some(hash) = expected_sha256 {
+        let cached = blobs_dir.join(hash.to_lowercase());
+        if cached.exists() {
+            return Ok(cached);
+        }
+    }
+    let bytes = download(agent, url)?;
+    let hash = hex::encode(Sha256::digest(&bytes));
+    if let Some(expected) = expected_sha256 {
+        if !expected.eq_ignore_ascii_case(&hash) {
+            return Err(anyhow::anyhow!("{} has sha256 {}, expected {}", url, hash, expected));
+        }
+    }
+    let path = blobs_dir.join(&hash);
+    if !path.exists() {
+        write_atomic(&path, bytes)?;
+    }
+    Ok(path)
+}
+
+fn download(agent: &ureq::Agent, url: &str) -> Result<Vec<u8>> {
+    info!("Downloading {}", url);
+    let response = agent.get(url).call().with_context(|| format!("Failed to download {}", url))?;
+    let mut bytes = Vec::new();
+    response.into_reader().read_to_end(&mut bytes)
+        .with_context(|| format!("Failed to read response from {}", url))?;
+    Ok(bytes)
+}
+
+fn resolve_url(base: &str, url: &str) -> String {
+    if url.contains("://") {
+        return url.to_string();
+    }
+    match base.rfind('/') {
+        Some(end) if url.starts_with('/') => {
+            let origin_end = base.find("://").and_then(|scheme| base[scheme + 3..].find('/').map(|i| scheme + 3 + i));
+            format!("{}{}", &base[..origin_end.unwrap_or(end)], url)
+        }
+        Some(end) => format!("{}/{}", &base[..end], url),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    // Serves `files` by path over plain HTTP, answering anything else with 404.
+    fn serve(files: Vec<(&'static str, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                reader.read_line(&mut request_line).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let (status, body) = match files.iter().find(|(served, _)| *served == path) {
+                    Some((_, body)) => ("200 OK", body.clone()),
+                    None => ("404 Not Found", Vec::new()),
+                };
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len()).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        base
+    }
+
+    fn manifest(pdf_filename: &str) -> Vec<u8> {
+        serde_json::json!({
+            "collections": [{
+                "name": "travel",
+                "input_url": "travel/input.json",
+                "pdfs": [{ "filename": pdf_filename, "url": "/files/guide.pdf" }],
+            }],
+        }).to_string().into_bytes()
+    }
+
+    #[test]
+    fn downloads_collections_under_the_configured_filenames() {
+        let base = serve(vec![
+            ("/data/manifest.json", manifest("guide.pdf")),
+            ("/data/travel/input.json", b"{}".to_vec()),
+            ("/files/guide.pdf", b"%PDF-1.4".to_vec()),
+        ]);
+        let cache = tempfile::tempdir().unwrap();
+        let options = ProcessingOptions {
+            input_filename: "request_*.json".to_string(),
+            output_filename: "result_*.json".to_string(),
+            pdf_subdir: "docs".to_string(),
+            ..Default::default()
+        };
+        let collections = fetch_collections(&format!("{}/data/manifest.json", base), cache.path(), &options).unwrap();
+
+        let dir = cache.path().join("travel");
+        assert_eq!(collections, [("travel".to_string(), dir.join("request_travel.json"), dir.join("result_travel.json"))]);
+        assert_eq!(std::fs::read(dir.join("request_travel.json")).unwrap(), b"{}");
+        assert_eq!(std::fs::read(dir.join("docs/guide.pdf")).unwrap(), b"%PDF-1.4");
+    }
+
+    #[test]
+    fn rejects_pdf_filenames_that_are_not_plain_file_names() {
+        for filename in ["", ".", "..", "../guide.pdf", "docs\\guide.pdf"] {
+            let base = serve(vec![
+                ("/manifest.json", manifest(filename)),
+                ("/travel/input.json", b"{}".to_vec()),
+                ("/files/guide.pdf", b"%PDF-1.4".to_vec()),
+            ]);
+            let cache = tempfile::tempdir().unwrap();
+            let error = fetch_collections(&format!("{}/manifest.json", base), cache.path(), &ProcessingOptions::default()).unwrap_err();
+            assert!(error.to_string().contains("must be a file name"), "{:?}: {}", filename, error);
+        }
+    }
+
+    #[test]
+    fn resolves_relative_and_absolute_urls_against_the_manifest() {
+        let base = "https://example.com/data/manifest.json";
+        assert_eq!(resolve_url(base, "travel/input.json"), "https://example.com/data/travel/input.json");
+        assert_eq!(resolve_url(base, "/files/guide.pdf"), "https://example.com/files/guide.pdf");
+        assert_eq!(resolve_url(base, "https://cdn.example.com/guide.pdf"), "https://cdn.example.com/guide.pdf");
+    }
+}
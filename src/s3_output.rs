@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use std::path::Path;
+
+// An `s3://bucket/prefix` output location. Objects are written under
+// `<prefix>/<collection>/<output filename>`, mirroring the local layout.
+pub struct S3Location {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl S3Location {
+    pub fn parse(uri: &str) -> Result<Self> {
+        let rest = uri.strip_prefix("s3://")
+            .with_context(|| format!("S3 output location must look like s3://bucket/prefix, got {}", uri))?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(anyhow::anyhow!("S3 output location {} has no bucket", uri));
+        }
+        Ok(Self { bucket: bucket.to_string(), prefix: prefix.trim_matches('/').to_string() })
+    }
+
+    pub fn key(&self, collection: &str, filename: &str) -> String {
+        if self.prefix.is_empty() {
+            format!("{}/{}", collection, filename)
+        } else {
+            format!("{}/{}/{}", self.prefix, collection, filename)
+        }
+    }
+}
+
+// Credentials come from the standard AWS environment variables and profile
+// files. The region is read from AWS_REGION (default us-east-1); setting
+// AWS_ENDPOINT_URL targets an S3-compatible store such as MinIO or LocalStack,
+// addressed path-style.
+fn open_bucket(location: &S3Location) -> Result<Box<Bucket>> {
+    let credentials = Credentials::default().context("Failed to load S3 credentials")?;
+    let region = std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+    let bucket = match std::env::var("AWS_ENDPOINT_URL") {
+        Ok(endpoint) => Bucket::new(&location.bucket, Region::Custom { region, endpoint }, credentials)?.with_path_style(),
+        Err(_) => Bucket::new(&location.bucket, region.parse()?, credentials)?,
+    };
+    Ok(bucket)
+}
+
+// Objects are served with a type matching the format they were rendered in.
+fn content_type(filename: &str) -> &'static str {
+    match Path::new(filename).extension().and_then(|extension| extension.to_str()) {
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("atom") => "application/atom+xml",
+        Some("csv") => "text/csv; charset=utf-8",
+        _ => "application/json",
+    }
+}
+
+pub fn write_collection(uri: &str, collection: &str, filename: &str, contents: &str) -> Result<()> {
+    let location = S3Location::parse(uri)?;
+    upload(&*open_bucket(&location)?, &location.key(collection, filename), contents)
+}
+
+fn upload(bucket: &Bucket, key: &str, contents: &str) -> Result<()> {
+    let response = bucket
+        .put_object_with_content_type(key, contents.as_bytes(), content_type(key))
+        .with_context(|| format!("Failed to upload s3://{}/{}", bucket.name(), key))?;
+    if response.status_code() != 200 {
+        return Err(anyhow::anyhow!(
+            "Upload to s3://{}/{} failed with status {}: {}",
+            bucket.name(), key, response.status_code(), String::from_utf8_lossy(response.as_slice())
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    // A fake S3 endpoint answering every request with 200 and reporting each
+    // one's method, path and content type.
+    fn fake_endpoint() -> (String, mpsc::Receiver<(String, String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let (mut content_type, mut length) = (String::new(), 0);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-type" => content_type = value.trim().to_string(),
+                        "content-length" => length = value.trim().parse().unwrap(),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+                if sender.send((method, path, content_type)).is_err() {
+                    break;
+                }
+            }
+        });
+        (endpoint, receiver)
+    }
+
+    #[test]
+    fn parses_bucket_and_prefix() {
+        let location = S3Location::parse("s3://reports/runs/2024/").unwrap();
+        assert_eq!(location.bucket, "reports");
+        assert_eq!(location.key("travel", "challenge1b_output.json"), "runs/2024/travel/challenge1b_output.json");
+        assert_eq!(S3Location::parse("s3://reports").unwrap().key("travel", "a.md"), "travel/a.md");
+        assert!(S3Location::parse("reports/runs").is_err());
+        assert!(S3Location::parse("s3:///runs").is_err());
+    }
+
+    #[test]
+    fn uploads_with_a_content_type_per_format() {
+        let (endpoint, requests) = fake_endpoint();
+        let credentials = Credentials::new(Some("key"), Some("secret"), None, None, None).unwrap();
+        let region = Region::Custom { region: "us-east-1".to_string(), endpoint };
+        let bucket = Bucket::new("reports", region, credentials).unwrap().with_path_style();
+        for (key, expected) in [
+            ("travel/challenge1b_output.json", "application/json"),
+            ("travel/challenge1b_output.md", "text/markdown; charset=utf-8"),
+            ("travel/challenge1b_output.atom", "application/atom+xml"),
+            ("travel/term_matrix.csv", "text/csv; charset=utf-8"),
+        ] {
+            upload(&bucket, key, "contents").unwrap();
+            let (method, path, content_type) = requests.recv().unwrap();
+            assert_eq!(method, "PUT");
+            assert_eq!(path, format!("/reports/{}", key));
+            assert_eq!(content_type, expected);
+        }
+    }
+
+    // The only test that reads the AWS_* variables, so setting them here
+    // doesn't race with anything else.
+    #[test]
+    fn every_artifact_of_a_collection_goes_to_s3() {
+        use crate::config::{MatrixFormat, ProcessingOptions};
+        use crate::pdf_processor::PdfProcessor;
+        use crate::test_pdf::TestPdf;
+
+        let (endpoint, requests) = fake_endpoint();
+        std::env::set_var("AWS_ENDPOINT_URL", endpoint);
+        std::env::set_var("AWS_ACCESS_KEY_ID", "key");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+
+        let dir = tempfile::tempdir().unwrap();
+        let collection = dir.path().join("travel");
+        std::fs::create_dir_all(collection.join("pdfs")).unwrap();
+        TestPdf::new().plain_text("BEACH TRIPS\n\nA beach trip for the travel planner.\n").write(&collection.join("pdfs/guide.pdf"));
+        let input = serde_json::json!({
+            "challenge_info": { "challenge_id": "test", "test_case_name": "test" },
+            "documents": [{ "filename": "guide.pdf", "title": "Guide" }],
+            "persona": { "role": "Travel planner" },
+            "job_to_be_done": { "task": "Plan a beach trip" },
+        });
+        let input_path = collection.join("challenge1b_input.json");
+        std::fs::write(&input_path, input.to_string()).unwrap();
+        let output_path = collection.join("challenge1b_output.json");
+
+        let options = ProcessingOptions {
+            s3_output: Some("s3://reports/runs".to_string()),
+            markdown_report: true,
+            export_matrix: true,
+            matrix_format: MatrixFormat::Csv,
+            ..Default::default()
+        };
+        PdfProcessor::new(options).unwrap()
+            .process_pdf_collection(input_path.to_str().unwrap(), output_path.to_str().unwrap())
+            .unwrap();
+
+        let uploaded: Vec<_> = requests.try_iter().map(|(_, path, content_type)| (path, content_type)).collect();
+        assert_eq!(uploaded, [
+            ("/reports/runs/travel/challenge1b_output.json".to_string(), "application/json".to_string()),
+            ("/reports/runs/travel/challenge1b_output.md".to_string(), "text/markdown; charset=utf-8".to_string()),
+            ("/reports/runs/travel/term_matrix.csv".to_string(), "text/csv; charset=utf-8".to_string()),
+        ]);
+        assert!(!output_path.exists() && !collection.join("term_matrix.csv").exists());
+    }
+}
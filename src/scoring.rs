@@ -0,0 +1,219 @@
+use crate::config::{CaseFolding, KeywordRequirement};
+use rust_stemmers::Stemmer;
+use std::collections::{HashMap, HashSet};
+
+pub struct Query {
+    pub persona_keywords: Vec<String>,
+    pub task_keywords: Vec<String>,
+    // Runs of adjacent persona or task keywords, space-separated. Only filled
+    // in when `phrase_keywords` is set.
+    pub phrase_keywords: Vec<String>,
+    // The persona and task as one token sequence, for phrase scoring.
+    pub phrase_tokens: Vec<String>,
+    // Applied to keywords and to the text they are matched against.
+    pub case_folding: CaseFolding,
+    // Applied to keywords and then to the text they are matched against. Only
+    // set when `stemming` is on.
+    pub stemmer: Option<Stemmer>,
+    // Keywords from each document's title, keyed by filename. Only filled in
+    // when `title_term_boost` is set.
+    pub title_keywords: HashMap<String, Vec<String>>,
+    // Score multipliers keyed by filename. Only filled in when
+    // `recency_weighting` is set.
+    pub recency_weights: HashMap<String, f64>,
+    // ln(N/df) of each keyword over all extracted pages of the collection, for
+    // TF-IDF scoring. Filled in once every document has been extracted.
+    pub keyword_idf: HashMap<String, f64>,
+}
+
+impl Query {
+    // The text as keywords are matched against it: case-folded, then stemmed
+    // when stemming is on.
+    pub fn fold(&self, text: &str) -> String {
+        let folded = fold_case(text, self.case_folding);
+        match &self.stemmer {
+            Some(stemmer) => stem_words(&folded, stemmer),
+            None => folded,
+        }
+    }
+}
+
+pub fn fold_case(text: &str, folding: CaseFolding) -> String {
+    match folding {
+        CaseFolding::Lowercase => text.to_lowercase(),
+        CaseFolding::Full => caseless::default_case_fold_str(text),
+    }
+}
+
+// Replaces each run of letters and digits in the folded `text` with its stem,
+// keeping everything between runs, so "planned trips." becomes "plan trip.".
+pub fn stem_words(text: &str, stemmer: &Stemmer) -> String {
+    let mut stemmed = String::with_capacity(text.len());
+    let mut word_start = None;
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            word_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = word_start.take() {
+            stemmed.push_str(&stemmer.stem(&text[start..i]));
+        }
+        stemmed.push(c);
+    }
+    if let Some(start) = word_start {
+        stemmed.push_str(&stemmer.stem(&text[start..]));
+    }
+    stemmed
+}
+
+// Byte offsets of the occurrences of `word` in `text` that aren't directly
+// preceded or followed by a letter or digit, so "art" matches "modern art."
+// but not "startup".
+pub fn word_matches<'a>(text: &'a str, word: &'a str) -> impl Iterator<Item = usize> + 'a {
+    text.match_indices(word)
+        .filter(move |(start, _)| {
+            let before = text[..*start].chars().next_back();
+            let after = text[start + word.len()..].chars().next();
+            !word.is_empty() && !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+        .map(|(start, _)| start)
+}
+
+pub fn contains_word(text: &str, word: &str) -> bool {
+    word_matches(text, word).next().is_some()
+}
+
+// Whether the folded `text` contains `phrase` as whole words, however the
+// whitespace between them falls, so a phrase can wrap across lines.
+pub fn contains_phrase(text: &str, phrase: &str) -> bool {
+    contains_word(&text.split_whitespace().collect::<Vec<_>>().join(" "), phrase)
+}
+
+// Whether the folded `text` contains as many distinct `keywords` as
+// `requirement` asks for. An empty keyword list is never met.
+pub fn meets_requirement(text: &str, keywords: &[String], requirement: KeywordRequirement) -> bool {
+    let distinct: HashSet<&String> = keywords.iter().collect();
+    let required = match requirement {
+        KeywordRequirement::Any => 1,
+        KeywordRequirement::All => distinct.len(),
+        KeywordRequirement::AtLeast(n) => n.clamp(1, distinct.len().max(1)),
+    };
+    !distinct.is_empty() && distinct.iter().filter(|k| contains_word(text, k)).take(required).count() == required
+}
+
+pub fn tokenize(text: &str, folding: CaseFolding) -> Vec<String> {
+    fold_case(text, folding)
+        .split_whitespace()
+        .map(|s| s.trim_matches(|c: char| !c.is_alphanumeric()).to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn ngrams(tokens: &[String], n: usize) -> HashSet<String> {
+    tokens.windows(n).map(|w| w.join(" ")).collect()
+}
+
+// Share of the query's word bigrams and trigrams that also occur in the text.
+// Containment rather than Jaccard, so long paragraphs aren't penalised for length.
+pub fn phrase_overlap(query_tokens: &[String], text: &str, folding: CaseFolding) -> f64 {
+    let text_tokens = tokenize(text, folding);
+    let mut total = 0;
+    let mut matched = 0;
+    for n in 2..=3 {
+        let query_ngrams = ngrams(query_tokens, n);
+        if query_ngrams.is_empty() {
+            continue;
+        }
+        let text_ngrams = ngrams(&text_tokens, n);
+        total += query_ngrams.len();
+        matched += query_ngrams.intersection(&text_ngrams).count();
+    }
+    if total == 0 {
+        0.0
+    } else {
+        matched as f64 / total as f64
+    }
+}
+
+pub type TermVector = HashMap<String, f64>;
+
+pub fn term_frequencies(tokens: impl IntoIterator<Item = String>) -> TermVector {
+    let mut counts = TermVector::new();
+    for token in tokens.into_iter().filter(|t| t.chars().count() > 2) {
+        *counts.entry(token).or_default() += 1.0;
+    }
+    counts
+}
+
+// Weights each document's term counts by a smoothed IDF, ln(1 + N/df), so terms
+// shared by every document still contribute in small collections.
+pub fn tf_idf_vectors(term_counts: &[TermVector]) -> Vec<TermVector> {
+    let n = term_counts.len() as f64;
+    let mut document_frequency: HashMap<&str, f64> = HashMap::new();
+    for counts in term_counts {
+        for term in counts.keys() {
+            *document_frequency.entry(term).or_default() += 1.0;
+        }
+    }
+    term_counts.iter().map(|counts| {
+        counts.iter()
+            .map(|(term, tf)| (term.clone(), tf * (1.0 + n / document_frequency[term.as_str()]).ln()))
+            .collect()
+    }).collect()
+}
+
+// The inverse document frequency of each term, ln(N/df), where `df` is the
+// number of the `n` documents containing it. Terms in no document are left out.
+pub fn inverse_document_frequencies(document_frequency: &HashMap<String, usize>, n: usize) -> HashMap<String, f64> {
+    document_frequency.iter()
+        .filter(|(_, df)| **df > 0)
+        .map(|(term, df)| (term.clone(), (n as f64 / *df as f64).ln()))
+        .collect()
+}
+
+// ln(1 + N/df), as used for document similarity, which doesn't reach zero.
+pub fn smoothed_inverse_document_frequencies(document_frequency: &HashMap<String, usize>, n: usize) -> HashMap<String, f64> {
+    document_frequency.iter()
+        .filter(|(_, df)| **df > 0)
+        .map(|(term, df)| (term.clone(), (1.0 + n as f64 / *df as f64).ln()))
+        .collect()
+}
+
+pub fn cosine(a: &TermVector, b: &TermVector) -> f64 {
+    let dot: f64 = a.iter().filter_map(|(term, x)| b.get(term).map(|y| x * y)).sum();
+    let norm_a = a.values().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.values().map(|x| x * x).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+// Counts keyword occurrences followed, within `window` tokens, by an occurrence
+// of a different keyword. A token matches a keyword it contains as a word.
+pub fn proximity_pairs(text: &str, keywords: &[String], window: usize, folding: CaseFolding) -> usize {
+    let occurrences: Vec<(usize, &str)> = tokenize(text, folding).iter().enumerate()
+        .filter_map(|(i, token)| keywords.iter().find(|k| contains_word(token, k)).map(|k| (i, k.as_str())))
+        .collect();
+    occurrences.iter().enumerate()
+        .filter(|(n, (position, keyword))| {
+            occurrences[n + 1..].iter()
+                .take_while(|(next, _)| next - position <= window)
+                .any(|(_, other)| other != keyword)
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keywords_match_whole_words_only() {
+        assert!(!contains_word("a startup in the old cart depot", "art"));
+        assert!(contains_word("art history", "art"));
+        assert!(contains_word("a tour of modern art.", "art"));
+        assert_eq!(word_matches("art, start, art", "art").collect::<Vec<_>>(), [0, 12]);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::models::OutputJson;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS collections (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    persona TEXT NOT NULL,
+    job_to_be_done TEXT NOT NULL,
+    processing_timestamp TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sections (
+    collection_id INTEGER NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+    document TEXT NOT NULL,
+    section_title TEXT NOT NULL,
+    importance_rank INTEGER NOT NULL,
+    page_number INTEGER NOT NULL,
+    heading_level INTEGER
+);
+CREATE TABLE IF NOT EXISTS subsections (
+    collection_id INTEGER NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+    document TEXT NOT NULL,
+    refined_text TEXT NOT NULL,
+    page_number INTEGER NOT NULL
+);
+";
+
+// Replaces any rows previously written for the collection, so re-runs don't duplicate.
+pub fn write_collection(db_path: &Path, collection: &str, output: &OutputJson) -> Result<()> {
+    let mut conn = Connection::open(db_path)
+        .with_context(|| format!("Failed to open SQLite database at {}", db_path.display()))?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM collections WHERE name = ?1", params![collection])?;
+    tx.execute(
+        "INSERT INTO collections (name, persona, job_to_be_done, processing_timestamp) VALUES (?1, ?2, ?3, ?4)",
+        params![collection, output.metadata.persona, output.metadata.job_to_be_done, output.metadata.processing_timestamp],
+    )?;
+    let collection_id = tx.last_insert_rowid();
+    {
+        let mut insert_section = tx.prepare(
+            "INSERT INTO sections (collection_id, document, section_title, importance_rank, page_number, heading_level)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for section in &output.extracted_sections {
+            insert_section.execute(params![
+                collection_id,
+                section.document,
+                section.section_title,
+                section.importance_rank,
+                section.page_number,
+                section.heading_level,
+            ])?;
+        }
+        let mut insert_subsection = tx.prepare(
+            "INSERT INTO subsections (collection_id, document, refined_text, page_number) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for subsection in &output.subsection_analysis {
+            insert_subsection.execute(params![
+                collection_id,
+                subsection.document,
+                subsection.refined_text,
+                subsection.page_number,
+            ])?;
+        }
+    }
+    tx.commit()
+        .with_context(|| format!("Failed to write collection {} to {}", collection, db_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessingOptions;
+    use crate::models::InputJson;
+    use crate::pdf_processor::PdfProcessor;
+    use crate::test_pdf::TestPdf;
+
+    fn fixture_output(dir: &Path) -> OutputJson {
+        TestPdf::new()
+            .plain_text("BEACHES\n\nA beach trip for the travel planner.\n\nHOTELS\n\nHotels near the beach for the travel planner.\n")
+            .write(&dir.join("guide.pdf"));
+        let input: InputJson = serde_json::from_value(serde_json::json!({
+            "challenge_info": { "challenge_id": "test", "test_case_name": "test" },
+            "documents": [{ "filename": "guide.pdf", "title": "Guide" }],
+            "persona": { "role": "Travel planner" },
+            "job_to_be_done": { "task": "Plan a beach trip" },
+        })).unwrap();
+        PdfProcessor::new(ProcessingOptions::default()).unwrap().analyze(&input, dir).unwrap()
+    }
+
+    #[test]
+    fn rows_match_the_output_and_rewrites_replace_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = fixture_output(dir.path());
+        assert!(!output.extracted_sections.is_empty() && !output.subsection_analysis.is_empty());
+        let db_path = dir.path().join("results.db");
+        write_collection(&db_path, "travel", &output).unwrap();
+        write_collection(&db_path, "travel", &output).unwrap();
+
+        let conn = Connection::open(&db_path).unwrap();
+        let (persona, task): (String, String) = conn
+            .query_row("SELECT persona, job_to_be_done FROM collections WHERE name = 'travel'", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!((persona.as_str(), task.as_str()), ("Travel planner", "Plan a beach trip"));
+
+        let mut sections = conn.prepare("SELECT document, section_title, importance_rank, page_number FROM sections ORDER BY importance_rank").unwrap();
+        let rows: Vec<(String, String, u32, u32)> = sections
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap().map(Result::unwrap).collect();
+        let expected: Vec<_> = output.extracted_sections.iter()
+            .map(|section| (section.document.clone(), section.section_title.clone(), section.importance_rank, section.page_number))
+            .collect();
+        assert_eq!(rows, expected);
+
+        let mut subsections = conn.prepare("SELECT document, refined_text, page_number FROM subsections").unwrap();
+        let rows: Vec<(String, String, u32)> = subsections
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap().map(Result::unwrap).collect();
+        let expected: Vec<_> = output.subsection_analysis.iter()
+            .map(|subsection| (subsection.document.clone(), subsection.refined_text.clone(), subsection.page_number))
+            .collect();
+        assert_eq!(rows, expected);
+    }
+}
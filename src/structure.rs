@@ -0,0 +1,193 @@
+use crate::layout::TextRun;
+use pdf::object::{PlainRef, Resolve};
+use pdf::primitive::{Dictionary, Primitive};
+use std::collections::HashMap;
+
+// Guards the structure tree walk against reference cycles.
+const MAX_STRUCT_DEPTH: usize = 64;
+// Custom element types are mapped to standard ones through at most this many
+// RoleMap entries.
+const MAX_ROLE_MAP_STEPS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructRole {
+    // H1-H6; a bare H counts as level 1.
+    Heading(u8),
+    // P elements and table cells.
+    Paragraph,
+    // The level is the item's list nesting depth, from 1.
+    ListItem(u8),
+}
+
+// A heading, paragraph or list item as marked in the structure tree, with its
+// text in content order. `label` is a list item's bullet or number.
+#[derive(Debug, Clone)]
+pub struct TaggedBlock {
+    pub role: StructRole,
+    pub label: String,
+    pub text: String,
+}
+
+impl TaggedBlock {
+    // The block as it appears in the page text.
+    pub fn full_text(&self) -> String {
+        if self.label.is_empty() {
+            self.text.clone()
+        } else {
+            format!("{} {}", self.label, self.text)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ContentTag {
+    block: usize,
+    role: StructRole,
+    label: bool,
+}
+
+#[derive(Clone, Copy)]
+struct WalkState {
+    page: Option<PlainRef>,
+    block: Option<(usize, StructRole)>,
+    list_depth: u8,
+    label: bool,
+}
+
+// Maps the marked-content IDs on each page of a tagged PDF to the block-level
+// structure elements (headings, paragraphs, list items) that contain them.
+#[derive(Default)]
+pub struct StructureMap {
+    tags: HashMap<(PlainRef, i32), ContentTag>,
+    role_map: HashMap<String, String>,
+    blocks: usize,
+}
+
+impl StructureMap {
+    // Returns `None` for documents without a structure tree.
+    pub fn load(resolver: &impl Resolve, catalog: PlainRef) -> pdf::error::Result<Option<Self>> {
+        let catalog = resolver.resolve(catalog)?.into_dictionary()?;
+        let Some(root) = catalog.get("StructTreeRoot") else {
+            return Ok(None);
+        };
+        let root = root.clone().resolve(resolver)?.into_dictionary()?;
+        let mut map = Self::default();
+        if let Some(role_map) = root.get("RoleMap") {
+            for (name, target) in role_map.clone().resolve(resolver)?.into_dictionary()?.iter() {
+                if let Ok(target) = target.as_name() {
+                    map.role_map.insert(name.as_str().to_string(), target.to_string());
+                }
+            }
+        }
+        if let Some(kids) = root.get("K") {
+            let state = WalkState { page: None, block: None, list_depth: 0, label: false };
+            map.visit(resolver, kids, state, 0);
+        }
+        Ok(Some(map))
+    }
+
+    // Groups the tagged runs of a page into blocks, in the order each block's
+    // first run was drawn. Runs outside any block are left out, which drops
+    // untagged artifacts such as running headers. `None` when nothing on the
+    // page is tagged.
+    pub fn page_blocks(&self, page: PlainRef, runs: &[TextRun]) -> Option<Vec<TaggedBlock>> {
+        let mut order: Vec<usize> = Vec::new();
+        let mut blocks: HashMap<usize, TaggedBlock> = HashMap::new();
+        for run in runs {
+            let Some(tag) = run.mcid.and_then(|mcid| self.tags.get(&(page, mcid))) else {
+                continue;
+            };
+            let block = blocks.entry(tag.block).or_insert_with(|| {
+                order.push(tag.block);
+                TaggedBlock { role: tag.role, label: String::new(), text: String::new() }
+            });
+            if tag.label {
+                block.label.push_str(&run.text);
+            } else {
+                block.text.push_str(&run.text);
+            }
+        }
+        if order.is_empty() {
+            return None;
+        }
+        Some(order.into_iter().filter_map(|id| blocks.remove(&id)).collect())
+    }
+
+    fn standard_role<'a>(&'a self, mut name: &'a str) -> &'a str {
+        for _ in 0..MAX_ROLE_MAP_STEPS {
+            match self.role_map.get(name) {
+                Some(target) if target != name => name = target,
+                _ => break,
+            }
+        }
+        name
+    }
+
+    fn visit(&mut self, resolver: &impl Resolve, kid: &Primitive, state: WalkState, depth: usize) {
+        if depth > MAX_STRUCT_DEPTH {
+            return;
+        }
+        match kid {
+            Primitive::Integer(mcid) => self.record(state, *mcid),
+            Primitive::Array(kids) => {
+                for kid in kids {
+                    self.visit(resolver, kid, state, depth + 1);
+                }
+            }
+            Primitive::Reference(r) => {
+                if let Ok(resolved) = resolver.resolve(*r) {
+                    self.visit(resolver, &resolved, state, depth + 1);
+                }
+            }
+            Primitive::Dictionary(dict) => self.visit_dictionary(resolver, dict, state, depth),
+            _ => {}
+        }
+    }
+
+    fn visit_dictionary(&mut self, resolver: &impl Resolve, dict: &Dictionary, mut state: WalkState, depth: usize) {
+        if let Some(Primitive::Reference(page)) = dict.get("Pg") {
+            state.page = Some(*page);
+        }
+        match dict.get("Type").and_then(|t| t.as_name().ok()) {
+            // A marked-content reference to content on another page.
+            Some("MCR") => {
+                if let Some(Primitive::Integer(mcid)) = dict.get("MCID") {
+                    self.record(state, *mcid);
+                }
+                return;
+            }
+            // An annotation or XObject, which carries no page text.
+            Some("OBJR") => return,
+            _ => {}
+        }
+        let role = dict.get("S").and_then(|s| s.as_name().ok()).unwrap_or_default().to_string();
+        let in_list_item = matches!(state.block, Some((_, StructRole::ListItem(_))));
+        match self.standard_role(&role) {
+            "H" => state.block = Some(self.new_block(StructRole::Heading(1))),
+            heading if heading.len() == 2 && heading.starts_with('H') => {
+                if let Ok(level @ 1..=6) = heading[1..].parse::<u8>() {
+                    state.block = Some(self.new_block(StructRole::Heading(level)));
+                }
+            }
+            "P" | "TD" | "TH" if !in_list_item => state.block = Some(self.new_block(StructRole::Paragraph)),
+            "L" => state.list_depth = state.list_depth.saturating_add(1),
+            "LI" => state.block = Some(self.new_block(StructRole::ListItem(state.list_depth.max(1)))),
+            "Lbl" => state.label = true,
+            _ => {}
+        }
+        if let Some(kids) = dict.get("K") {
+            self.visit(resolver, kids, state, depth + 1);
+        }
+    }
+
+    fn new_block(&mut self, role: StructRole) -> (usize, StructRole) {
+        self.blocks += 1;
+        (self.blocks, role)
+    }
+
+    fn record(&mut self, state: WalkState, mcid: i32) {
+        if let (Some(page), Some((block, role))) = (state.page, state.block) {
+            self.tags.insert((page, mcid), ContentTag { block, role, label: state.label });
+        }
+    }
+}
@@ -0,0 +1,239 @@
+// Writes small uncompressed PDFs for tests: one Helvetica font shared by
+// every page, and each page's content stream given as raw operators.
+
+pub struct TestPdf {
+    // `None` for a page whose object is missing.
+    pages: Vec<Option<String>>,
+    // Indices of pages whose content stream claims a filter it isn't encoded with.
+    undecodable: Vec<usize>,
+    // The /Count written to the page tree, when it shouldn't match the pages.
+    declared_count: Option<usize>,
+    // A /CropBox given to every page.
+    crop_box: Option<[u32; 4]>,
+    // (structure type, page from 1, MCID) of each element under /Document.
+    structure: Vec<(String, usize, i32)>,
+    // The user password the file is encrypted with (40-bit RC4, revision 2).
+    password: Option<String>,
+    // The /Title of the document information dictionary.
+    title: Option<String>,
+}
+
+impl TestPdf {
+    pub fn new() -> Self {
+        Self { pages: Vec::new(), undecodable: Vec::new(), declared_count: None, crop_box: None, structure: Vec::new(), password: None, title: None }
+    }
+
+    // A page drawing `content`; an empty string leaves out /Contents.
+    pub fn page(mut self, content: &str) -> Self {
+        self.pages.push(Some(content.to_string()));
+        self
+    }
+
+    // A page with one line of `text` per (y, font size, text), all at x = 72.
+    pub fn text_page(self, lines: &[(f32, f32, &str)]) -> Self {
+        let content: String = lines.iter()
+            .map(|(y, size, text)| format!("BT /F1 {} Tf 72 {} Td ({}) Tj ET\n", size, y, escape(text)))
+            .collect();
+        self.page(&content)
+    }
+
+    // A page per form-feed-separated part of `text`, one 12pt line per line,
+    // so a blank line leaves the gap a paragraph break needs.
+    pub fn plain_text(mut self, text: &str) -> Self {
+        for page in text.split('\x0c') {
+            let mut y = 720.0;
+            let mut lines = Vec::new();
+            for line in page.lines() {
+                if !line.is_empty() {
+                    lines.push((y, 12.0, line));
+                }
+                y -= 14.0;
+            }
+            self = self.text_page(&lines);
+        }
+        self
+    }
+
+    // A page drawing `content` from a stream marked /FlateDecode but left
+    // uncompressed, so reading its operators fails.
+    pub fn undecodable_page(mut self, content: &str) -> Self {
+        self.undecodable.push(self.pages.len());
+        self.page(content)
+    }
+
+    // A page tree entry pointing at an object that isn't a page.
+    pub fn unresolvable_page(mut self) -> Self {
+        self.pages.push(None);
+        self
+    }
+
+    pub fn declared_count(mut self, count: usize) -> Self {
+        self.declared_count = Some(count);
+        self
+    }
+
+    pub fn crop_box(mut self, crop_box: [u32; 4]) -> Self {
+        self.crop_box = Some(crop_box);
+        self
+    }
+
+    // Tags the document with one structure element per (structure type, page
+    // from 1, MCID), all children of a /Document element.
+    pub fn structure(mut self, elements: &[(&str, usize, i32)]) -> Self {
+        self.structure = elements.iter().map(|&(role, page, mcid)| (role.to_string(), page, mcid)).collect();
+        self
+    }
+
+    // Encrypts the content streams so the file only opens with `password`.
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    // Not encrypted along with the content streams, so don't combine it with
+    // `password`.
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let encryption = self.password.as_deref().map(Encryption::new);
+        let kids: Vec<String> = (0..self.pages.len()).map(|i| format!("{} 0 R", 4 + 2 * i)).collect();
+        let struct_tree_root = 4 + 2 * self.pages.len();
+        let catalog = if self.structure.is_empty() {
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string()
+        } else {
+            format!("<< /Type /Catalog /Pages 2 0 R /MarkInfo << /Marked true >> /StructTreeRoot {} 0 R >>", struct_tree_root)
+        };
+        let mut objects: Vec<Vec<u8>> = vec![
+            catalog.into_bytes(),
+            format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), self.declared_count.unwrap_or(self.pages.len())).into_bytes(),
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_vec(),
+        ];
+        for (i, content) in self.pages.iter().enumerate() {
+            let Some(content) = content else {
+                objects.extend([b"null".to_vec(), b"null".to_vec()]);
+                continue;
+            };
+            let mut entries = if content.is_empty() { String::new() } else { format!(" /Contents {} 0 R", 5 + 2 * i) };
+            if let Some([x0, y0, x1, y1]) = self.crop_box {
+                entries.push_str(&format!(" /CropBox [{} {} {} {}]", x0, y0, x1, y1));
+            }
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Resources << /Font << /F1 3 0 R >> >>{} >>",
+                entries
+            ).into_bytes());
+            let mut data = format!("{}\n", content).into_bytes();
+            if let Some(encryption) = &encryption {
+                data = encryption.encrypt(objects.len() + 1, &data);
+            }
+            let filter = if self.undecodable.contains(&i) { " /Filter /FlateDecode" } else { "" };
+            let mut stream = format!("<< /Length {}{} >>\nstream\n", data.len(), filter).into_bytes();
+            stream.extend(data);
+            stream.extend(b"\nendstream");
+            objects.push(stream);
+        }
+        if !self.structure.is_empty() {
+            let document = struct_tree_root + 1;
+            let kids: Vec<String> = (0..self.structure.len()).map(|i| format!("{} 0 R", document + 1 + i)).collect();
+            objects.push(format!("<< /Type /StructTreeRoot /K [{} 0 R] >>", document).into_bytes());
+            objects.push(format!("<< /Type /StructElem /S /Document /P {} 0 R /K [{}] >>", struct_tree_root, kids.join(" ")).into_bytes());
+            for (role, page, mcid) in &self.structure {
+                objects.push(format!("<< /Type /StructElem /S /{} /P {} 0 R /Pg {} 0 R /K {} >>", role, document, 2 + 2 * page, mcid).into_bytes());
+            }
+        }
+        let mut trailer = String::new();
+        if let Some(title) = &self.title {
+            objects.push(format!("<< /Title ({}) >>", escape(title)).into_bytes());
+            trailer = format!(" /Info {} 0 R", objects.len());
+        }
+        if let Some(encryption) = &encryption {
+            objects.push(format!(
+                "<< /Filter /Standard /V 1 /R 2 /O <{}> /U <{}> /P {} >>",
+                hex::encode(encryption.owner), hex::encode(encryption.user), Encryption::PERMISSIONS
+            ).into_bytes());
+            trailer = format!(" /Encrypt {} 0 R /ID [<{id}> <{id}>]", objects.len(), id = hex::encode(Encryption::ID));
+        }
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n", i + 1).as_bytes());
+            pdf.extend(object);
+            pdf.extend(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            pdf.extend(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        pdf.extend(format!(
+            "trailer\n<< /Size {} /Root 1 0 R{} >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1, trailer, xref
+        ).as_bytes());
+        pdf
+    }
+
+    pub fn write(&self, path: &std::path::Path) {
+        std::fs::write(path, self.build()).unwrap();
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('(', "\\(").replace(')', "\\)")
+}
+
+// The standard security handler at revision 2, as specified in section 7.6.3
+// of ISO 32000-1, with the owner password the same as the user password.
+struct Encryption {
+    key: [u8; 5],
+    owner: [u8; 32],
+    user: [u8; 32],
+}
+
+impl Encryption {
+    const PADDING: [u8; 32] = [
+        0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+        0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+    ];
+    const PERMISSIONS: i32 = -4;
+    const ID: [u8; 16] = *b"pdf_analyzer_id_";
+
+    fn new(password: &str) -> Self {
+        let padded: Vec<u8> = password.bytes().chain(Self::PADDING).take(32).collect();
+        let owner_key = md5::compute(&padded);
+        let owner: [u8; 32] = rc4(&owner_key[..5], &padded).try_into().unwrap();
+        let mut input = padded.clone();
+        input.extend(owner);
+        input.extend(Self::PERMISSIONS.to_le_bytes());
+        input.extend(Self::ID);
+        let key: [u8; 5] = md5::compute(&input)[..5].try_into().unwrap();
+        let user = rc4(&key, &Self::PADDING).try_into().unwrap();
+        Self { key, owner, user }
+    }
+
+    fn encrypt(&self, object: usize, data: &[u8]) -> Vec<u8> {
+        let mut input = self.key.to_vec();
+        input.extend(&(object as u32).to_le_bytes()[..3]);
+        input.extend([0, 0]);
+        rc4(&md5::compute(&input)[..10], data)
+    }
+}
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter().map(|byte| {
+        i = i.wrapping_add(1);
+        j = j.wrapping_add(state[i as usize]);
+        state.swap(i as usize, j as usize);
+        byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+    }).collect()
+}
@@ -1,13 +1,262 @@
-use std::path::Path;
-use anyhow::Result;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+
+// How often `run_bounded` checks on its child process.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// A fresh directory under the system temp dir, removed with its contents on
+// drop. The name gets a random suffix and is created exclusively, so another
+// process can't have prepared it in advance.
+pub struct ScratchDir(tempfile::TempDir);
+
+impl ScratchDir {
+    pub fn new() -> Result<Self> {
+        tempfile::Builder::new()
+            .prefix("pdf_analyzer-")
+            .tempdir()
+            .map(Self)
+            .context("Failed to create scratch directory")
+    }
+
+    pub fn path(&self) -> &Path {
+        self.0.path()
+    }
+}
+
+// How long `run_bounded` waits for a pipe to close after killing its process.
+const READER_GRACE: Duration = Duration::from_secs(1);
+
+pub struct BoundedOutput {
+    // The status the process was killed with when `truncated` is set.
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub truncated: bool,
+}
 
 pub fn ensure_directory_exists(path: &Path) -> Result<()> {
     if !path.exists() {
-        std::fs::create_dir_all(path)?;
+        std::fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create directory {}", path.display()))?;
     }
     Ok(())
 }
 
 pub fn sanitize_filename(filename: &str) -> String {
     filename.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
-} 
\ No newline at end of file
+}
+
+// NFKC-normalizes extracted text, which also expands typographic ligatures
+// such as "ﬁ" and composes accents, so "ﬁnance" matches "finance". Curly quotes
+// and dashes become their ASCII forms.
+pub fn normalize_text(text: &str) -> String {
+    text.nfkc()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => '"',
+            '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' | '\u{2014}' | '\u{2015}' | '\u{2212}' => '-',
+            c => c,
+        })
+        .collect()
+}
+
+// Writes to a sibling temp file and renames it into place, so readers (and an
+// interrupted run) never see a partially written file. Missing parent
+// directories are created first.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        ensure_directory_exists(parent)?;
+    }
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path: PathBuf = path.with_file_name(tmp_name);
+    std::fs::write(&tmp_path, contents)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to move {} into place", path.display()))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {} for hashing", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {} for hashing", path.display()))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// Formats `time` with a strftime-style `format`, or as RFC 3339 when unset, in
+// `timezone`: an IANA name such as "Europe/Paris", a fixed offset such as
+// "+05:30", or UTC when unset.
+pub fn format_timestamp(time: DateTime<Utc>, format: Option<&str>, timezone: Option<&str>) -> Result<String> {
+    if let Some(format) = format {
+        if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+            return Err(anyhow::anyhow!("Invalid timestamp format {:?}", format));
+        }
+    }
+    fn render<Tz: TimeZone>(time: DateTime<Tz>, format: Option<&str>) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        match format {
+            Some(format) => time.format(format).to_string(),
+            None => time.to_rfc3339(),
+        }
+    }
+    let Some(timezone) = timezone else {
+        return Ok(render(time, format));
+    };
+    if let Ok(offset) = timezone.parse::<FixedOffset>() {
+        return Ok(render(time.with_timezone(&offset), format));
+    }
+    let zone: chrono_tz::Tz = timezone.parse().map_err(|_| anyhow::anyhow!(
+        "Unknown timezone {:?}; use an IANA name such as \"Europe/Paris\" or an offset such as \"+02:00\"",
+        timezone
+    ))?;
+    Ok(render(time.with_timezone(&zone), format))
+}
+
+// Runs `command` capturing its output, killing it once stdout exceeds
+// `max_stdout` bytes (the output is cut to that length and marked truncated)
+// or once it has run longer than `timeout`, which is an error.
+pub fn run_bounded(command: &mut Command, timeout: Option<Duration>, max_stdout: Option<usize>) -> std::io::Result<BoundedOutput> {
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let over_limit = Arc::new(AtomicBool::new(false));
+    let stdout_reader = {
+        let over_limit = Arc::clone(&over_limit);
+        std::thread::spawn(move || -> std::io::Result<Vec<u8>> {
+            let mut output = Vec::new();
+            match max_stdout {
+                Some(limit) => {
+                    (&mut stdout).take(limit as u64 + 1).read_to_end(&mut output)?;
+                    if output.len() > limit {
+                        output.truncate(limit);
+                        over_limit.store(true, Ordering::SeqCst);
+                    }
+                }
+                None => {
+                    stdout.read_to_end(&mut output)?;
+                }
+            }
+            Ok(output)
+        })
+    };
+    let stderr_reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stderr.read_to_end(&mut output);
+        output
+    });
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if over_limit.load(Ordering::SeqCst) {
+            child.kill()?;
+            break child.wait()?;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            child.kill()?;
+            child.wait()?;
+            // Nothing read is reported, but the readers finish once the pipes close.
+            let grace = Instant::now() + READER_GRACE;
+            let _ = join_reader(stdout_reader, grace);
+            let _ = join_reader(stderr_reader, grace);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("killed after {:?}", timeout.unwrap_or_default()),
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    let stdout = stdout_reader.join().expect("stdout reader panicked")?;
+    // A truncated process is either killed or dies of SIGPIPE first.
+    let truncated = over_limit.load(Ordering::SeqCst);
+    let stderr = if truncated {
+        join_reader(stderr_reader, Instant::now() + READER_GRACE).unwrap_or_default()
+    } else {
+        stderr_reader.join().expect("stderr reader panicked")
+    };
+    Ok(BoundedOutput { status, stdout, stderr, truncated })
+}
+
+// Joins a pipe reader of a killed process. Grandchildren that inherited the
+// pipe can keep it open indefinitely, so a reader still going at `deadline`
+// is detached instead; it ends when they do.
+fn join_reader<T>(reader: std::thread::JoinHandle<T>, deadline: Instant) -> Option<T> {
+    while !reader.is_finished() {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Some(reader.join().expect("pipe reader panicked"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_output_reports_the_killed_status() {
+        let output = run_bounded(Command::new("yes").arg("page"), Some(Duration::from_secs(10)), Some(1000)).unwrap();
+        assert!(output.truncated);
+        assert_eq!(output.stdout.len(), 1000);
+        assert!(output.stdout.starts_with(b"page\npage\n"));
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn output_under_the_limit_is_kept_whole() {
+        let output = run_bounded(Command::new("sh").args(["-c", "printf text; printf oops >&2"]), None, Some(1000)).unwrap();
+        assert!(!output.truncated);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"text");
+        assert_eq!(output.stderr, b"oops");
+    }
+
+    #[test]
+    fn timeout_kills_the_process_without_waiting_on_its_pipes() {
+        let started = Instant::now();
+        // The background sleep keeps both pipes open after the shell is killed.
+        let error = run_bounded(Command::new("sh").args(["-c", "sleep 5 & sleep 5"]), Some(Duration::from_millis(100)), None)
+            .err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(4), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn scratch_dirs_are_distinct_and_removed_on_drop() {
+        let first = ScratchDir::new().unwrap();
+        let second = ScratchDir::new().unwrap();
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().file_name().unwrap().to_string_lossy().starts_with("pdf_analyzer-"));
+        std::fs::write(first.path().join("page-1.png"), b"png").unwrap();
+        let path = first.path().to_path_buf();
+        drop(first);
+        assert!(!path.exists());
+        assert!(second.path().is_dir());
+    }
+
+    #[test]
+    fn timestamps_follow_the_configured_format_and_timezone() {
+        let time = DateTime::parse_from_rfc3339("2024-03-05T14:07:09Z").unwrap().with_timezone(&Utc);
+        assert_eq!(format_timestamp(time, None, None).unwrap(), "2024-03-05T14:07:09+00:00");
+        assert_eq!(format_timestamp(time, Some("%d/%m/%Y %H:%M %Z"), Some("Europe/Paris")).unwrap(), "05/03/2024 15:07 CET");
+        assert_eq!(format_timestamp(time, None, Some("+05:30")).unwrap(), "2024-03-05T19:37:09+05:30");
+        assert!(format_timestamp(time, Some("%Q"), None).is_err());
+        assert!(format_timestamp(time, None, Some("Mars/Olympus")).is_err());
+    }
+}